@@ -0,0 +1,69 @@
+//! Compares reading values from a preferences group with deserializing a clone of the stored
+//! value, which is how `get` used to work.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use serde::Deserialize;
+
+use bevy_prefs_lite::{prefs_json::JsonPreferencesFile, prefs_toml::TomlPreferencesFile};
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct Display {
+    resolution: [u32; 2],
+    monitor: String,
+    fullscreen: bool,
+    layers: Vec<Layer>,
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct Layer {
+    name: String,
+    opacity: f32,
+    tint: [f32; 4],
+}
+
+const TOML: &str = r#"
+[video]
+gamma = 1.2
+
+[video.display]
+resolution = [1920, 1080]
+monitor = "primary"
+fullscreen = true
+layers = [
+    { name = "background", opacity = 1.0, tint = [1.0, 1.0, 1.0, 1.0] },
+    { name = "world", opacity = 1.0, tint = [0.9, 0.9, 1.0, 1.0] },
+    { name = "effects", opacity = 0.8, tint = [1.0, 0.5, 0.5, 1.0] },
+    { name = "ui", opacity = 0.9, tint = [1.0, 1.0, 1.0, 0.5] },
+]
+"#;
+
+fn bench_get(c: &mut Criterion) {
+    let file: TomlPreferencesFile = TOML.parse().unwrap();
+    let toml_group = file.get_group("video").unwrap();
+    let table: toml::Table = toml::from_str(TOML).unwrap();
+    let toml_value = &table["video"]["display"];
+
+    let json = serde_json::to_string(&table).unwrap();
+    let file: JsonPreferencesFile = json.parse().unwrap();
+    let json_group = file.get_group("video").unwrap();
+    let json_value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    let json_value = &json_value["video"]["display"];
+
+    c.bench_function("toml get", |b| {
+        b.iter(|| black_box(toml_group.get::<Display>(black_box("display")).unwrap()))
+    });
+    c.bench_function("toml clone and deserialize", |b| {
+        b.iter(|| black_box(toml_value.clone().try_into::<Display>().unwrap()))
+    });
+    c.bench_function("json get", |b| {
+        b.iter(|| black_box(json_group.get::<Display>(black_box("display")).unwrap()))
+    });
+    c.bench_function("json clone and deserialize", |b| {
+        b.iter(|| black_box(serde_json::from_value::<Display>(json_value.clone()).unwrap()))
+    });
+}
+
+criterion_group!(benches, bench_get);
+criterion_main!(benches);
@@ -1,43 +1,34 @@
-use bevy::{
-    prelude::*,
-    window::{ExitCondition, WindowCloseRequested},
-};
-use bevy_prefs_lite::{AutosavePrefsPlugin, Preferences, SavePreferencesSync, StartAutosaveTimer};
+use bevy::prelude::*;
+use bevy_prefs_lite::{Preferences, PreferencesPlugin, StartAutosaveTimer};
 
 /// Example that remembers window position and size.
 fn main() {
-    // Configure preferences store
-    let mut preferences = Preferences::new("org.viridia.counter");
-    let count: i32 = preferences
-        .get("prefs")
-        .map(|file| {
-            file.get_group("counter")
-                .map(|group| group.get::<i32>("count").unwrap_or(0))
-                .unwrap_or(0)
-        })
-        .unwrap_or(0);
-
     App::new()
         .add_plugins(DefaultPlugins.set(WindowPlugin {
-            exit_condition: ExitCondition::DontExit,
             primary_window: Some(Window {
                 title: "Prefs Counter".into(),
                 ..default()
             }),
             ..default()
         }))
-        .add_plugins(AutosavePrefsPlugin)
-        .insert_resource(preferences)
-        .insert_resource(Counter(count))
+        // Configure preferences store, and load the prefs file at startup.
+        .add_plugins(PreferencesPlugin::new("org.viridia.counter").preload(&["prefs"]))
         .add_systems(Startup, setup)
-        .add_systems(Update, (show_count, change_count, on_window_close))
+        .add_systems(Update, (show_count, change_count))
         .run();
 }
 
 #[derive(Resource)]
 struct Counter(i32);
 
-fn setup(mut commands: Commands) {
+fn setup(mut commands: Commands, mut prefs: ResMut<Preferences>) {
+    let count = prefs
+        .get("prefs")
+        .and_then(|file| file.get_group("counter"))
+        .and_then(|group| group.get::<i32>("count"))
+        .unwrap_or(0);
+    commands.insert_resource(Counter(count));
+
     commands.spawn((Camera::default(), Camera2d));
     commands
         .spawn(Node {
@@ -94,18 +85,3 @@ fn change_count(
         }
     }
 }
-
-fn on_window_close(mut close: MessageReader<WindowCloseRequested>, mut commands: Commands) {
-    for _close_event in close.read() {
-        commands.queue(SavePreferencesSync::IfChanged);
-        commands.queue(ExitAfterSave);
-    }
-}
-
-struct ExitAfterSave;
-
-impl Command for ExitAfterSave {
-    fn apply(self, world: &mut World) {
-        world.write_message(AppExit::Success);
-    }
-}
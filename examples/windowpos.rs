@@ -62,22 +62,18 @@ pub fn update_window_settings(
 }
 
 fn load_window_settings(prefs: &mut Preferences, window: &mut Window) {
-    if let Some(app_prefs) = prefs.get("prefs") {
-        if let Some(window_prefs) = app_prefs.get_group("window") {
-            if let Some(fullscreen) = window_prefs.get::<bool>("fullscreen") {
-                window.mode = if fullscreen {
-                    WindowMode::BorderlessFullscreen(MonitorSelection::Current)
-                } else {
-                    WindowMode::Windowed
-                };
-            }
-            if let Some(pos) = window_prefs.get::<IVec2>("position") {
-                window.position = WindowPosition::new(pos);
-            }
-            if let Some(size) = window_prefs.get::<UVec2>("size") {
-                window.resolution = WindowResolution::new(size.x, size.y);
-            }
-        }
+    let Some(app_prefs) = prefs.get("prefs") else {
+        return;
+    };
+    let window_prefs = app_prefs.group("window");
+    if window_prefs.get_or("fullscreen", false) {
+        window.mode = WindowMode::BorderlessFullscreen(MonitorSelection::Current);
+    }
+    if let Some(pos) = window_prefs.get::<IVec2>("position") {
+        window.position = WindowPosition::new(pos);
+    }
+    if let Some(size) = window_prefs.get::<UVec2>("size") {
+        window.resolution = WindowResolution::new(size.x, size.y);
     }
 }
 
@@ -92,14 +88,11 @@ fn store_window_settings(
     window_prefs.set_if_changed("fullscreen", window.mode != WindowMode::Windowed);
 
     // Window position
-    match window.position {
-        WindowPosition::At(pos) => {
-            window_prefs.set_if_changed("position", pos);
-        }
-        _ => {
-            window_prefs.remove("position");
-        }
+    let position = match window.position {
+        WindowPosition::At(pos) => Some(pos),
+        _ => None,
     };
+    window_prefs.set_if_changed("position", position);
 
     // Window size
     window_prefs.set_if_changed(
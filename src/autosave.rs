@@ -1,46 +1,319 @@
 use bevy::{
-    app::{App, Plugin, Update},
+    app::{App, AppExit, Last, Plugin, PreUpdate, Update},
     ecs::{
+        message::{MessageReader, MessageWriter},
         resource::Resource,
+        schedule::{IntoScheduleConfigs, SystemSet},
         system::{Command, Commands, Res, ResMut},
         world::World,
     },
+    platform::collections::HashMap,
     time::Time,
 };
 
-use crate::SavePreferences;
+use crate::{
+    report_save_failures, Preferences, PreferencesFileChanged, PreferencesFileCorrupt,
+    PreferencesSaveFailed, RequiredPreferenceMissing, SavePreferencesSync,
+};
+
+/// How changes to a top-level preferences group are autosaved. Set with
+/// [`Preferences::set_group_policy`]. Groups without a policy are saved when the timer started
+/// by [`StartAutosaveTimer`] goes off.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GroupAutosavePolicy {
+    /// Save the file on the frame after the group changes.
+    Immediate,
+    /// Save the file this many seconds after the group first changes. This does not need
+    /// [`StartAutosaveTimer`], and is not affected by [`PrefsDebounceConfig`].
+    Debounce(f32),
+    /// Never autosave the file because of changes to this group. The changes are still written
+    /// when the file is saved explicitly, or because another group changed.
+    Manual,
+}
+
+/// System sets for the systems added by [`AutosavePrefsPlugin`], so that app systems can be
+/// ordered relative to them, for example with `.after(PreferencesSystem::LoadPreferences)`.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PreferencesSystem {
+    /// Reloads files which were changed by another program, in `PreUpdate`. Files listed in
+    /// [`PreferencesPlugin::preload`](crate::PreferencesPlugin::preload) are loaded earlier,
+    /// before any schedule runs.
+    LoadPreferences,
+    /// Sends [`RequiredPreferenceMissing`] messages, in `PreUpdate` after `LoadPreferences`.
+    /// Systems which fill in defaults for missing preferences can run after this.
+    ApplyDefaults,
+    /// Runs the autosave timers, and queues the save when they go off, in `Update`.
+    SavePreferences,
+}
+
+/// Resource which configures how autosaves are debounced. Insert it before or after adding
+/// [`AutosavePrefsPlugin`] to change the defaults; changes take effect the next time the timer
+/// is started.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct PrefsDebounceConfig {
+    /// How long to wait after the most recent [`StartAutosaveTimer`] before saving, in seconds.
+    /// Each new change restarts the wait. Defaults to 1 second.
+    pub delay_secs: f32,
+    /// The longest time to wait after the first unsaved change, in seconds. If changes keep
+    /// arriving more often than `delay_secs`, preferences are saved anyway once this has
+    /// passed. Defaults to 10 seconds.
+    pub max_wait_secs: f32,
+    /// The shortest time between two autosaves, in seconds. A save which falls due sooner is
+    /// put off until this has passed, however often preferences change. Defaults to zero,
+    /// which means no limit.
+    pub min_save_interval_secs: f32,
+}
+
+impl Default for PrefsDebounceConfig {
+    fn default() -> Self {
+        Self {
+            delay_secs: 1.0,
+            max_wait_secs: 10.0,
+            min_save_interval_secs: 0.0,
+        }
+    }
+}
 
 /// Resource which contains a countdown timer for debouncing preferences changes.
-/// If this is non-zero, preferences will be saved after the timer reaches zero.
+/// If `remaining` is non-zero, preferences will be saved after it reaches zero.
 #[derive(Resource, Default)]
-struct AutosaveTimer(f32);
+struct AutosaveTimer {
+    /// The time until the save, in seconds.
+    remaining: f32,
+    /// The time since the first unsaved change, in seconds.
+    waited: f32,
+    /// The time since the last autosave, in seconds, or `None` if there hasn't been one.
+    since_save: Option<f32>,
+}
+
+impl AutosaveTimer {
+    /// Start or restart the countdown.
+    fn start(&mut self, config: &PrefsDebounceConfig) {
+        if self.remaining <= 0.0 {
+            self.waited = 0.0;
+        }
+        self.remaining = config.delay_secs.max(f32::EPSILON);
+    }
+
+    /// Stop the countdown without saving.
+    fn cancel(&mut self) {
+        self.remaining = 0.0;
+        self.waited = 0.0;
+    }
+
+    /// Advance the countdown by `delta` seconds. Returns true if preferences should be saved
+    /// now. While `cooldown` is non-zero, the save is postponed until it has passed.
+    fn tick(&mut self, delta: f32, cooldown: f32, config: &PrefsDebounceConfig) -> bool {
+        if let Some(since_save) = &mut self.since_save {
+            *since_save += delta;
+        }
+        if self.remaining <= 0.0 {
+            return false;
+        }
+        self.remaining = (self.remaining - delta).max(0.0);
+        self.waited += delta;
+        if self.remaining > 0.0 && self.waited < config.max_wait_secs {
+            return false;
+        }
+        // Postpone the save until the cooldown and the minimum interval have both passed.
+        let throttle = self
+            .since_save
+            .map_or(0.0, |since_save| config.min_save_interval_secs - since_save);
+        let postpone = cooldown.max(throttle);
+        if postpone > 0.0 {
+            self.remaining = postpone;
+            return false;
+        }
+        self.cancel();
+        self.since_save = Some(0.0);
+        true
+    }
+}
+
+/// Resource which tracks how long each top-level group with a [`GroupAutosavePolicy`] other
+/// than `Manual` has had unsaved changes, keyed by file and group name.
+#[derive(Resource, Default)]
+struct GroupAutosaveTimers(HashMap<(String, String), f32>);
+
+impl GroupAutosaveTimers {
+    /// Advance the timers by `delta` seconds. Returns the names of the files which should be
+    /// saved now. Each group is saved once per change, so a file which fails to save is not
+    /// retried every frame. While `cooldown` is non-zero, no files are saved.
+    fn tick(&mut self, prefs: &Preferences, delta: f32, cooldown: f32) -> Vec<String> {
+        let mut due = Vec::new();
+        let mut timers = HashMap::default();
+        for (filename, file) in prefs.loaded_files() {
+            for group in file.changed_groups() {
+                let delay = match prefs.group_policy(filename, group) {
+                    Some(GroupAutosavePolicy::Immediate) => 0.0,
+                    Some(GroupAutosavePolicy::Debounce(secs)) => secs,
+                    _ => continue,
+                };
+                let key = (filename.to_owned(), group.to_owned());
+                let previous = self.0.get(&key).copied();
+                let elapsed = match previous {
+                    Some(elapsed) if cooldown > 0.0 => elapsed,
+                    Some(elapsed) => elapsed + delta,
+                    // Groups which change during the cooldown are picked up after it.
+                    None if cooldown > 0.0 => continue,
+                    None => 0.0,
+                };
+                if cooldown <= 0.0
+                    && previous.is_none_or(|previous| previous < delay)
+                    && elapsed >= delay
+                    && !due.iter().any(|f| f == filename)
+                {
+                    due.push(filename.to_owned());
+                }
+                timers.insert(key, elapsed);
+            }
+        }
+        self.0 = timers;
+        due
+    }
+}
+
+/// Resource which holds autosaves back for a while after a file has been changed by another
+/// program, so that the external edit isn't immediately overwritten.
+#[derive(Resource, Default)]
+struct ExternalChangeCooldown(f32);
+
+/// How long autosaves are held back after an external change, in seconds.
+const EXTERNAL_CHANGE_COOLDOWN: f32 = 5.0;
 
 /// Plugin which automatically saves preferences when they change. This uses a delay timer
-/// to prevent saving preferences too frequently. Preferences will be automatically saved 1 second
-/// after they have been marked as changed.
+/// to prevent saving preferences too frequently. By default, preferences will be automatically
+/// saved 1 second after they have been marked as changed; see [`PrefsDebounceConfig`].
+/// Changed preferences are also saved synchronously when an [`AppExit`] message is sent.
 pub struct AutosavePrefsPlugin;
 
 impl Plugin for AutosavePrefsPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<AutosaveTimer>();
+        app.init_resource::<AutosaveTimer>()
+            .init_resource::<GroupAutosaveTimers>()
+            .init_resource::<PrefsDebounceConfig>()
+            .init_resource::<ExternalChangeCooldown>()
+            .add_message::<PreferencesSaveFailed>()
+            .add_message::<RequiredPreferenceMissing>()
+            .add_message::<PreferencesFileChanged>()
+            .add_message::<PreferencesFileCorrupt>()
+            .configure_sets(
+                PreUpdate,
+                PreferencesSystem::ApplyDefaults.after(PreferencesSystem::LoadPreferences),
+            )
+            .add_systems(
+                PreUpdate,
+                (
+                    reload_external_changes.in_set(PreferencesSystem::LoadPreferences),
+                    (report_corrupt_files, report_missing_preferences)
+                        .in_set(PreferencesSystem::ApplyDefaults),
+                ),
+            );
     }
 
     fn finish(&self, app: &mut App) {
-        // Only load preferences if we were able to locate the user configuration directories.
-        // let prefs = app.world().get_resource::<Preferences>().unwrap();
-        // if prefs.is_valid() {
-        //     info!("Loading Preferences from: {:?}", prefs.base_path);
-        //     app.world_mut().trigger(LoadPreferences);
-        // }
-        app.add_systems(Update, auto_save_preferences);
+        // Preferences are loaded by `PreferencesPlugin`, or by the app before it is built.
+        app.add_systems(
+            Update,
+            (report_async_save_failures, auto_save_preferences)
+                .in_set(PreferencesSystem::SavePreferences),
+        )
+        .add_systems(
+            Last,
+            save_preferences_on_exit.in_set(PreferencesSystem::SavePreferences),
+        );
+    }
+}
+
+/// Save changed preferences before the app exits. This runs in `Last`, so that changes made by
+/// other systems during the final frame are included.
+fn save_preferences_on_exit(mut exit: MessageReader<AppExit>, mut cmd: Commands) {
+    if exit.read().count() > 0 {
+        cmd.queue(SavePreferencesSync::IfChanged);
+    }
+}
+
+fn auto_save_preferences(
+    mut timer: ResMut<AutosaveTimer>,
+    mut group_timers: ResMut<GroupAutosaveTimers>,
+    mut cooldown: ResMut<ExternalChangeCooldown>,
+    config: Res<PrefsDebounceConfig>,
+    prefs: Option<Res<Preferences>>,
+    time: Res<Time>,
+    mut cmd: Commands,
+) {
+    cooldown.0 = (cooldown.0 - time.delta_secs()).max(0.0);
+    let due = prefs
+        .map(|prefs| group_timers.tick(&prefs, time.delta_secs(), cooldown.0))
+        .unwrap_or_default();
+    if timer.tick(time.delta_secs(), cooldown.0, &config) {
+        cmd.queue(AutosavePreferences(None));
+    } else if !due.is_empty() {
+        cmd.queue(AutosavePreferences(Some(due)));
+    }
+}
+
+/// A Command which saves changed preferences files in another thread, skipping files whose
+/// only changes are to groups with the [`GroupAutosavePolicy::Manual`] policy.
+struct AutosavePreferences(Option<Vec<String>>);
+
+impl Command for AutosavePreferences {
+    fn apply(self, world: &mut World) {
+        let prefs = world.get_resource::<Preferences>().unwrap();
+        let failed = prefs.autosave(self.0.as_deref());
+        report_save_failures(world, failed);
+    }
+}
+
+/// Sends a message for each file which could not be saved in another thread.
+fn report_async_save_failures(
+    prefs: Option<Res<Preferences>>,
+    mut writer: MessageWriter<PreferencesSaveFailed>,
+) {
+    if let Some(prefs) = prefs {
+        writer.write_batch(prefs.take_save_failures());
     }
 }
 
-fn auto_save_preferences(mut timer: ResMut<AutosaveTimer>, time: Res<Time>, mut cmd: Commands) {
-    if timer.0 > 0.0 {
-        timer.0 = (timer.0 - time.delta_secs()).max(0.0);
-        if timer.0 <= 0.0 {
-            cmd.queue(SavePreferences::IfChanged);
+/// Reloads preferences files which were changed by another program, and sends a message for
+/// each one. Autosaves are held back for a few seconds afterwards.
+fn reload_external_changes(
+    prefs: Option<ResMut<Preferences>>,
+    mut cooldown: ResMut<ExternalChangeCooldown>,
+    mut writer: MessageWriter<PreferencesFileChanged>,
+) {
+    if let Some(mut prefs) = prefs {
+        let changed = prefs.reload_external_changes();
+        if !changed.is_empty() {
+            cooldown.0 = EXTERNAL_CHANGE_COOLDOWN;
+            writer.write_batch(
+                changed
+                    .into_iter()
+                    .map(|filename| PreferencesFileChanged { filename }),
+            );
+        }
+    }
+}
+
+/// Sends a message for each file which could not be parsed when it was loaded.
+fn report_corrupt_files(
+    prefs: Option<Res<Preferences>>,
+    mut writer: MessageWriter<PreferencesFileCorrupt>,
+) {
+    if let Some(prefs) = prefs {
+        writer.write_batch(prefs.take_corrupt_files());
+    }
+}
+
+/// Sends messages for required preferences which were found to be missing when their file was
+/// loaded.
+fn report_missing_preferences(
+    prefs: Option<ResMut<Preferences>>,
+    mut writer: MessageWriter<RequiredPreferenceMissing>,
+) {
+    if let Some(mut prefs) = prefs {
+        if prefs.has_missing() {
+            writer.write_batch(prefs.drain_missing());
         }
     }
 }
@@ -50,8 +323,134 @@ fn auto_save_preferences(mut timer: ResMut<AutosaveTimer>, time: Res<Time>, mut
 pub struct StartAutosaveTimer;
 
 impl Command for StartAutosaveTimer {
+    fn apply(self, world: &mut World) {
+        let config = world
+            .get_resource::<PrefsDebounceConfig>()
+            .cloned()
+            .unwrap_or_default();
+        let mut timer = world.get_resource_mut::<AutosaveTimer>().unwrap();
+        timer.start(&config);
+    }
+}
+
+/// A Command which stops a pending autosave, without saving. This is the counterpart to
+/// [`StartAutosaveTimer`], and is useful when the user discards their changes.
+#[derive(Default)]
+pub struct CancelAutosaveTimer;
+
+impl Command for CancelAutosaveTimer {
     fn apply(self, world: &mut World) {
         let mut timer = world.get_resource_mut::<AutosaveTimer>().unwrap();
-        timer.0 = 1.0;
+        timer.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StoreChain;
+
+    #[test]
+    fn test_autosave_debounce() {
+        let config = PrefsDebounceConfig {
+            delay_secs: 1.0,
+            max_wait_secs: 3.0,
+            ..Default::default()
+        };
+        let mut timer = AutosaveTimer::default();
+        assert!(!timer.tick(0.5, 0.0, &config));
+
+        // A single change is saved after the delay.
+        timer.start(&config);
+        assert!(!timer.tick(0.5, 0.0, &config));
+        assert!(timer.tick(0.5, 0.0, &config));
+        assert!(!timer.tick(0.5, 0.0, &config));
+
+        // Changes which keep arriving are saved after the maximum wait.
+        timer.start(&config);
+        for _ in 0..5 {
+            assert!(!timer.tick(0.5, 0.0, &config));
+            timer.start(&config);
+        }
+        assert!(timer.tick(0.5, 0.0, &config));
+
+        // A cooldown postpones the save.
+        timer.start(&config);
+        assert!(!timer.tick(1.0, 2.0, &config));
+        assert!(!timer.tick(1.0, 1.0, &config));
+        assert!(timer.tick(1.0, 0.0, &config));
+    }
+
+    #[test]
+    fn test_autosave_min_interval() {
+        let config = PrefsDebounceConfig {
+            delay_secs: 1.0,
+            max_wait_secs: 10.0,
+            min_save_interval_secs: 5.0,
+        };
+        let mut timer = AutosaveTimer::default();
+
+        // The first save is not throttled.
+        timer.start(&config);
+        assert!(timer.tick(1.0, 0.0, &config));
+
+        // The next one waits until 5 seconds after the first.
+        timer.start(&config);
+        assert!(!timer.tick(1.0, 0.0, &config));
+        assert!(!timer.tick(2.0, 0.0, &config));
+        assert!(timer.tick(2.0, 0.0, &config));
+    }
+
+    #[test]
+    fn test_group_autosave_policy() {
+        let mut prefs = Preferences::with_store(StoreChain::new(Vec::new()));
+        prefs.set_group_policy("settings", "input", GroupAutosavePolicy::Immediate);
+        prefs.set_group_policy("settings", "video", GroupAutosavePolicy::Debounce(2.0));
+        prefs.set_group_policy("settings", "window", GroupAutosavePolicy::Manual);
+        let file = prefs.get_mut("settings").unwrap();
+        file.get_group_mut("input").unwrap().set("invert", true);
+        file.get_group_mut("video").unwrap().set("gamma", 1.2);
+        file.get_group_mut("window").unwrap().set("width", 800);
+
+        // Immediate groups are saved on the next tick, once per change.
+        let mut timers = GroupAutosaveTimers::default();
+        assert_eq!(timers.tick(&prefs, 1.0, 0.0), vec!["settings".to_string()]);
+        assert!(timers.tick(&prefs, 0.5, 0.0).is_empty());
+        prefs.get("settings").unwrap().clear_group_changed("input");
+
+        // Debounced groups wait for their delay.
+        assert!(timers.tick(&prefs, 1.0, 0.0).is_empty());
+        assert_eq!(timers.tick(&prefs, 1.0, 0.0), vec!["settings".to_string()]);
+        prefs.get("settings").unwrap().clear_group_changed("video");
+
+        // Manual groups are never autosaved.
+        assert!(timers.tick(&prefs, 10.0, 0.0).is_empty());
+        assert!(prefs.autosave(None).is_empty());
+        assert!(prefs.is_file_changed("settings"));
+    }
+
+    #[test]
+    fn test_save_on_exit() {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .insert_resource(Preferences::with_store(StoreChain::new(Vec::new())))
+            .add_plugins(AutosavePrefsPlugin);
+        app.finish();
+
+        let mut prefs = app.world_mut().resource_mut::<Preferences>();
+        let file = prefs.get_mut("settings").unwrap();
+        file.get_group_mut("audio").unwrap().set("volume", 0.5);
+        app.update();
+        assert!(app
+            .world()
+            .resource::<Preferences>()
+            .is_file_changed("settings"));
+
+        app.world_mut().write_message(AppExit::Success);
+        app.update();
+        assert!(!app
+            .world()
+            .resource::<Preferences>()
+            .is_file_changed("settings"));
     }
 }
@@ -0,0 +1,68 @@
+use crate::{PreferencesFile, PreferencesGroupMut};
+
+/// Builds a [`PreferencesFile`] one group at a time. This is mainly useful for test fixtures,
+/// where building a TOML table or JSON map by hand is verbose.
+///
+/// ```ignore
+/// let file = PreferencesFileBuilder::new()
+///     .group("window", |g| {
+///         g.set("size", UVec2::new(800, 600));
+///         g.set("fullscreen", false);
+///     })
+///     .build();
+/// ```
+#[derive(Debug, Default)]
+pub struct PreferencesFileBuilder {
+    file: PreferencesFile,
+}
+
+impl PreferencesFileBuilder {
+    /// Create a builder for an empty preferences file.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a group to the file, or modify it if it already exists, by calling `f` with the
+    /// mutable group.
+    pub fn group(mut self, name: &str, f: impl FnOnce(&mut PreferencesGroupMut)) -> Self {
+        if let Some(mut group) = self.file.get_group_mut(name) {
+            f(&mut group);
+        }
+        self
+    }
+
+    /// Finish building the file. The file is not marked as changed.
+    pub fn build(self) -> PreferencesFile {
+        self.file.clear_changed();
+        self.file
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::UVec2;
+
+    use super::*;
+
+    #[test]
+    fn test_builder() {
+        let file = PreferencesFileBuilder::new()
+            .group("window", |g| {
+                g.set("size", UVec2::new(800, 600));
+                g.get_group_mut("monitor").unwrap().set("index", 1);
+            })
+            .group("audio", |g| g.set("volume", 0.5))
+            .build();
+        assert!(!file.is_changed());
+        let window = file.get_group("window").unwrap();
+        assert_eq!(window.get::<UVec2>("size"), Some(UVec2::new(800, 600)));
+        assert_eq!(
+            window.get_group("monitor").unwrap().get::<i32>("index"),
+            Some(1)
+        );
+        assert_eq!(
+            file.get_group("audio").unwrap().get::<f32>("volume"),
+            Some(0.5)
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::PrefsValueRef;
+
+/// The size in bytes above which `set_bytes` logs a warning. Preferences files are read and
+/// written in full, so large blobs slow down every save; they are better kept in a separate
+/// file.
+pub const BYTES_SIZE_WARNING: usize = 64 * 1024;
+
+/// Encode bytes as a standard, padded base64 string.
+pub(crate) fn bytes_to_base64(bytes: &[u8]) -> String {
+    STANDARD.encode(bytes)
+}
+
+/// Read bytes written by [`bytes_to_base64`]. For migrating older files, an array of integers
+/// in the range 0..=255, as written by serde for a `Vec<u8>`, is also accepted.
+pub(crate) fn bytes_from_value(value: PrefsValueRef) -> Result<Vec<u8>, String> {
+    if let Some(text) = value.as_str() {
+        return STANDARD.decode(text).map_err(|e| e.to_string());
+    }
+    value
+        .as_array()
+        .ok_or_else(|| format!("expected bytes, found {:?}", value.kind()))?
+        .iter()
+        .map(|byte| {
+            byte.as_i64()
+                .and_then(|byte| u8::try_from(byte).ok())
+                .ok_or_else(|| "array element is not a byte".to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_bytes() {
+        let bytes = [0u8, 1, 2, 254, 255];
+        let encoded = bytes_to_base64(&bytes);
+        assert_eq!(encoded, "AAEC/v8=");
+        let value = toml::Value::String(encoded);
+        assert_eq!(
+            bytes_from_value(PrefsValueRef::Toml(&value)),
+            Ok(bytes.to_vec())
+        );
+
+        let value = json!([0, 1, 2, 254, 255]);
+        assert_eq!(
+            bytes_from_value(PrefsValueRef::Json(&value)),
+            Ok(bytes.to_vec())
+        );
+
+        for bad in [
+            json!("not base64!"),
+            json!([1, 256]),
+            json!([1, -1]),
+            json!(5),
+        ] {
+            assert!(bytes_from_value(PrefsValueRef::Json(&bad)).is_err());
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use bevy::platform::collections::HashMap;
+
+/// The changed flags for a preferences file. Each top-level group has its own flag; changes which
+/// are not made within a group, such as to top-level keys, set the flag for the file itself.
+#[derive(Debug, Default)]
+pub(crate) struct ChangedFlags {
+    file: AtomicBool,
+    groups: HashMap<String, AtomicBool>,
+    /// Set when contents of the file are replaced in bulk, such as by `set_all`, so that its
+    /// required keys are checked again. This is not cleared by saving.
+    replaced: AtomicBool,
+}
+
+impl ChangedFlags {
+    /// The flag for changes which are not made within a top-level group.
+    pub(crate) fn file(&self) -> &AtomicBool {
+        &self.file
+    }
+
+    /// The flag for a top-level group, created if it does not exist.
+    pub(crate) fn group(&mut self, name: &str) -> &AtomicBool {
+        self.groups.entry(name.to_owned()).or_default()
+    }
+
+    /// The flag for a top-level group, along with the flag for bulk replacements.
+    pub(crate) fn group_and_replaced(&mut self, name: &str) -> (&AtomicBool, &AtomicBool) {
+        (
+            self.groups.entry(name.to_owned()).or_default(),
+            &self.replaced,
+        )
+    }
+
+    /// The flag which is set when contents of the file are replaced in bulk.
+    pub(crate) fn replaced(&self) -> &AtomicBool {
+        &self.replaced
+    }
+
+    /// Record that contents of the file were replaced in bulk.
+    pub(crate) fn set_replaced(&self) {
+        self.replaced.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true if contents of the file were replaced in bulk since the last call to
+    /// `take_replaced`.
+    pub(crate) fn is_replaced(&self) -> bool {
+        self.replaced.load(Ordering::Relaxed)
+    }
+
+    /// Returns true if contents of the file were replaced in bulk, and clears the flag.
+    pub(crate) fn take_replaced(&self) -> bool {
+        self.replaced.swap(false, Ordering::Relaxed)
+    }
+
+    /// Mark the file itself as changed.
+    pub(crate) fn set_file(&self) {
+        self.file.store(true, Ordering::Relaxed);
+    }
+
+    /// Mark a top-level group as changed.
+    pub(crate) fn set_group(&mut self, name: &str) {
+        self.group(name).store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true if the file or any of its groups has changed.
+    pub(crate) fn is_changed(&self) -> bool {
+        self.file.load(Ordering::Relaxed)
+            || self
+                .groups
+                .values()
+                .any(|flag| flag.load(Ordering::Relaxed))
+    }
+
+    /// Returns true if the file itself has changed, outside of any top-level group.
+    pub(crate) fn is_file_changed(&self) -> bool {
+        self.file.load(Ordering::Relaxed)
+    }
+
+    /// Returns true if the named top-level group has changed.
+    pub(crate) fn is_group_changed(&self, name: &str) -> bool {
+        self.groups
+            .get(name)
+            .is_some_and(|flag| flag.load(Ordering::Relaxed))
+    }
+
+    /// The names of the top-level groups which have changed, in sorted order.
+    pub(crate) fn changed_groups(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .groups
+            .iter()
+            .filter(|(_, flag)| flag.load(Ordering::Relaxed))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Clear every flag.
+    pub(crate) fn clear(&self) {
+        self.file.store(false, Ordering::Relaxed);
+        for flag in self.groups.values() {
+            flag.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Clear the flag for a top-level group.
+    pub(crate) fn clear_group(&self, name: &str) {
+        if let Some(flag) = self.groups.get(name) {
+            flag.store(false, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use bevy::color::{Color, ColorToPacked, Srgba};
+
+use crate::PrefsValueRef;
+
+/// Format a color as an `"#rrggbbaa"` hex string of sRGB components. Colors in other color
+/// spaces, such as linear RGB, are converted to sRGB first. Each channel is stored with 8 bits,
+/// so colors round-trip to within 1/255 per channel.
+pub(crate) fn color_to_hex(color: Color) -> String {
+    let [r, g, b, a] = color.to_srgba().to_u8_array();
+    format!("#{r:02x}{g:02x}{b:02x}{a:02x}")
+}
+
+/// Read a color written by [`color_to_hex`]. Any hex form accepted by [`Srgba::hex`] is allowed.
+/// For migrating older files, an array of 3 or 4 floats is also accepted, and is treated as
+/// sRGB components with an optional alpha.
+pub(crate) fn color_from_value(value: PrefsValueRef) -> Option<Color> {
+    if let Some(hex) = value.as_str() {
+        return Srgba::hex(hex).ok().map(Color::from);
+    }
+    let components = value
+        .as_array()?
+        .iter()
+        .map(|c| c.as_f64().map(|c| c as f32))
+        .collect::<Option<Vec<_>>>()?;
+    match components[..] {
+        [red, green, blue] => Some(Color::srgb(red, green, blue)),
+        [red, green, blue, alpha] => Some(Color::srgba(red, green, blue, alpha)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::color::LinearRgba;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_color_hex() {
+        let color = Color::srgba_u8(255, 128, 0, 64);
+        let hex = color_to_hex(color);
+        assert_eq!(hex, "#ff800040");
+        let value = toml::Value::String(hex);
+        assert_eq!(color_from_value(PrefsValueRef::Toml(&value)), Some(color));
+
+        // Linear colors are stored as sRGB.
+        let linear = Color::from(LinearRgba::new(0.5, 0.5, 0.5, 1.0));
+        let value = json!(color_to_hex(linear));
+        let read = color_from_value(PrefsValueRef::Json(&value)).unwrap();
+        assert!((read.to_linear().red - 0.5).abs() < 0.01);
+
+        let value = json!("#fff");
+        assert_eq!(
+            color_from_value(PrefsValueRef::Json(&value)),
+            Some(Color::srgb(1.0, 1.0, 1.0))
+        );
+        let value = json!("not a color");
+        assert_eq!(color_from_value(PrefsValueRef::Json(&value)), None);
+    }
+
+    #[test]
+    fn test_color_legacy_arrays() {
+        let value: toml::Value = toml::Value::Array(vec![
+            toml::Value::Float(1.0),
+            toml::Value::Float(0.5),
+            toml::Value::Integer(0),
+        ]);
+        assert_eq!(
+            color_from_value(PrefsValueRef::Toml(&value)),
+            Some(Color::srgb(1.0, 0.5, 0.0))
+        );
+        let value = json!([1.0, 0.5, 0.0, 0.25]);
+        assert_eq!(
+            color_from_value(PrefsValueRef::Json(&value)),
+            Some(Color::srgba(1.0, 0.5, 0.0, 0.25))
+        );
+        let value = json!([1.0, 0.5]);
+        assert_eq!(color_from_value(PrefsValueRef::Json(&value)), None);
+    }
+}
@@ -0,0 +1,98 @@
+use std::path::Path;
+
+/// Compression applied to preferences files written by [`StoreFs`](crate::StoreFs). Each
+/// algorithm other than `None` requires the cargo feature of the same name.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionAlgorithm {
+    /// Files are written as plain text.
+    #[default]
+    None,
+    /// Files are compressed with LZ4, and have the extension `.toml.lz4`.
+    #[cfg(feature = "lz4")]
+    Lz4,
+    /// Files are compressed with Zstandard, and have the extension `.toml.zst`.
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl CompressionAlgorithm {
+    /// The suffix appended to the file extension, including the leading dot.
+    pub(crate) fn suffix(self) -> &'static str {
+        match self {
+            CompressionAlgorithm::None => "",
+            #[cfg(feature = "lz4")]
+            CompressionAlgorithm::Lz4 => ".lz4",
+            #[cfg(feature = "zstd")]
+            CompressionAlgorithm::Zstd => ".zst",
+        }
+    }
+
+    /// Detect the compression of a file from its extension. Unknown extensions are treated as
+    /// uncompressed.
+    pub(crate) fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            #[cfg(feature = "lz4")]
+            Some("lz4") => CompressionAlgorithm::Lz4,
+            #[cfg(feature = "zstd")]
+            Some("zst") => CompressionAlgorithm::Zstd,
+            _ => CompressionAlgorithm::None,
+        }
+    }
+
+    /// Compress the serialized contents of a file.
+    pub(crate) fn compress(self, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        match self {
+            CompressionAlgorithm::None => Ok(data),
+            #[cfg(feature = "lz4")]
+            CompressionAlgorithm::Lz4 => Ok(lz4_flex::compress_prepend_size(&data)),
+            #[cfg(feature = "zstd")]
+            CompressionAlgorithm::Zstd => {
+                zstd::encode_all(data.as_slice(), 0).map_err(|e| e.to_string())
+            }
+        }
+    }
+
+    /// Decompress the contents of a file read from disk.
+    pub(crate) fn decompress(self, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        match self {
+            CompressionAlgorithm::None => Ok(data),
+            #[cfg(feature = "lz4")]
+            CompressionAlgorithm::Lz4 => {
+                lz4_flex::decompress_size_prepended(&data).map_err(|e| e.to_string())
+            }
+            #[cfg(feature = "zstd")]
+            CompressionAlgorithm::Zstd => {
+                zstd::decode_all(data.as_slice()).map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    const CONTENTS: &str = "[window]\nposition = [100, 200]\n";
+
+    #[test]
+    fn test_compression_round_trip() {
+        let algorithms = [
+            CompressionAlgorithm::None,
+            #[cfg(feature = "lz4")]
+            CompressionAlgorithm::Lz4,
+            #[cfg(feature = "zstd")]
+            CompressionAlgorithm::Zstd,
+        ];
+
+        for algorithm in algorithms {
+            let path = PathBuf::from(format!("settings.toml{}", algorithm.suffix()));
+            assert_eq!(CompressionAlgorithm::from_path(&path), algorithm);
+
+            let compressed = algorithm.compress(CONTENTS.as_bytes().to_vec()).unwrap();
+            let decompressed = algorithm.decompress(compressed).unwrap();
+            assert_eq!(decompressed, CONTENTS.as_bytes());
+        }
+    }
+}
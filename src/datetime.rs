@@ -0,0 +1,232 @@
+use std::{fmt, str::FromStr};
+
+use serde::{
+    de::{self, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use toml::value::{Date, Datetime, DatetimeParseError, Offset, Time};
+
+/// The field name which the `toml` crate uses to mark a datetime while serializing.
+pub(crate) const TOML_DATETIME_FIELD: &str = "$__toml_private_datetime";
+
+/// A date and time which is stored the same way by both preferences backends: as a native
+/// datetime in TOML files, and as an RFC 3339 string in JSON. It can be used directly with
+/// `get_datetime` and `set_datetime`, or as a field of a struct which is read and written with
+/// serde. With the `chrono` cargo feature, it can be converted to and from `chrono::DateTime`,
+/// keeping the UTC offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrefsDateTime(Datetime);
+
+impl PrefsDateTime {
+    /// Create a UTC datetime from a number of seconds since the Unix epoch. Returns `None` if
+    /// the year is outside the range 0..=9999.
+    pub fn from_unix_timestamp(timestamp: i64) -> Option<Self> {
+        let (year, month, day) = civil_from_days(timestamp.div_euclid(86400));
+        let seconds = timestamp.rem_euclid(86400);
+        Some(Self(Datetime {
+            date: Some(Date {
+                year: u16::try_from(year).ok().filter(|year| *year <= 9999)?,
+                month,
+                day,
+            }),
+            time: Some(Time {
+                hour: (seconds / 3600) as u8,
+                minute: (seconds / 60 % 60) as u8,
+                second: (seconds % 60) as u8,
+                nanosecond: 0,
+            }),
+            offset: Some(Offset::Z),
+        }))
+    }
+
+    /// The number of whole seconds since the Unix epoch, or `None` if this is not a complete
+    /// datetime with a date, time and offset.
+    pub fn unix_timestamp(&self) -> Option<i64> {
+        let (date, time) = (self.0.date?, self.0.time?);
+        let offset_minutes = match self.0.offset? {
+            Offset::Z => 0,
+            Offset::Custom { minutes } => i64::from(minutes),
+        };
+        let days = days_from_civil(i64::from(date.year), date.month, date.day);
+        Some(
+            days * 86400
+                + i64::from(time.hour) * 3600
+                + i64::from(time.minute) * 60
+                + i64::from(time.second)
+                - offset_minutes * 60,
+        )
+    }
+
+    /// The underlying TOML datetime.
+    pub fn as_toml(&self) -> &Datetime {
+        &self.0
+    }
+}
+
+impl From<Datetime> for PrefsDateTime {
+    fn from(datetime: Datetime) -> Self {
+        Self(datetime)
+    }
+}
+
+impl From<PrefsDateTime> for Datetime {
+    fn from(datetime: PrefsDateTime) -> Self {
+        datetime.0
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl TryFrom<chrono::DateTime<chrono::FixedOffset>> for PrefsDateTime {
+    type Error = DatetimeParseError;
+
+    /// Convert a chrono datetime, keeping its offset. Fails if the year is outside 0..=9999.
+    fn try_from(datetime: chrono::DateTime<chrono::FixedOffset>) -> Result<Self, Self::Error> {
+        datetime
+            .to_rfc3339_opts(chrono::SecondsFormat::AutoSi, false)
+            .parse()
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl TryFrom<chrono::DateTime<chrono::Utc>> for PrefsDateTime {
+    type Error = DatetimeParseError;
+
+    fn try_from(datetime: chrono::DateTime<chrono::Utc>) -> Result<Self, Self::Error> {
+        datetime.fixed_offset().try_into()
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl TryFrom<PrefsDateTime> for chrono::DateTime<chrono::FixedOffset> {
+    type Error = chrono::ParseError;
+
+    /// Convert to a chrono datetime. Fails if this is not a complete datetime with a date, time
+    /// and offset.
+    fn try_from(datetime: PrefsDateTime) -> Result<Self, Self::Error> {
+        chrono::DateTime::parse_from_rfc3339(&datetime.to_string())
+    }
+}
+
+impl FromStr for PrefsDateTime {
+    type Err = DatetimeParseError;
+
+    /// Parse an RFC 3339 datetime, such as `2024-03-01T12:30:00Z`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
+impl fmt::Display for PrefsDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Serialize for PrefsDateTime {
+    /// Serialize as a TOML datetime. The JSON backend converts this to an RFC 3339 string.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PrefsDateTime {
+    /// Deserialize from either a TOML datetime or an RFC 3339 string.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(PrefsDateTimeVisitor)
+    }
+}
+
+struct PrefsDateTimeVisitor;
+
+impl<'de> Visitor<'de> for PrefsDateTimeVisitor {
+    type Value = PrefsDateTime;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a datetime")
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<PrefsDateTime, E> {
+        s.parse().map_err(E::custom)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<PrefsDateTime, A::Error> {
+        match map.next_key::<String>()? {
+            Some(key) if key == TOML_DATETIME_FIELD => {
+                let s = map.next_value::<String>()?;
+                s.parse().map_err(de::Error::custom)
+            }
+            _ => Err(de::Error::custom("expected a datetime")),
+        }
+    }
+}
+
+/// Convert a number of days since 1970-01-01 to a `(year, month, day)` date in the proleptic
+/// Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Convert a date in the proleptic Gregorian calendar to a number of days since 1970-01-01.
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unix_timestamp() {
+        for timestamp in [0, 951782400, 1709296200, -86400] {
+            let datetime = PrefsDateTime::from_unix_timestamp(timestamp).unwrap();
+            assert_eq!(datetime.unix_timestamp(), Some(timestamp));
+        }
+        assert_eq!(
+            PrefsDateTime::from_unix_timestamp(1709296200)
+                .unwrap()
+                .to_string(),
+            "2024-03-01T12:30:00Z"
+        );
+
+        let local: PrefsDateTime = "2024-03-01T14:30:00+02:00".parse().unwrap();
+        assert_eq!(local.unix_timestamp(), Some(1709296200));
+        let date_only: PrefsDateTime = "2024-03-01".parse().unwrap();
+        assert_eq!(date_only.unix_timestamp(), None);
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_chrono() {
+        use chrono::{DateTime, FixedOffset};
+
+        let local = DateTime::parse_from_rfc3339("2024-03-01T14:30:00.250+02:00").unwrap();
+        let datetime = PrefsDateTime::try_from(local).unwrap();
+        assert_eq!(datetime.to_string(), "2024-03-01T14:30:00.25+02:00");
+
+        let mut file = crate::PreferencesFile::new();
+        let mut group = file.get_group_mut("game").unwrap();
+        group.set_datetime("last_played", datetime);
+        let stored = group.get_datetime("last_played").unwrap();
+        let round_trip = DateTime::<FixedOffset>::try_from(stored).unwrap();
+        assert_eq!(round_trip, local);
+        assert_eq!(round_trip.offset(), local.offset());
+
+        let date_only: PrefsDateTime = "2024-03-01".parse().unwrap();
+        assert!(DateTime::<FixedOffset>::try_from(date_only).is_err());
+    }
+}
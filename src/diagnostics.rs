@@ -0,0 +1,72 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use bevy::{
+    app::{App, Last, Plugin},
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic},
+    ecs::system::Res,
+};
+
+use crate::Preferences;
+
+/// Running totals of preferences activity, kept by [`Preferences`].
+#[derive(Debug, Default)]
+pub(crate) struct PreferencesCounters {
+    pub(crate) saves: AtomicU64,
+    pub(crate) loads: AtomicU64,
+    pub(crate) errors: AtomicU64,
+    pub(crate) bytes_written: AtomicU64,
+    /// Measuring bytes written means serializing each file an extra time, so it is only done
+    /// once [`PreferencesDiagnosticsPlugin`] is running.
+    pub(crate) measure_bytes: AtomicBool,
+}
+
+impl PreferencesCounters {
+    /// Add `amount` to a counter.
+    pub(crate) fn add(counter: &AtomicU64, amount: u64) {
+        counter.fetch_add(amount, Ordering::Relaxed);
+    }
+}
+
+/// Plugin which reports preferences activity to Bevy's diagnostics. Each diagnostic is a running
+/// total since the [`Preferences`] resource was created.
+pub struct PreferencesDiagnosticsPlugin;
+
+impl PreferencesDiagnosticsPlugin {
+    /// The number of preferences files which have been saved.
+    pub const SAVES: DiagnosticPath = DiagnosticPath::const_new("prefs/saves");
+    /// The number of preferences files which have been loaded from the store.
+    pub const LOADS: DiagnosticPath = DiagnosticPath::const_new("prefs/loads");
+    /// The number of preferences files which could not be saved.
+    pub const ERRORS: DiagnosticPath = DiagnosticPath::const_new("prefs/errors");
+    /// The total size of the saved preferences files, in their serialized form.
+    pub const BYTES_WRITTEN: DiagnosticPath = DiagnosticPath::const_new("prefs/bytes_written");
+}
+
+impl Plugin for PreferencesDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_diagnostic(Diagnostic::new(Self::SAVES))
+            .register_diagnostic(Diagnostic::new(Self::LOADS))
+            .register_diagnostic(Diagnostic::new(Self::ERRORS))
+            .register_diagnostic(Diagnostic::new(Self::BYTES_WRITTEN).with_suffix(" bytes"))
+            .add_systems(Last, update_preferences_diagnostics);
+    }
+}
+
+fn update_preferences_diagnostics(prefs: Option<Res<Preferences>>, mut diagnostics: Diagnostics) {
+    let Some(prefs) = prefs else {
+        return;
+    };
+    let counters = prefs.counters();
+    counters.measure_bytes.store(true, Ordering::Relaxed);
+    for (path, counter) in [
+        (&PreferencesDiagnosticsPlugin::SAVES, &counters.saves),
+        (&PreferencesDiagnosticsPlugin::LOADS, &counters.loads),
+        (&PreferencesDiagnosticsPlugin::ERRORS, &counters.errors),
+        (
+            &PreferencesDiagnosticsPlugin::BYTES_WRITTEN,
+            &counters.bytes_written,
+        ),
+    ] {
+        diagnostics.add_measurement(path, || counter.load(Ordering::Relaxed) as f64);
+    }
+}
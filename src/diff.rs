@@ -0,0 +1,162 @@
+use std::{collections::BTreeMap, fmt};
+
+use crate::{
+    prefs_toml::{escape_key_segment, split_flat_key},
+    PrefsValue,
+};
+
+/// A change to a single value in a [`PrefsDiff`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrefsChange {
+    /// The value was added.
+    Added(PrefsValue),
+    /// The value was removed. This holds the value which was removed.
+    Removed(PrefsValue),
+    /// The value was changed, possibly to a different type.
+    Modified {
+        /// The previous value.
+        old: PrefsValue,
+        /// The new value.
+        new: PrefsValue,
+    },
+}
+
+impl PrefsChange {
+    /// The value after the change, or `None` if it was removed.
+    pub fn new_value(&self) -> Option<&PrefsValue> {
+        match self {
+            PrefsChange::Added(new) | PrefsChange::Modified { new, .. } => Some(new),
+            PrefsChange::Removed(_) => None,
+        }
+    }
+
+    /// True if the value on either side of the change is a group.
+    pub(crate) fn is_group(&self) -> bool {
+        match self {
+            PrefsChange::Added(value) | PrefsChange::Removed(value) => {
+                matches!(value, PrefsValue::Table(_))
+            }
+            PrefsChange::Modified { old, new } => {
+                matches!(old, PrefsValue::Table(_)) || matches!(new, PrefsValue::Table(_))
+            }
+        }
+    }
+}
+
+/// The differences between two versions of a preferences file, as computed by
+/// `PreferencesFile::diff`. Changes are keyed by dotted path, such as `"window.size"`; dots and
+/// backslashes within key names are escaped with a backslash. Groups which exist on both sides
+/// are compared key by key, so only the values which differ are listed. A group which was added
+/// or removed as a whole is listed as a single change.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PrefsDiff {
+    /// The changes, in path order.
+    pub changes: BTreeMap<String, PrefsChange>,
+}
+
+impl PrefsDiff {
+    /// Compute the changes needed to turn the top-level table `old` into `new`.
+    pub(crate) fn between(old: &PrefsValue, new: &PrefsValue) -> Self {
+        let mut diff = PrefsDiff::default();
+        if let (PrefsValue::Table(old), PrefsValue::Table(new)) = (old, new) {
+            diff.compare("", old, new);
+        }
+        diff
+    }
+
+    fn compare(
+        &mut self,
+        prefix: &str,
+        old: &BTreeMap<String, PrefsValue>,
+        new: &BTreeMap<String, PrefsValue>,
+    ) {
+        for (key, old_value) in old {
+            let path = format!("{prefix}{}", escape_key_segment(key));
+            match (old_value, new.get(key)) {
+                (_, None) => {
+                    self.changes
+                        .insert(path, PrefsChange::Removed(old_value.clone()));
+                }
+                (PrefsValue::Table(old_table), Some(PrefsValue::Table(new_table))) => {
+                    self.compare(&format!("{path}."), old_table, new_table);
+                }
+                (_, Some(new_value)) if new_value != old_value => {
+                    self.changes.insert(
+                        path,
+                        PrefsChange::Modified {
+                            old: old_value.clone(),
+                            new: new_value.clone(),
+                        },
+                    );
+                }
+                _ => {}
+            }
+        }
+        for (key, new_value) in new {
+            if !old.contains_key(key) {
+                let path = format!("{prefix}{}", escape_key_segment(key));
+                self.changes
+                    .insert(path, PrefsChange::Added(new_value.clone()));
+            }
+        }
+    }
+
+    /// Returns true if there are no changes.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The paths and values which were added.
+    pub fn added(&self) -> impl Iterator<Item = (&str, &PrefsValue)> {
+        self.changes
+            .iter()
+            .filter_map(|(path, change)| match change {
+                PrefsChange::Added(value) => Some((path.as_str(), value)),
+                _ => None,
+            })
+    }
+
+    /// The paths and previous values which were removed.
+    pub fn removed(&self) -> impl Iterator<Item = (&str, &PrefsValue)> {
+        self.changes
+            .iter()
+            .filter_map(|(path, change)| match change {
+                PrefsChange::Removed(value) => Some((path.as_str(), value)),
+                _ => None,
+            })
+    }
+
+    /// The paths which were modified, with their old and new values.
+    pub fn modified(&self) -> impl Iterator<Item = (&str, &PrefsValue, &PrefsValue)> {
+        self.changes
+            .iter()
+            .filter_map(|(path, change)| match change {
+                PrefsChange::Modified { old, new } => Some((path.as_str(), old, new)),
+                _ => None,
+            })
+    }
+
+    /// Split a path from [`PrefsDiff::changes`] into unescaped segments.
+    pub(crate) fn segments(path: &str) -> Vec<String> {
+        split_flat_key(path)
+    }
+}
+
+impl fmt::Display for PrefsDiff {
+    /// Format the changes one per line, such as `+ window.size = [800,600]` for an added value,
+    /// `- window.title = "Game"` for a removed one, and `~ audio.volume: 0.5 -> 0.8` for a
+    /// modified one. Values are written as JSON.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let json = |value: &PrefsValue| serde_json::to_string(value).map_err(|_| fmt::Error);
+        for (path, change) in &self.changes {
+            match change {
+                PrefsChange::Added(value) => writeln!(f, "+ {} = {}", path, json(value)?)?,
+                PrefsChange::Removed(value) => writeln!(f, "- {} = {}", path, json(value)?)?,
+                PrefsChange::Modified { old, new } => {
+                    writeln!(f, "~ {}: {} -> {}", path, json(old)?, json(new)?)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use crate::PrefsValueRef;
+
+/// The suffixes accepted by [`duration_from_value`], with their length in seconds. Longer
+/// suffixes come first, so that `ms` is not mistaken for `s`.
+const UNITS: [(&str, f64); 4] = [("ms", 0.001), ("s", 1.0), ("m", 60.0), ("h", 3600.0)];
+
+/// Format a duration as a human-friendly string, such as `"90s"`, `"5m"` or `"250ms"`. The
+/// largest unit which represents the duration exactly is used; durations which are not a whole
+/// number of milliseconds are written as fractional seconds.
+pub(crate) fn duration_to_string(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    if !duration.subsec_nanos().is_multiple_of(1_000_000) {
+        format!("{}s", duration.as_secs_f64())
+    } else if !millis.is_multiple_of(1000) {
+        format!("{millis}ms")
+    } else if millis.is_multiple_of(3_600_000) && millis > 0 {
+        format!("{}h", millis / 3_600_000)
+    } else if millis.is_multiple_of(60_000) && millis > 0 {
+        format!("{}m", millis / 60_000)
+    } else {
+        format!("{}s", millis / 1000)
+    }
+}
+
+/// Read a duration written by [`duration_to_string`]. The number may have a fraction, such as
+/// `"1.5m"`, and must be followed by one of the suffixes `ms`, `s`, `m` or `h`. A bare number,
+/// either as a string or a numeric value, is read as seconds. Negative or malformed values are
+/// rejected.
+pub(crate) fn duration_from_value(value: PrefsValueRef) -> Result<Duration, String> {
+    let secs = match value.as_str() {
+        Some(text) => parse_secs(text.trim())?,
+        None => value
+            .as_f64()
+            .ok_or_else(|| format!("expected a duration, found {:?}", value.kind()))?,
+    };
+    Duration::try_from_secs_f64(secs).map_err(|e| format!("invalid duration {secs}: {e}"))
+}
+
+/// Parse a number of seconds from a string with an optional unit suffix.
+fn parse_secs(text: &str) -> Result<f64, String> {
+    let (number, scale) = UNITS
+        .iter()
+        .find_map(|(suffix, scale)| Some((text.strip_suffix(suffix)?, *scale)))
+        .unwrap_or((text, 1.0));
+    match number.trim_end().parse::<f64>() {
+        Ok(number) if number.is_finite() => Ok(number * scale),
+        _ => Err(format!("invalid duration {text:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_duration_strings() {
+        let durations = [
+            (Duration::from_secs(90), "90s"),
+            (Duration::from_secs(300), "5m"),
+            (Duration::from_secs(7200), "2h"),
+            (Duration::from_millis(250), "250ms"),
+            (Duration::from_micros(1500), "0.0015s"),
+            (Duration::ZERO, "0s"),
+        ];
+        for (duration, text) in durations {
+            assert_eq!(duration_to_string(duration), text);
+            let value = json!(text);
+            assert_eq!(
+                duration_from_value(PrefsValueRef::Json(&value)),
+                Ok(duration)
+            );
+        }
+
+        let value = json!("1.5m");
+        assert_eq!(
+            duration_from_value(PrefsValueRef::Json(&value)),
+            Ok(Duration::from_secs(90))
+        );
+        let value = toml::Value::Integer(30);
+        assert_eq!(
+            duration_from_value(PrefsValueRef::Toml(&value)),
+            Ok(Duration::from_secs(30))
+        );
+        let value = json!(2.5);
+        assert_eq!(
+            duration_from_value(PrefsValueRef::Json(&value)),
+            Ok(Duration::from_millis(2500))
+        );
+
+        for bad in [
+            json!("-5s"),
+            json!("soon"),
+            json!("5 days"),
+            json!(-1),
+            json!(true),
+        ] {
+            assert!(duration_from_value(PrefsValueRef::Json(&bad)).is_err());
+        }
+    }
+}
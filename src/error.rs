@@ -0,0 +1,104 @@
+use std::fmt;
+
+use crate::{PreferencesSaveFailed, ValueKind};
+
+/// Errors which can occur when reading or writing preferences.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PreferencesError {
+    /// A value could not be converted into the preferences file format.
+    Serialize(String),
+    /// A value was rejected by the validator registered for its key.
+    Invalid {
+        /// The key which was being set.
+        key: String,
+        /// The message returned by the validator.
+        reason: String,
+    },
+    /// A preferences path had no segments.
+    EmptyPath,
+    /// A segment of a preferences path exists, but is not a group.
+    NotATable(String),
+    /// A key exists, but does not hold a list.
+    NotAList(String),
+    /// The store could not write a preferences file.
+    Storage(String),
+    /// A key name is empty, too long, or contains a reserved character.
+    InvalidKey {
+        /// The key which was rejected.
+        key: String,
+        /// Why the key was rejected.
+        reason: String,
+    },
+    /// A list index was past the end of the list.
+    IndexOutOfBounds {
+        /// The key which holds the list.
+        key: String,
+        /// The index which was requested.
+        index: usize,
+        /// The length of the list.
+        len: usize,
+    },
+    /// One or more preferences files could not be saved.
+    SaveFailed(Vec<PreferencesSaveFailed>),
+}
+
+impl fmt::Display for PreferencesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreferencesError::Serialize(e) => write!(f, "could not serialize value: {e}"),
+            PreferencesError::Invalid { key, reason } => {
+                write!(f, "invalid value for {key}: {reason}")
+            }
+            PreferencesError::EmptyPath => write!(f, "preferences path is empty"),
+            PreferencesError::NotATable(path) => write!(f, "{path} is not a preferences group"),
+            PreferencesError::NotAList(key) => write!(f, "{key} is not a list"),
+            PreferencesError::Storage(e) => write!(f, "could not write preferences: {e}"),
+            PreferencesError::InvalidKey { key, reason } => {
+                write!(f, "invalid key {key:?}: {reason}")
+            }
+            PreferencesError::IndexOutOfBounds { key, index, len } => {
+                write!(f, "index {index} is out of bounds for {key} (length {len})")
+            }
+            PreferencesError::SaveFailed(failed) => {
+                write!(f, "could not save preferences")?;
+                for (i, failed) in failed.iter().enumerate() {
+                    let separator = if i == 0 { ": " } else { "; " };
+                    write!(f, "{separator}{} ({})", failed.filename, failed.reason)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for PreferencesError {}
+
+/// Errors which can occur when reading a preferences value with `get_checked`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GetError {
+    /// The key does not exist.
+    Missing,
+    /// The key exists, but its value could not be deserialized as the requested type.
+    TypeMismatch {
+        /// The kind of value which was found.
+        found: ValueKind,
+        /// The message from the deserializer.
+        error: String,
+    },
+}
+
+impl fmt::Display for GetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GetError::Missing => write!(f, "preference is missing"),
+            GetError::TypeMismatch { found, error } => {
+                write!(
+                    f,
+                    "preference has the wrong type (found {found:?}): {error}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for GetError {}
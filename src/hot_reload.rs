@@ -0,0 +1,131 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use bevy::log::{error, warn};
+use crossbeam_channel::{unbounded, Receiver};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+
+/// Watches a preferences directory for files which are changed by other programs. Events are
+/// delivered by `notify` on its own thread, and collected until they are polled.
+pub(crate) struct FsWatcher {
+    _watcher: notify::RecommendedWatcher,
+    receiver: Receiver<PathBuf>,
+    /// The modification time of each file when it was last saved by this process, so that our
+    /// own writes are not reported as external changes.
+    saved: Mutex<HashMap<PathBuf, SystemTime>>,
+}
+
+impl FsWatcher {
+    /// Start watching `dir`, creating it if necessary. Returns `None`, after logging the reason,
+    /// if the watcher could not be started.
+    pub(crate) fn new(dir: &Path) -> Option<Self> {
+        if let Err(e) = fs::DirBuilder::new().recursive(true).create(dir) {
+            warn!("Could not create preferences directory: {:?}", e);
+            return None;
+        }
+        let (sender, receiver) = unbounded();
+        let watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            match event {
+                Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                    for path in event.paths {
+                        // The receiver is only dropped along with the watcher.
+                        let _ = sender.send(path);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => error!("Error watching preferences directory: {}", e),
+            }
+        });
+        let mut watcher = match watcher {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                warn!("Could not watch preferences directory: {}", e);
+                return None;
+            }
+        };
+        if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+            warn!("Could not watch preferences directory: {}", e);
+            return None;
+        }
+        Some(Self {
+            _watcher: watcher,
+            receiver,
+            saved: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Record that this process has just written `path`.
+    pub(crate) fn record_save(&self, path: &Path) {
+        if let Ok(modified) = fs::metadata(path).and_then(|metadata| metadata.modified()) {
+            self.saved
+                .lock()
+                .unwrap()
+                .insert(path.to_path_buf(), modified);
+        }
+    }
+
+    /// The paths which have changed since the last call, excluding files whose contents are
+    /// still the ones this process saved. Each path is listed once.
+    pub(crate) fn changed_paths(&self) -> Vec<PathBuf> {
+        let saved = self.saved.lock().unwrap();
+        let mut paths: Vec<PathBuf> = Vec::new();
+        for path in self.receiver.try_iter() {
+            if paths.contains(&path) {
+                continue;
+            }
+            let modified = fs::metadata(&path).and_then(|metadata| metadata.modified());
+            if modified.is_ok_and(|modified| saved.get(&path) == Some(&modified)) {
+                continue;
+            }
+            paths.push(path);
+        }
+        paths
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    /// Poll the watcher until it reports a change, or a few seconds have passed.
+    fn wait_for_changes(watcher: &FsWatcher) -> Vec<PathBuf> {
+        let start = Instant::now();
+        loop {
+            let paths = watcher.changed_paths();
+            if !paths.is_empty() || start.elapsed() > Duration::from_secs(3) {
+                return paths;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn test_fs_watcher() {
+        let dir =
+            std::env::temp_dir().join(format!("bevy_prefs_lite_watch_{}", std::process::id()));
+        let Some(watcher) = FsWatcher::new(&dir) else {
+            // File watching is not available on this system.
+            return;
+        };
+        let path = dir.join("settings.toml");
+
+        fs::write(&path, "volume = 0.5\n").unwrap();
+        let changed = wait_for_changes(&watcher);
+        assert!(changed.iter().any(|p| p.ends_with("settings.toml")));
+
+        // Our own saves are not reported.
+        fs::write(&path, "volume = 0.75\n").unwrap();
+        watcher.record_save(&path);
+        std::thread::sleep(Duration::from_millis(250));
+        assert!(watcher.changed_paths().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+use crate::PreferencesError;
+
+/// The maximum length of a preferences key, in characters.
+pub const MAX_KEY_LENGTH: usize = 256;
+
+/// Characters which may not appear in a preferences key. `.` is reserved for paths such as
+/// `"window.position"`, and the others would corrupt a hand-edited file.
+const RESERVED_CHARS: [char; 4] = ['.', '\n', '\r', '='];
+
+/// Keys beginning with this prefix, such as `_version` or `_comment`, are reserved for metadata.
+/// They are stored and saved like any other key, but are skipped by the `keys` and `iter`
+/// methods of a preferences group, so that they don't appear in generic settings UIs. Apps
+/// should not use this prefix for ordinary preferences.
+pub const RESERVED_KEY_PREFIX: &str = "_";
+
+/// Returns true if `key` is a reserved metadata key. See [`RESERVED_KEY_PREFIX`].
+pub fn is_reserved_key(key: &str) -> bool {
+    key.starts_with(RESERVED_KEY_PREFIX)
+}
+
+/// Check that `key` can be used as the name of a preferences key. Keys must be non-empty, no
+/// longer than [`MAX_KEY_LENGTH`] characters, and must not contain `.`, `=` or line breaks.
+pub fn validate_key(key: &str) -> Result<(), PreferencesError> {
+    let invalid = |reason: &str| {
+        Err(PreferencesError::InvalidKey {
+            key: key.to_owned(),
+            reason: reason.to_owned(),
+        })
+    };
+    if key.is_empty() {
+        return invalid("key is empty");
+    }
+    if key.chars().count() > MAX_KEY_LENGTH {
+        return invalid("key is too long");
+    }
+    if let Some(c) = key.chars().find(|c| RESERVED_CHARS.contains(c)) {
+        return invalid(&format!("key contains {c:?}"));
+    }
+    Ok(())
+}
+
+/// Check a key which is about to be set with `set`. Invalid keys are a programming error, so they
+/// panic in debug builds; release builds leave `try_set` to return the error, which is logged.
+pub(crate) fn debug_check_key(key: &str) {
+    if let Err(e) = validate_key(key) {
+        debug_assert!(false, "{e}");
+    }
+}
+
+/// Escape one segment of a web storage key, so that it does not contain `:`.
+#[cfg(any(target_arch = "wasm32", test))]
+pub(crate) fn escape_key_segment(segment: &str) -> String {
+    segment.replace('%', "%25").replace(':', "%3A")
+}
+
+/// Reverse [`escape_key_segment`].
+#[cfg(any(target_arch = "wasm32", test))]
+pub(crate) fn unescape_key_segment(segment: &str) -> String {
+    segment.replace("%3A", ":").replace("%25", "%")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_key() {
+        assert_eq!(validate_key("window_mode"), Ok(()));
+        assert_eq!(validate_key(&"k".repeat(MAX_KEY_LENGTH)), Ok(()));
+        for key in ["", "window.mode", "a\nb", "a\rb", "a=b"] {
+            assert!(matches!(
+                validate_key(key),
+                Err(PreferencesError::InvalidKey { .. })
+            ));
+        }
+        assert!(validate_key(&"k".repeat(MAX_KEY_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "window.mode")]
+    fn test_set_invalid_key() {
+        let mut file = crate::PreferencesFile::new();
+        file.get_group_mut("window")
+            .unwrap()
+            .set("window.mode", "borderless");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "window.mode")]
+    fn test_set_if_changed_invalid_key() {
+        let mut file = crate::PreferencesFile::new();
+        file.get_group_mut("window")
+            .unwrap()
+            .set_if_changed("window.mode", "borderless");
+    }
+
+    #[test]
+    fn test_key_segment_round_trip() {
+        for segment in ["settings", "a::b", "a%3Ab", "100%", "%", ":", "%25:%3A"] {
+            let escaped = escape_key_segment(segment);
+            assert!(!escaped.contains(':'));
+            assert_eq!(unescape_key_segment(&escaped), segment);
+        }
+    }
+
+    #[test]
+    fn test_key_segments_do_not_alias() {
+        let storage_key = |app: &str, file: &str| {
+            format!("{}::{}", escape_key_segment(app), escape_key_segment(file))
+        };
+        assert_ne!(
+            storage_key("foo", "bar::baz"),
+            storage_key("foo::bar", "baz")
+        );
+        assert_ne!(storage_key("foo:", ":bar"), storage_key("foo", "::bar"));
+        assert_ne!(escape_key_segment("a:b"), escape_key_segment("a%3Ab"));
+    }
+}
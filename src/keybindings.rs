@@ -0,0 +1,229 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use bevy::{
+    input::{keyboard::KeyCode, mouse::MouseButton},
+    log::warn,
+    platform::collections::HashMap,
+};
+use serde_json::Value as JsonValue;
+
+use crate::PrefsValue;
+
+/// Input bindings for a set of actions, keyed by action name. Each action may have several
+/// bindings. Use with `set_keybindings` and `get_keybindings` on a preferences group.
+pub type BindingMap = HashMap<String, Vec<Binding>>;
+
+/// A key or mouse button which can be bound to an action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BindingInput {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+/// The modifier keys which must be held along with a binding. Either the left or right key
+/// satisfies each modifier.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BindingModifiers {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    pub super_key: bool,
+}
+
+/// A single input binding, such as `Ctrl+KeyS`.
+///
+/// Bindings are stored as strings, so that they are stable across Bevy versions and easy to edit
+/// by hand. Keys use the names of the [`KeyCode`] variants, such as `"KeyW"` or `"Space"`. Mouse
+/// buttons are `"Mouse1"` (left), `"Mouse2"` (right), `"Mouse3"` (middle), `"Mouse4"` (back),
+/// `"Mouse5"` (forward), or `"MouseOther7"` for other buttons. Modifiers are written before the
+/// input, separated by `+`, and may be any of `Ctrl`, `Shift`, `Alt` and `Super`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Binding {
+    pub input: BindingInput,
+    pub modifiers: BindingModifiers,
+}
+
+impl Binding {
+    /// A binding to a key, with no modifiers.
+    pub fn key(key: KeyCode) -> Self {
+        Self {
+            input: BindingInput::Key(key),
+            modifiers: BindingModifiers::default(),
+        }
+    }
+
+    /// A binding to a mouse button, with no modifiers.
+    pub fn mouse(button: MouseButton) -> Self {
+        Self {
+            input: BindingInput::Mouse(button),
+            modifiers: BindingModifiers::default(),
+        }
+    }
+
+    /// Require the Ctrl key to be held.
+    pub fn with_ctrl(mut self) -> Self {
+        self.modifiers.ctrl = true;
+        self
+    }
+
+    /// Require the Shift key to be held.
+    pub fn with_shift(mut self) -> Self {
+        self.modifiers.shift = true;
+        self
+    }
+
+    /// Require the Alt key to be held.
+    pub fn with_alt(mut self) -> Self {
+        self.modifiers.alt = true;
+        self
+    }
+
+    /// Require the Super (Windows or Command) key to be held.
+    pub fn with_super(mut self) -> Self {
+        self.modifiers.super_key = true;
+        self
+    }
+}
+
+impl fmt::Display for Binding {
+    /// Format the binding in its stored form, such as `Ctrl+KeyS`. Keys which have no stable
+    /// name, such as [`KeyCode::Unidentified`], are formatted with their debug form, and can't
+    /// be parsed back.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let modifiers = [
+            (self.modifiers.ctrl, "Ctrl+"),
+            (self.modifiers.shift, "Shift+"),
+            (self.modifiers.alt, "Alt+"),
+            (self.modifiers.super_key, "Super+"),
+        ];
+        for (held, name) in modifiers {
+            if held {
+                f.write_str(name)?;
+            }
+        }
+        match self.input {
+            BindingInput::Key(key) => match serde_json::to_value(key) {
+                Ok(JsonValue::String(name)) => f.write_str(&name),
+                _ => write!(f, "{key:?}"),
+            },
+            BindingInput::Mouse(MouseButton::Left) => f.write_str("Mouse1"),
+            BindingInput::Mouse(MouseButton::Right) => f.write_str("Mouse2"),
+            BindingInput::Mouse(MouseButton::Middle) => f.write_str("Mouse3"),
+            BindingInput::Mouse(MouseButton::Back) => f.write_str("Mouse4"),
+            BindingInput::Mouse(MouseButton::Forward) => f.write_str("Mouse5"),
+            BindingInput::Mouse(MouseButton::Other(n)) => write!(f, "MouseOther{n}"),
+        }
+    }
+}
+
+impl FromStr for Binding {
+    type Err = String;
+
+    /// Parse a binding in its stored form. Modifier names are not case-sensitive, but key names
+    /// are.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts: Vec<&str> = s.split('+').map(str::trim).collect();
+        let input = parts.pop().unwrap_or_default();
+        let mut modifiers = BindingModifiers::default();
+        for modifier in parts {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => modifiers.ctrl = true,
+                "shift" => modifiers.shift = true,
+                "alt" => modifiers.alt = true,
+                "super" | "meta" | "cmd" => modifiers.super_key = true,
+                _ => return Err(format!("unknown modifier {modifier:?}")),
+            }
+        }
+        let input = match input {
+            "Mouse1" => BindingInput::Mouse(MouseButton::Left),
+            "Mouse2" => BindingInput::Mouse(MouseButton::Right),
+            "Mouse3" => BindingInput::Mouse(MouseButton::Middle),
+            "Mouse4" => BindingInput::Mouse(MouseButton::Back),
+            "Mouse5" => BindingInput::Mouse(MouseButton::Forward),
+            _ => match input.strip_prefix("MouseOther").map(str::parse) {
+                Some(Ok(n)) => BindingInput::Mouse(MouseButton::Other(n)),
+                _ => BindingInput::Key(
+                    serde_json::from_value(JsonValue::String(input.to_owned()))
+                        .map_err(|_| format!("unknown key {input:?}"))?,
+                ),
+            },
+        };
+        Ok(Self { input, modifiers })
+    }
+}
+
+/// Convert bindings into the form they are stored in: a table of action names, each holding a
+/// list of binding strings. Actions are sorted so that the file is stable.
+pub(crate) fn bindings_to_value(bindings: &BindingMap) -> BTreeMap<&str, Vec<String>> {
+    bindings
+        .iter()
+        .map(|(action, bindings)| {
+            (
+                action.as_str(),
+                bindings.iter().map(Binding::to_string).collect(),
+            )
+        })
+        .collect()
+}
+
+/// Read bindings in the form written by [`bindings_to_value`]. This is lenient, so that one bad
+/// entry doesn't lose the rest of the user's bindings: bindings which can't be parsed, such as
+/// keys which were renamed in a newer version of Bevy, are skipped with a warning. A single
+/// string is accepted in place of a list.
+pub(crate) fn bindings_from_value(value: HashMap<String, PrefsValue>) -> BindingMap {
+    value
+        .into_iter()
+        .map(|(action, value)| {
+            let names = match value {
+                PrefsValue::Array(names) => names,
+                value => vec![value],
+            };
+            let bindings = names
+                .into_iter()
+                .filter_map(|name| match name {
+                    PrefsValue::String(name) => match name.parse() {
+                        Ok(binding) => Some(binding),
+                        Err(e) => {
+                            warn!("Skipping binding for {}: {}", action, e);
+                            None
+                        }
+                    },
+                    other => {
+                        warn!("Skipping binding for {}: not a string: {:?}", action, other);
+                        None
+                    }
+                })
+                .collect();
+            (action, bindings)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binding_names() {
+        let bindings = [
+            (Binding::key(KeyCode::KeyW), "KeyW"),
+            (Binding::key(KeyCode::Space), "Space"),
+            (Binding::mouse(MouseButton::Left), "Mouse1"),
+            (Binding::mouse(MouseButton::Other(7)), "MouseOther7"),
+            (
+                Binding::key(KeyCode::KeyS).with_ctrl().with_shift(),
+                "Ctrl+Shift+KeyS",
+            ),
+        ];
+        for (binding, name) in bindings {
+            assert_eq!(binding.to_string(), name);
+            assert_eq!(name.parse::<Binding>(), Ok(binding));
+        }
+        assert_eq!(
+            "ctrl + KeyS".parse::<Binding>(),
+            Ok(Binding::key(KeyCode::KeyS).with_ctrl())
+        );
+        assert!("KeyBogus".parse::<Binding>().is_err());
+        assert!("Hyper+KeyS".parse::<Binding>().is_err());
+    }
+}
@@ -1,11 +1,47 @@
 mod autosave;
-
-pub use autosave::{AutosavePrefsPlugin, StartAutosaveTimer};
-
+mod builder;
+mod bytes;
+mod changed;
+mod color;
+mod compression;
+mod datetime;
+mod diagnostics;
+mod diff;
+mod duration;
+
+pub use autosave::{
+    AutosavePrefsPlugin, CancelAutosaveTimer, GroupAutosavePolicy, PreferencesSystem,
+    PrefsDebounceConfig, StartAutosaveTimer,
+};
+
+mod error;
+#[cfg(all(feature = "hot-reload", not(target_arch = "wasm32")))]
+mod hot_reload;
+mod key;
+mod keybindings;
+mod plugin;
 mod prefs;
+mod resource;
+mod scene;
 
 pub mod prefs_json;
+#[cfg(all(feature = "json5", not(target_arch = "wasm32")))]
+mod prefs_json5;
+#[cfg(not(target_arch = "wasm32"))]
+mod prefs_json_file;
 pub mod prefs_toml;
+mod toml_de;
+mod toml_document;
+
+mod value;
+mod watch;
+
+pub use value::{PrefsEntries, PrefsValue, PrefsValueRef, ValueKind};
+
+mod store_chain;
+#[cfg(feature = "figment-integration")]
+mod store_figment;
+mod store_read_only;
 
 #[cfg(not(target_arch = "wasm32"))]
 mod store_fs;
@@ -13,14 +49,38 @@ mod store_fs;
 #[cfg(target_arch = "wasm32")]
 mod store_wasm;
 
-use bevy::ecs::{system::Command, world::World};
+use bevy::ecs::{
+    message::{Message, Messages},
+    system::Command,
+    world::World,
+};
+pub use bytes::BYTES_SIZE_WARNING;
+pub use compression::CompressionAlgorithm;
+pub use datetime::PrefsDateTime;
+pub use diff::{PrefsChange, PrefsDiff};
+pub use store_chain::{BoxedPreferencesStore, StoreChain};
+#[cfg(feature = "figment-integration")]
+pub use store_figment::StoreFigment;
+pub use store_read_only::StoreReadOnly;
+
 #[cfg(not(target_arch = "wasm32"))]
-pub use store_fs::StoreFs;
+pub use store_fs::{FsFormat, StoreFs};
 
 #[cfg(target_arch = "wasm32")]
 pub use store_wasm::StoreWasm;
 
-pub use crate::prefs::Preferences;
+pub use crate::builder::PreferencesFileBuilder;
+pub use crate::diagnostics::PreferencesDiagnosticsPlugin;
+pub use crate::error::{GetError, PreferencesError};
+pub use crate::key::{is_reserved_key, validate_key, MAX_KEY_LENGTH, RESERVED_KEY_PREFIX};
+pub use crate::keybindings::{Binding, BindingInput, BindingMap, BindingModifiers};
+pub use crate::plugin::{preferences_ready, PreferencesPlugin, PreferencesReady};
+pub use crate::prefs::{
+    Preferences, PreferencesSchema, PreferencesStore, RequiredPreferenceMissing, RequiredSeverity,
+};
+pub use crate::resource::PreferencesAppExt;
+pub use crate::scene::{ScenePreferences, ScenePrefsPlugin, UnloadScenePreferences};
+pub use crate::watch::{watch_pref_key, PrefKeyChanged};
 
 #[cfg(target_arch = "wasm32")]
 mod format {
@@ -30,6 +90,8 @@ mod format {
     pub type PreferencesFileContent = prefs_json::JsonPreferencesFileContent;
     pub type PreferencesGroup<'a> = prefs_json::JsonPreferencesGroup<'a>;
     pub type PreferencesGroupMut<'a> = prefs_json::JsonPreferencesGroupMut<'a>;
+    pub type PreferencesGroupRef<'a> = prefs_json::JsonPreferencesGroupRef<'a>;
+    pub type PrefEntry<'a> = prefs_json::JsonPrefEntry<'a>;
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -40,10 +102,50 @@ mod format {
     pub type PreferencesFileContent = prefs_toml::TomlPreferencesFileContent;
     pub type PreferencesGroup<'a> = prefs_toml::TomlPreferencesGroup<'a>;
     pub type PreferencesGroupMut<'a> = prefs_toml::TomlPreferencesGroupMut<'a>;
+    pub type PreferencesGroupRef<'a> = prefs_toml::TomlPreferencesGroupRef<'a>;
+    pub type PrefEntry<'a> = prefs_toml::TomlPrefEntry<'a>;
 }
 
 pub use self::format::*;
 
+/// Message which is sent when a preferences file was changed by another program, and has been
+/// reloaded. See `StoreFs::with_hot_reload`.
+#[derive(Message, Debug, Clone, PartialEq, Eq)]
+pub struct PreferencesFileChanged {
+    /// The name of the preferences file, without the file extension.
+    pub filename: String,
+}
+
+/// Message which is sent when a preferences file could not be saved.
+#[derive(Message, Debug, Clone, PartialEq)]
+pub struct PreferencesSaveFailed {
+    /// The name of the preferences file, without the file extension.
+    pub filename: String,
+    /// Why the file was not saved.
+    pub reason: String,
+}
+
+/// Message which is sent when a preferences file could not be parsed. The file is moved aside
+/// to a backup, so that it is not overwritten by the next save, and the app carries on as if the
+/// file did not exist.
+#[derive(Message, Debug, Clone, PartialEq)]
+pub struct PreferencesFileCorrupt {
+    /// The name of the preferences file, without the file extension.
+    pub filename: String,
+    /// Where the broken file was copied to: a path on desktop, or a LocalStorage key on the web.
+    /// This is `None` if it could not be backed up.
+    pub backup: Option<String>,
+    /// Why the file could not be parsed.
+    pub reason: String,
+}
+
+/// Send [`PreferencesSaveFailed`] messages, if the message type has been registered.
+fn report_save_failures(world: &mut World, failed: Vec<PreferencesSaveFailed>) {
+    if !failed.is_empty() && world.contains_resource::<Messages<PreferencesSaveFailed>>() {
+        world.write_message_batch(failed);
+    }
+}
+
 /// A Command which saves preferences to disk. This blocks the command queue until saving
 /// is complete.
 #[derive(Default, PartialEq)]
@@ -58,7 +160,32 @@ pub enum SavePreferencesSync {
 impl Command for SavePreferencesSync {
     fn apply(self, world: &mut World) {
         let prefs = world.get_resource::<Preferences>().unwrap();
-        prefs.save(self == SavePreferencesSync::Always);
+        let failed = prefs.save(self == SavePreferencesSync::Always);
+        report_save_failures(world, failed);
+    }
+}
+
+/// A Command which saves a single preferences file to disk if it has changed, leaving other
+/// changed files for later. Actual FS operations happen in another thread.
+pub struct SavePreferencesFile(pub String);
+
+impl Command for SavePreferencesFile {
+    fn apply(self, world: &mut World) {
+        let prefs = world.get_resource::<Preferences>().unwrap();
+        let failed = prefs.save_specific_async(&self.0, false);
+        report_save_failures(world, failed);
+    }
+}
+
+/// A Command which saves a single preferences file to disk if it has changed. This blocks the
+/// command queue until saving is complete.
+pub struct SavePreferencesFileSync(pub String);
+
+impl Command for SavePreferencesFileSync {
+    fn apply(self, world: &mut World) {
+        let prefs = world.get_resource::<Preferences>().unwrap();
+        let failed = prefs.save_specific(&self.0, false);
+        report_save_failures(world, failed);
     }
 }
 
@@ -70,11 +197,22 @@ pub enum SavePreferences {
     IfChanged,
     /// Save preferences unconditionally.
     Always,
+    /// Save changed preferences on the current thread, without spawning a task, so that they
+    /// are on disk by the time the command has been applied. This does the same as
+    /// [`SavePreferencesSync::IfChanged`], and is convenient on a shutdown path, such as just
+    /// before sending [`AppExit`](bevy::app::AppExit), where there may be no later frame for a
+    /// background save to finish in.
+    Immediate,
 }
 
 impl Command for SavePreferences {
     fn apply(self, world: &mut World) {
         let prefs = world.get_resource::<Preferences>().unwrap();
-        prefs.save_async(self == SavePreferences::Always);
+        let failed = match self {
+            SavePreferences::IfChanged => prefs.save_async(false),
+            SavePreferences::Always => prefs.save_async(true),
+            SavePreferences::Immediate => prefs.save(false),
+        };
+        report_save_failures(world, failed);
     }
 }
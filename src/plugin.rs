@@ -0,0 +1,163 @@
+use bevy::{
+    app::{App, Plugin},
+    ecs::{resource::Resource, system::Res},
+};
+
+use crate::{AutosavePrefsPlugin, Preferences, PrefsDebounceConfig};
+
+/// Plugin which sets up preferences for an app, so that it does not need to be done in `main`.
+/// When the app finishes building, this inserts a [`Preferences`] resource for the given app
+/// name, and loads each of the preloaded files into it. This happens before any schedule runs,
+/// so systems, including startup systems, can assume that those files are present.
+///
+/// If a `Preferences` resource has already been inserted, for example one which uses a custom
+/// store, it is used instead and the app name is ignored.
+///
+/// By default this also adds [`AutosavePrefsPlugin`], unless it has already been added.
+pub struct PreferencesPlugin {
+    app_name: String,
+    autosave: bool,
+    autosave_delay_secs: f32,
+    preload: Vec<String>,
+}
+
+impl PreferencesPlugin {
+    /// Construct a new preferences plugin.
+    ///
+    /// # Arguments
+    /// * `app_name` - The name of the application, which is passed to [`Preferences::new`].
+    pub fn new(app_name: &str) -> Self {
+        Self {
+            app_name: app_name.to_owned(),
+            autosave: true,
+            autosave_delay_secs: PrefsDebounceConfig::default().delay_secs,
+            preload: Vec::new(),
+        }
+    }
+
+    /// Whether to add [`AutosavePrefsPlugin`]. Defaults to true.
+    pub fn with_autosave(mut self, autosave: bool) -> Self {
+        self.autosave = autosave;
+        self
+    }
+
+    /// How long to wait after [`StartAutosaveTimer`](crate::StartAutosaveTimer) before saving,
+    /// in seconds. This sets [`PrefsDebounceConfig::delay_secs`]. Defaults to 1 second.
+    pub fn with_autosave_delay(mut self, secs: f32) -> Self {
+        self.autosave_delay_secs = secs;
+        self
+    }
+
+    /// Load the named preferences files at startup, in parallel with [`Preferences::preload`].
+    /// Files which do not exist yet are created empty, the same as [`Preferences::get_mut`]
+    /// does, so they are always present.
+    pub fn preload(mut self, filenames: &[&str]) -> Self {
+        self.preload
+            .extend(filenames.iter().map(|name| (*name).to_owned()));
+        self
+    }
+}
+
+impl Plugin for PreferencesPlugin {
+    fn build(&self, app: &mut App) {
+        if !self.autosave {
+            return;
+        }
+        if !app.is_plugin_added::<AutosavePrefsPlugin>() {
+            app.add_plugins(AutosavePrefsPlugin);
+        }
+        app.world_mut()
+            .resource_mut::<PrefsDebounceConfig>()
+            .delay_secs = self.autosave_delay_secs;
+    }
+
+    fn finish(&self, app: &mut App) {
+        let world = app.world_mut();
+        if !world.contains_resource::<Preferences>() {
+            world.insert_resource(Preferences::new(&self.app_name));
+        }
+        let mut prefs = world.resource_mut::<Preferences>();
+        let filenames: Vec<&str> = self.preload.iter().map(String::as_str).collect();
+        prefs.preload(&filenames);
+        for filename in filenames {
+            prefs.get_mut(filename);
+        }
+        world.insert_resource(PreferencesReady {
+            files: self.preload.clone(),
+        });
+    }
+}
+
+/// Resource which is inserted by [`PreferencesPlugin`] once the files listed with
+/// [`PreferencesPlugin::preload`] have been loaded. Files which could not be loaded are created
+/// empty, so they count as loaded.
+#[derive(Resource, Debug, Clone)]
+pub struct PreferencesReady {
+    /// The names of the preloaded files.
+    pub files: Vec<String>,
+}
+
+/// Run condition which is true once [`PreferencesPlugin`] has loaded its preloaded files. This
+/// can be used to hold back gameplay systems, or a transition out of a loading state, until
+/// preferences are available:
+///
+/// ```ignore
+/// app.add_systems(Update, finish_loading.run_if(preferences_ready));
+/// ```
+pub fn preferences_ready(ready: Option<Res<PreferencesReady>>) -> bool {
+    ready.is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::app::TaskPoolPlugin;
+
+    use crate::StoreChain;
+
+    use super::*;
+
+    #[test]
+    fn test_preload() {
+        let mut app = App::new();
+        app.add_plugins(TaskPoolPlugin::default())
+            .insert_resource(Preferences::with_store(StoreChain::new(Vec::new())))
+            .add_plugins(PreferencesPlugin::new("org.example.test").preload(&["settings"]));
+        app.finish();
+
+        let prefs = app.world().resource::<Preferences>();
+        let loaded: Vec<_> = prefs.loaded_files().map(|(filename, _)| filename).collect();
+        assert_eq!(loaded, ["settings"]);
+        assert_eq!(
+            app.world().resource::<PreferencesReady>().files,
+            ["settings"]
+        );
+    }
+
+    #[test]
+    fn test_preferences_ready() {
+        use bevy::ecs::system::RunSystemOnce;
+
+        let mut app = App::new();
+        app.add_plugins(TaskPoolPlugin::default())
+            .insert_resource(Preferences::with_store(StoreChain::new(Vec::new())))
+            .add_plugins(PreferencesPlugin::new("org.example.test").with_autosave(false));
+        assert!(!app.world_mut().run_system_once(preferences_ready).unwrap());
+        app.finish();
+        assert!(app.world_mut().run_system_once(preferences_ready).unwrap());
+    }
+
+    #[test]
+    fn test_autosave_config() {
+        let mut app = App::new();
+        app.add_plugins(PreferencesPlugin::new("org.example.test").with_autosave_delay(3.0));
+        assert!(app.is_plugin_added::<AutosavePrefsPlugin>());
+        assert_eq!(
+            app.world().resource::<PrefsDebounceConfig>().delay_secs,
+            3.0
+        );
+
+        let mut app = App::new();
+        app.add_plugins(PreferencesPlugin::new("org.example.test").with_autosave(false));
+        assert!(!app.is_plugin_added::<AutosavePrefsPlugin>());
+    }
+}
@@ -1,4 +1,14 @@
-use bevy::{ecs::resource::Resource, log::info, platform::collections::HashMap};
+use bevy::{
+    ecs::{message::Message, resource::Resource},
+    log::{error, info, warn},
+    platform::collections::{HashMap, HashSet},
+    tasks::IoTaskPool,
+};
+use serde::de::DeserializeOwned;
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::SystemTime,
+};
 
 #[cfg(not(target_arch = "wasm32"))]
 use crate::StoreFs;
@@ -6,7 +16,12 @@ use crate::StoreFs;
 #[cfg(target_arch = "wasm32")]
 use crate::StoreWasm;
 
+use crate::{
+    diagnostics::PreferencesCounters, GroupAutosavePolicy, PreferencesError,
+    PreferencesFileCorrupt, PreferencesSaveFailed, PrefsDiff, PrefsValue,
+};
 pub use crate::{PreferencesFile, PreferencesFileContent};
+use crate::{PreferencesGroup, PreferencesGroupMut};
 
 // TODO: Think about potential Results:
 // NoFile
@@ -26,19 +41,162 @@ pub trait PreferencesStore {
     /// Read a [`PreferencesFile`] from the store.
     fn load(&mut self, filename: &str) -> Option<PreferencesFile>;
 
-    /// Save a [`PreferencesFile`] to the store.
+    /// Read a [`PreferencesFile`] from the store without exclusive access, so that it can be
+    /// called from another thread. This is used by [`Preferences::preload`] to read several
+    /// files in parallel.
+    fn load_async(&self, filename: &str) -> Option<PreferencesFile>;
+
+    /// Read a [`PreferencesFile`] which is already loaded, such as to reload it or to compare it
+    /// with the copy in memory. Unlike [`load_async`](Self::load_async), this must have no side
+    /// effects: a file which can't be parsed is logged and left as it is, rather than backed up
+    /// and reported, since the copy in memory is still good. The default calls `load_async`,
+    /// which suits stores that don't back up corrupt files.
+    fn reload(&self, filename: &str) -> Option<PreferencesFile> {
+        self.load_async(filename)
+    }
+
+    /// Save a [`PreferencesFile`] to the store. Returns a [`PreferencesError::Storage`] error
+    /// if the file could not be written.
     ///
     /// # Arguments
     /// * `filename` - the filename of the [`PreferencesFile`].
     /// * `file` - the contents of the file.
-    fn save(&self, filename: &str, file: &PreferencesFile);
+    fn save(&self, filename: &str, file: &PreferencesFile) -> Result<(), PreferencesError>;
 
-    /// Save a [`PreferencesFile`] to the store in another thread.
+    /// Save a [`PreferencesFile`] to the store in another thread. Since there is no way to
+    /// return an error to the caller, failures should be logged, and returned from
+    /// [`take_save_failures`](PreferencesStore::take_save_failures) once the save has finished.
     ///
     /// # Arguments
     /// * `filename` - the filename of the [`PreferencesFile`].
     /// * `file` - the contents of the file.
     fn save_async(&self, filename: &str, file: PreferencesFileContent);
+
+    /// Returns the names of files which were changed by another program since the last call.
+    /// Stores which don't watch for changes return an empty list, which is the default.
+    fn take_external_changes(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Returns the files which could not be saved by `save_async` since the last call. Stores
+    /// which save synchronously, or which only log failures, return an empty list, which is the
+    /// default.
+    fn take_save_failures(&self) -> Vec<PreferencesSaveFailed> {
+        Vec::new()
+    }
+
+    /// Returns the name of each file which `save_async` has finished writing since the last
+    /// call, with the number of bytes written. These are counted by the activity counters;
+    /// stores which don't report them, which is the default, leave asynchronous saves uncounted.
+    fn take_completed_saves(&self) -> Vec<(String, usize)> {
+        Vec::new()
+    }
+
+    /// Returns the files which could not be parsed when they were loaded since the last call.
+    /// Stores should back up a corrupt file before reporting it, since it will be overwritten
+    /// when the empty file which replaces it is saved. The default is an empty list.
+    fn take_corrupt_files(&self) -> Vec<PreferencesFileCorrupt> {
+        Vec::new()
+    }
+
+    /// Returns the time at which a file was last written, or `None` if the file does not exist
+    /// or the store does not track modification times, which is the default.
+    ///
+    /// # Arguments
+    /// * `filename` - The name of the preferences file, without the file extension.
+    fn modified(&self, _filename: &str) -> Option<SystemTime> {
+        None
+    }
+
+    /// Point the store at the preferences of a different app, such as when switching between
+    /// profiles or tenants at runtime. Stores which aren't keyed by app name ignore this, which
+    /// is the default.
+    ///
+    /// # Arguments
+    /// * `app_name` - The new name of the application, as passed to [`Preferences::new`].
+    fn set_app_name(&mut self, _app_name: &str) {}
+}
+
+/// Declares the structure of a preferences file: its name, the default values written when it
+/// is first created, and the checks it must pass before it is saved. This is usually implemented
+/// for a unit struct, and used with [`Preferences::get_schema_mut`].
+///
+/// ```ignore
+/// struct AudioSchema;
+///
+/// impl PreferencesSchema for AudioSchema {
+///     fn filename() -> &'static str {
+///         "audio"
+///     }
+///
+///     fn apply_defaults(file: &mut PreferencesGroupMut) {
+///         file.get_group_mut("volume").unwrap().set("master", 0.8);
+///     }
+///
+///     fn validate(file: &PreferencesGroup) -> Result<(), Vec<String>> {
+///         match file.get_group("volume").and_then(|g| g.get::<f32>("master")) {
+///             Some(v) if !(0.0..=1.0).contains(&v) => Err(vec!["volume.master out of range".into()]),
+///             _ => Ok(()),
+///         }
+///     }
+/// }
+/// ```
+pub trait PreferencesSchema {
+    /// The name of the preferences file, without the file extension.
+    fn filename() -> &'static str;
+
+    /// Write default values into a newly created file. `file` is the top level of the file.
+    fn apply_defaults(file: &mut PreferencesGroupMut);
+
+    /// Check the file before it is saved, returning a list of problems if it is invalid.
+    /// `file` is the top level of the file.
+    fn validate(file: &PreferencesGroup) -> Result<(), Vec<String>>;
+}
+
+/// A function which checks a [`PreferencesFile`] before it is saved.
+type FileValidator = Box<dyn Fn(&PreferencesFile) -> Result<(), String> + Send + Sync>;
+
+/// A function which fixes up a [`PreferencesFile`] after it is loaded.
+type FileLoader = Box<dyn Fn(&mut PreferencesFile) + Send + Sync>;
+
+/// A function which checks, and may correct, the value of a single key. These are shared
+/// between the registration and every file they are applied to.
+type KeyValidator = Arc<dyn Fn(&mut PrefsValue) -> Result<(), String> + Send + Sync>;
+
+/// How to report a required preference which is missing or has the wrong type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequiredSeverity {
+    /// Log an error.
+    Log,
+    /// Log an error and send a [`RequiredPreferenceMissing`] message.
+    Message,
+    /// Panic, listing every missing key in the file.
+    Panic,
+    /// Fill the key in from the defaults registered with [`Preferences::set_defaults`] and log a
+    /// warning, so that the game still runs. If the defaults don't have the key either, it is
+    /// reported as for [`Message`](Self::Message).
+    Fill,
+}
+
+/// Message which is sent when a required preference is missing or has the wrong type. See
+/// [`Preferences::require`].
+#[derive(Message, Debug, Clone, PartialEq, Eq)]
+pub struct RequiredPreferenceMissing {
+    /// The name of the preferences file, without the file extension.
+    pub filename: String,
+    /// The dotted path of the key within the file, e.g. "combat.damage_multiplier".
+    pub path: String,
+}
+
+/// A key which must be present in a preferences file.
+struct RequiredKey {
+    filename: String,
+    path: String,
+    severity: RequiredSeverity,
+    is_present: Box<dyn Fn(&PreferencesFile) -> bool + Send + Sync>,
+    /// Whether the key was missing when it was last checked, so it is only reported again
+    /// after it has been restored.
+    missing: bool,
 }
 
 /// Resource which represents the place where preferences files are stored. This can be either
@@ -51,6 +209,16 @@ pub trait PreferencesStore {
 pub struct Preferences {
     store: Box<dyn PreferencesStore + Send + Sync + 'static>,
     files: HashMap<String, PreferencesFile>,
+    validators: HashMap<String, FileValidator>,
+    loaders: HashMap<String, FileLoader>,
+    required: Vec<RequiredKey>,
+    missing: Vec<RequiredPreferenceMissing>,
+    counters: PreferencesCounters,
+    sensitive: HashMap<String, Vec<(String, String)>>,
+    key_validators: HashMap<String, Vec<(String, String, KeyValidator)>>,
+    group_policies: HashMap<String, HashMap<String, GroupAutosavePolicy>>,
+    defaults: HashMap<String, PreferencesFile>,
+    schemas: HashSet<String>,
 }
 
 impl Preferences {
@@ -65,12 +233,27 @@ impl Preferences {
     ///   This is only used on desktop platforms. On web platforms, the name is ignored.
     ///
     pub fn new(app_name: &str) -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        return Self::with_store(StoreFs::new(app_name));
+        #[cfg(target_arch = "wasm32")]
+        return Self::with_store(StoreWasm::new(app_name));
+    }
+
+    /// Construct a new `Preferences` resource which uses a custom [`PreferencesStore`].
+    pub fn with_store(store: impl PreferencesStore + Send + Sync + 'static) -> Self {
         Self {
-            #[cfg(not(target_arch = "wasm32"))]
-            store: Box::new(StoreFs::new(app_name)),
-            #[cfg(target_arch = "wasm32")]
-            store: Box::new(StoreWasm::new(app_name)),
+            store: Box::new(store),
             files: HashMap::default(),
+            validators: HashMap::default(),
+            loaders: HashMap::default(),
+            required: Vec::new(),
+            missing: Vec::new(),
+            counters: PreferencesCounters::default(),
+            sensitive: HashMap::default(),
+            key_validators: HashMap::default(),
+            group_policies: HashMap::default(),
+            defaults: HashMap::default(),
+            schemas: HashSet::default(),
         }
     }
 
@@ -79,32 +262,623 @@ impl Preferences {
         self.store.is_valid()
     }
 
-    /// Save all changed `PreferenceFile`s to disk
+    /// Returns true if the named preferences file is loaded and has unsaved changes.
+    ///
+    /// # Arguments
+    /// * `filename` - The name of the preferences file, without the file extension.
+    pub fn is_file_changed(&self, filename: &str) -> bool {
+        self.files
+            .get(filename)
+            .is_some_and(|file| file.is_changed())
+    }
+
+    /// Iterate over the preferences files which are currently loaded, in no particular order.
+    /// This reflects only what is in memory; it does not look in the store.
+    pub fn loaded_files(&self) -> impl Iterator<Item = (&str, &PreferencesFile)> {
+        self.files
+            .iter()
+            .map(|(filename, file)| (filename.as_str(), file))
+    }
+
+    /// Returns the time at which the named file was last written to the store, if the store
+    /// tracks modification times. Comparing this with the time a file was loaded shows whether
+    /// it was changed by another program or browser tab since.
+    ///
+    /// # Arguments
+    /// * `filename` - The name of the preferences file, without the file extension.
+    pub fn modified(&self, filename: &str) -> Option<SystemTime> {
+        self.store.modified(filename)
+    }
+
+    /// Preview what saving a file would change, by comparing the loaded file with the version
+    /// in the store. Returns `None` if the file has not been loaded. If the file has never been
+    /// saved, every value is listed as added. The result can be formatted with `to_string` for
+    /// logging, or shown in a "review changes" dialog.
+    ///
+    /// # Arguments
+    /// * `filename` - The name of the preferences file, without the file extension.
+    pub fn diff(&self, filename: &str) -> Option<PrefsDiff> {
+        let file = self.files.get(filename)?;
+        let stored = self
+            .store
+            .reload(filename)
+            .unwrap_or_else(|| self.store.create());
+        Some(file.diff(&stored.content()))
+    }
+
+    /// Returns true if any loaded preferences file has unsaved changes.
+    pub fn any_changed(&self) -> bool {
+        self.files.values().any(|file| file.is_changed())
+    }
+
+    /// The estimated total size in bytes of all loaded preferences files. See
+    /// [`PreferencesFile::estimated_size_bytes`].
+    pub fn total_estimated_size_bytes(&self) -> usize {
+        self.files
+            .values()
+            .map(|file| file.estimated_size_bytes())
+            .sum()
+    }
+
+    /// Register a validator which is run before every save of the named preferences file. If the
+    /// validator returns an error, the file is not saved and remains marked as changed.
+    ///
+    /// # Arguments
+    /// * `filename` - The name of the preferences file, without the file extension.
+    /// * `validator` - a function which returns an error message if the file is invalid.
+    pub fn set_validator(
+        &mut self,
+        filename: &str,
+        validator: impl Fn(&PreferencesFile) -> Result<(), String> + Send + Sync + 'static,
+    ) {
+        self.validators
+            .insert(filename.to_owned(), Box::new(validator));
+    }
+
+    /// Register a function which is run on the named preferences file immediately after it is
+    /// loaded from the store, before it is returned to callers. This is useful for one-time
+    /// fixups such as converting old values, adding missing keys, or removing obsolete ones.
+    ///
+    /// # Arguments
+    /// * `filename` - The name of the preferences file, without the file extension.
+    /// * `loader` - a function which modifies the loaded file.
+    pub fn set_loader(
+        &mut self,
+        filename: &str,
+        loader: impl Fn(&mut PreferencesFile) + Send + Sync + 'static,
+    ) {
+        self.loaders.insert(filename.to_owned(), Box::new(loader));
+    }
+
+    /// Load a file from the store and run its loader, if there is one.
+    fn load(&mut self, filename: &str) -> Option<PreferencesFile> {
+        let mut file = self.store.load(filename)?;
+        self.loaded(filename, &mut file);
+        Some(file)
+    }
+
+    /// Count a file which has just been read from the store, and run its loader.
+    fn loaded(&self, filename: &str, file: &mut PreferencesFile) {
+        PreferencesCounters::add(&self.counters.loads, 1);
+        if let Some(loader) = self.loaders.get(filename) {
+            loader(file);
+        }
+    }
+
+    /// Point the store at the preferences of a different app name, such as when the user switches
+    /// to another profile, and drop all loaded files so that they are read from the new location
+    /// when next used. Validators, loaders and autosave policies are kept.
+    ///
+    /// Unsaved changes are dropped; call [`flush_all`](Self::flush_all) first to keep them.
+    pub fn set_app_name(&mut self, app_name: &str) {
+        self.store.set_app_name(app_name);
+        self.files.clear();
+    }
+
+    /// Reload the files which the store reports were changed by another program. Files which
+    /// have not been loaded yet are left alone, since they will be read fresh when first used. If
+    /// a changed file can't be read, such as when it is only partly written, the copy in memory
+    /// is kept and the file is left on disk.
+    ///
+    /// Files with unsaved changes are not reloaded, so that the user's edits aren't lost; a
+    /// warning is logged, and the copy in memory replaces the external changes when it is next
+    /// saved. Returns the names of the other changed files.
+    pub fn reload_external_changes(&mut self) -> Vec<String> {
+        let mut changed = self.store.take_external_changes();
+        changed.sort();
+        changed.dedup();
+        changed.retain(|filename| {
+            let dirty = self
+                .files
+                .get(filename)
+                .is_some_and(|file| file.is_changed());
+            if dirty {
+                warn!(
+                    "Preferences file {} was changed by another program, keeping unsaved changes",
+                    filename
+                );
+            }
+            !dirty
+        });
+        for filename in &changed {
+            if !self.files.contains_key(filename) {
+                continue;
+            }
+            if let Some(mut file) = self.store.reload(filename) {
+                self.loaded(filename, &mut file);
+                self.insert_file(filename, file);
+            }
+        }
+        changed
+    }
+
+    /// Returns the files which could not be saved in another thread since the last call, such
+    /// as by [`save_async`](Self::save_async), and marks them as changed again so that they are
+    /// saved next time. Saves which have finished are added to the activity counters.
+    /// [`AutosavePrefsPlugin`](crate::AutosavePrefsPlugin) calls this each frame and sends a
+    /// [`PreferencesSaveFailed`] message for each failed file.
+    pub fn take_save_failures(&self) -> Vec<PreferencesSaveFailed> {
+        for (_, bytes) in self.store.take_completed_saves() {
+            PreferencesCounters::add(&self.counters.saves, 1);
+            if self.counters.measure_bytes.load(Ordering::Relaxed) {
+                PreferencesCounters::add(&self.counters.bytes_written, bytes as u64);
+            }
+        }
+        let failed = self.store.take_save_failures();
+        PreferencesCounters::add(&self.counters.errors, failed.len() as u64);
+        for failure in &failed {
+            if let Some(file) = self.files.get(&failure.filename) {
+                file.set_changed();
+            }
+        }
+        failed
+    }
+
+    /// Returns the files which could not be parsed when they were loaded since the last call.
+    /// Each one was backed up by the store and replaced by an empty file.
+    /// [`AutosavePrefsPlugin`](crate::AutosavePrefsPlugin) calls this each frame and sends a
+    /// [`PreferencesFileCorrupt`] message for each one.
+    pub fn take_corrupt_files(&self) -> Vec<PreferencesFileCorrupt> {
+        let corrupt = self.store.take_corrupt_files();
+        PreferencesCounters::add(&self.counters.errors, corrupt.len() as u64);
+        corrupt
+    }
+
+    /// Load several preferences files in parallel on the [`IoTaskPool`], and wait for all of
+    /// them. This is useful at startup, to avoid loading files one at a time later on. Files
+    /// which are already loaded are skipped, and files which do not exist are not created.
+    ///
+    /// # Arguments
+    /// * `filenames` - The names of the preferences files, without the file extension.
+    pub fn preload(&mut self, filenames: &[&str]) {
+        let store = &self.store;
+        let loaded = IoTaskPool::get().scope(|scope| {
+            for filename in filenames {
+                if self.files.contains_key(*filename) {
+                    continue;
+                }
+                let filename = filename.to_string();
+                scope.spawn(async move {
+                    let file = store.load_async(&filename);
+                    (filename, file)
+                });
+            }
+        });
+        for (filename, file) in loaded {
+            // The same name may have been listed more than once.
+            if self.files.contains_key(&filename) {
+                continue;
+            }
+            if let Some(mut file) = file {
+                self.loaded(&filename, &mut file);
+                self.insert_file(&filename, file);
+            }
+        }
+    }
+
+    /// Declare that a key must be present in a preferences file, and deserializable as `D`. The
+    /// check runs each time the file is loaded, reloaded or created; if the file is already
+    /// loaded, it runs immediately. If the contents of the file are replaced in bulk, such as by
+    /// [`set_all`](PreferencesFile::set_all) or [`apply_diff`](PreferencesFile::apply_diff), it
+    /// runs again when missing keys are next reported, which
+    /// [`AutosavePrefsPlugin`](crate::AutosavePrefsPlugin) does each frame.
+    ///
+    /// # Arguments
+    /// * `filename` - The name of the preferences file, without the file extension.
+    /// * `path` - The dotted path of the key, e.g. "combat.damage_multiplier". The last segment
+    ///   is the key name, the preceding segments are nested group names. A single segment names
+    ///   a key which is not in any group.
+    /// * `severity` - How to report the key if it is missing.
+    pub fn require<D: DeserializeOwned>(
+        &mut self,
+        filename: &str,
+        path: &str,
+        severity: RequiredSeverity,
+    ) {
+        let segments: Vec<String> = path.split('.').map(str::to_owned).collect();
+        self.required.push(RequiredKey {
+            filename: filename.to_owned(),
+            path: path.to_owned(),
+            severity,
+            is_present: Box::new(move |file| {
+                let Some((key, groups)) = segments.split_last() else {
+                    return false;
+                };
+                let Some((first, rest)) = groups.split_first() else {
+                    return file.root().get::<D>(key).is_some();
+                };
+                let mut group = file.get_group(first);
+                for name in rest {
+                    group = group.and_then(|g| g.get_group(name));
+                }
+                group.and_then(|g| g.get::<D>(key)).is_some()
+            }),
+            missing: false,
+        });
+        if self.files.contains_key(filename) {
+            self.check_required(filename, true);
+        }
+    }
+
+    /// Register the shipped defaults for a preferences file, such as a file bundled with the
+    /// game. Required keys declared with [`RequiredSeverity::Fill`] which are missing from the
+    /// file are filled in from these defaults.
+    ///
+    /// # Arguments
+    /// * `filename` - The name of the preferences file, without the file extension.
+    /// * `defaults` - The default contents of the file.
+    pub fn set_defaults(&mut self, filename: &str, defaults: PreferencesFile) {
+        self.defaults.insert(filename.to_owned(), defaults);
+    }
+
+    /// Cache a file which has just been loaded or created, marking its sensitive keys and
+    /// checking its required keys.
+    fn insert_file(&mut self, filename: &str, mut file: PreferencesFile) {
+        if let Some(keys) = self.sensitive.get(filename) {
+            for (group, key) in keys {
+                file.mark_sensitive(group, key);
+            }
+        }
+        if let Some(validators) = self.key_validators.get(filename) {
+            for (group, key, validator) in validators {
+                let validator = validator.clone();
+                file.register_validator(group, key, move |value| validator(value));
+            }
+        }
+        file.take_replaced();
+        self.files.insert(filename.to_owned(), file);
+        self.check_required(filename, false);
+    }
+
+    /// Check the required keys for a file. When a file has just been loaded or created, every
+    /// missing key is reported; otherwise, if `only_new` is true, only keys which have gone
+    /// missing since the last check are reported.
+    fn check_required(&mut self, filename: &str, only_new: bool) {
+        let Some(file) = self.files.get_mut(filename) else {
+            return;
+        };
+        let defaults = self.defaults.get(filename);
+        let mut must_panic = false;
+        let mut missing = Vec::new();
+        for required in self.required.iter_mut() {
+            if required.filename != filename {
+                continue;
+            }
+            let mut is_present = (required.is_present)(file);
+            if !is_present && required.severity == RequiredSeverity::Fill {
+                let segments: Vec<&str> = required.path.split('.').collect();
+                if let Some(value) =
+                    defaults.and_then(|d| d.get_path_segments::<PrefsValue>(&segments))
+                {
+                    is_present = file.set_path_segments(&segments, value).is_ok()
+                        && (required.is_present)(file);
+                }
+                if is_present {
+                    warn!(
+                        "Required preference {} is missing from {}, using the default",
+                        required.path, filename
+                    );
+                }
+            }
+            let was_missing = std::mem::replace(&mut required.missing, !is_present);
+            if !required.missing || (only_new && was_missing) {
+                continue;
+            }
+            error!(
+                "Required preference {} is missing from {}",
+                required.path, filename
+            );
+            match required.severity {
+                RequiredSeverity::Log => {}
+                RequiredSeverity::Message | RequiredSeverity::Fill => {
+                    self.missing.push(RequiredPreferenceMissing {
+                        filename: filename.to_owned(),
+                        path: required.path.clone(),
+                    })
+                }
+                RequiredSeverity::Panic => must_panic = true,
+            }
+            missing.push(required.path.as_str());
+        }
+        if must_panic {
+            panic!(
+                "Required preferences missing from {}: {}",
+                filename,
+                missing.join(", ")
+            );
+        }
+    }
+
+    /// Mark a key as sensitive, such as an auth token, so that its value is shown as
+    /// `"<redacted>"` when the file is printed with `Debug`. This applies to the file now if it
+    /// is already loaded, and whenever it is loaded or created later.
+    ///
+    /// # Arguments
+    /// * `filename` - The name of the preferences file, without the file extension.
+    /// * `group` - The name of the top-level group which contains the key.
+    /// * `key` - The name of the key.
+    pub fn mark_sensitive(&mut self, filename: &str, group: &str, key: &str) {
+        self.sensitive
+            .entry(filename.to_owned())
+            .or_default()
+            .push((group.to_owned(), key.to_owned()));
+        if let Some(file) = self.files.get_mut(filename) {
+            file.mark_sensitive(group, key);
+        }
+    }
+
+    /// Register a validator for a key, which is consulted whenever the key is set. The validator
+    /// may correct the value in place, such as by clamping it to a range, in which case the
+    /// corrected value is stored; if it returns an error, the value is not stored. Validators
+    /// are also run once when the file is loaded, so that hand-edited files are sanitized; the
+    /// file is marked as changed if any value was corrected or removed. This applies to the file
+    /// now if it is already loaded, and whenever it is loaded or created later.
+    ///
+    /// # Arguments
+    /// * `filename` - The name of the preferences file, without the file extension.
+    /// * `group` - The name of the top-level group which contains the key.
+    /// * `key` - The name of the key.
+    /// * `validator` - a function which corrects the value, or returns an error message if the
+    ///   value is invalid.
+    pub fn register_validator(
+        &mut self,
+        filename: &str,
+        group: &str,
+        key: &str,
+        validator: impl Fn(&mut PrefsValue) -> Result<(), String> + Send + Sync + 'static,
+    ) {
+        let validator: KeyValidator = Arc::new(validator);
+        self.key_validators
+            .entry(filename.to_owned())
+            .or_default()
+            .push((group.to_owned(), key.to_owned(), validator.clone()));
+        if let Some(file) = self.files.get_mut(filename) {
+            file.register_validator(group, key, move |value| validator(value));
+        }
+    }
+
+    /// Check the required keys of every loaded file whose contents were replaced in bulk since
+    /// it was last checked, reporting every key which is missing after the replacement.
+    fn recheck_required(&mut self) {
+        let mut filenames: Vec<String> = self
+            .required
+            .iter()
+            .filter(|required| {
+                self.files
+                    .get(&required.filename)
+                    .is_some_and(|file| file.is_replaced())
+            })
+            .map(|required| required.filename.clone())
+            .collect();
+        filenames.sort();
+        filenames.dedup();
+        for filename in filenames {
+            self.files[&filename].take_replaced();
+            self.check_required(&filename, false);
+        }
+    }
+
+    /// Returns true if there are [`RequiredPreferenceMissing`] messages waiting to be sent, or
+    /// if a file with required keys has had its contents replaced in bulk.
+    pub(crate) fn has_missing(&self) -> bool {
+        !self.missing.is_empty()
+            || self.required.iter().any(|required| {
+                self.files
+                    .get(&required.filename)
+                    .is_some_and(|file| file.is_replaced())
+            })
+    }
+
+    /// Remove and return the pending [`RequiredPreferenceMissing`] messages, after checking the
+    /// required keys of files whose contents were replaced in bulk.
+    pub(crate) fn drain_missing(&mut self) -> Vec<RequiredPreferenceMissing> {
+        self.recheck_required();
+        std::mem::take(&mut self.missing)
+    }
+
+    /// Activity counters, reported by [`PreferencesDiagnosticsPlugin`].
+    ///
+    /// [`PreferencesDiagnosticsPlugin`]: crate::PreferencesDiagnosticsPlugin
+    pub(crate) fn counters(&self) -> &PreferencesCounters {
+        &self.counters
+    }
+
+    /// Record a successful save of `file` in the activity counters.
+    fn count_save(&self, file: &PreferencesFile) {
+        PreferencesCounters::add(&self.counters.saves, 1);
+        if self.counters.measure_bytes.load(Ordering::Relaxed) {
+            if let Ok(encoded) = file.encode() {
+                PreferencesCounters::add(&self.counters.bytes_written, encoded.len() as u64);
+            }
+        }
+    }
+
+    /// Run the validator for `filename`, if there is one.
+    fn validate(
+        &self,
+        filename: &str,
+        file: &PreferencesFile,
+    ) -> Result<(), PreferencesSaveFailed> {
+        match self.validators.get(filename).map(|v| v(file)) {
+            Some(Err(reason)) => {
+                warn!(
+                    "Not saving invalid preferences file {}: {}",
+                    filename, reason
+                );
+                Err(PreferencesSaveFailed {
+                    filename: filename.to_owned(),
+                    reason,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Save all changed `PreferenceFile`s to disk. Returns the list of files which could not
+    /// be saved.
     ///
     /// # Arguments
     /// * `force` - If true, all preferences will be saved, even if they have not changed.
-    pub fn save(&self, force: bool) {
+    pub fn save(&self, force: bool) -> Vec<PreferencesSaveFailed> {
+        self.save_where(|_, file| force || file.is_changed())
+    }
+
+    /// Save a single `PreferenceFile` to disk, if it is loaded and has changed, leaving other
+    /// changed files for later. Returns the file if it could not be saved.
+    ///
+    /// # Arguments
+    /// * `filename` - The name of the preferences file, without the file extension.
+    /// * `force` - If true, the file will be saved even if it has not changed.
+    pub fn save_specific(&self, filename: &str, force: bool) -> Vec<PreferencesSaveFailed> {
+        self.save_where(|name, file| name == filename && (force || file.is_changed()))
+    }
+
+    /// Save the files for which `predicate` returns true.
+    fn save_where(
+        &self,
+        predicate: impl Fn(&str, &PreferencesFile) -> bool,
+    ) -> Vec<PreferencesSaveFailed> {
+        let mut failed = Vec::new();
         for (filename, file) in self.files.iter() {
-            if file.is_changed() || force {
+            if predicate(filename, file) {
+                if let Err(e) = self.validate(filename, file) {
+                    PreferencesCounters::add(&self.counters.errors, 1);
+                    failed.push(e);
+                    continue;
+                }
                 info!("Saving preferences file: {}", filename);
+                if let Err(e) = self.store.save(filename, file) {
+                    error!("Could not save preferences file {}: {}", filename, e);
+                    PreferencesCounters::add(&self.counters.errors, 1);
+                    failed.push(PreferencesSaveFailed {
+                        filename: filename.clone(),
+                        reason: e.to_string(),
+                    });
+                    continue;
+                }
                 file.clear_changed();
-                self.store.save(filename, file);
+                self.count_save(file);
             }
         }
+        failed
+    }
+
+    /// Save all changed `PreferenceFile`s to disk, and wait until they have been written. This
+    /// is useful before a risky operation, such as a self-update, to be sure that nothing is
+    /// lost. Every changed file is attempted, even if an earlier one fails; the error lists all
+    /// of the files which could not be saved.
+    pub fn flush_all(&self) -> Result<(), PreferencesError> {
+        let failed = self.save(false);
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(PreferencesError::SaveFailed(failed))
+        }
     }
 
-    /// Save all changed `PreferenceFile`s to disk, in another thread.
+    /// Save all changed `PreferenceFile`s to disk, in another thread. Returns the list of files
+    /// which could not be saved.
     ///
     /// # Arguments
     /// * `force` - If true, all preferences will be saved, even if they have not changed.
-    pub fn save_async(&self, force: bool) {
+    pub fn save_async(&self, force: bool) -> Vec<PreferencesSaveFailed> {
+        self.save_async_where(|_, file| force || file.is_changed())
+    }
+
+    /// Save a single `PreferenceFile` to disk in another thread, if it is loaded and has
+    /// changed. Returns the file if it could not be saved.
+    ///
+    /// # Arguments
+    /// * `filename` - The name of the preferences file, without the file extension.
+    /// * `force` - If true, the file will be saved even if it has not changed.
+    pub fn save_specific_async(&self, filename: &str, force: bool) -> Vec<PreferencesSaveFailed> {
+        self.save_async_where(|name, file| name == filename && (force || file.is_changed()))
+    }
+
+    /// Set how changes to a top-level group are autosaved by
+    /// [`AutosavePrefsPlugin`](crate::AutosavePrefsPlugin). Groups without a policy are saved
+    /// when the timer started by [`StartAutosaveTimer`](crate::StartAutosaveTimer) goes off.
+    ///
+    /// # Arguments
+    /// * `filename` - The name of the preferences file, without the file extension.
+    /// * `group` - The name of a top-level group within the file.
+    /// * `policy` - When changes to the group should be saved.
+    pub fn set_group_policy(&mut self, filename: &str, group: &str, policy: GroupAutosavePolicy) {
+        self.group_policies
+            .entry(filename.to_owned())
+            .or_default()
+            .insert(group.to_owned(), policy);
+    }
+
+    /// The autosave policy set for a top-level group with [`Preferences::set_group_policy`], if
+    /// any.
+    pub fn group_policy(&self, filename: &str, group: &str) -> Option<GroupAutosavePolicy> {
+        self.group_policies.get(filename)?.get(group).copied()
+    }
+
+    /// Returns true if a file has changes which autosave should write: changes to keys outside
+    /// of any group, or to a group whose policy is not [`GroupAutosavePolicy::Manual`].
+    fn has_autosave_changes(&self, filename: &str, file: &PreferencesFile) -> bool {
+        file.is_ungrouped_changed()
+            || file.changed_groups().iter().any(|group| {
+                self.group_policy(filename, group) != Some(GroupAutosavePolicy::Manual)
+            })
+    }
+
+    /// Autosave files in another thread. Only files with changes outside of groups with the
+    /// [`GroupAutosavePolicy::Manual`] policy are saved. Returns the list of files which could
+    /// not be saved.
+    ///
+    /// # Arguments
+    /// * `filenames` - The files to consider, or `None` for all loaded files.
+    pub(crate) fn autosave(&self, filenames: Option<&[String]>) -> Vec<PreferencesSaveFailed> {
+        self.save_async_where(|filename, file| {
+            filenames.is_none_or(|filenames| filenames.iter().any(|f| f == filename))
+                && self.has_autosave_changes(filename, file)
+        })
+    }
+
+    /// Save the files for which `predicate` returns true in another thread.
+    fn save_async_where(
+        &self,
+        predicate: impl Fn(&str, &PreferencesFile) -> bool,
+    ) -> Vec<PreferencesSaveFailed> {
+        let mut failed = Vec::new();
         for (filename, file) in self.files.iter() {
-            if file.is_changed() || force {
+            if predicate(filename, file) {
+                if let Err(e) = self.validate(filename, file) {
+                    PreferencesCounters::add(&self.counters.errors, 1);
+                    failed.push(e);
+                    continue;
+                }
                 info!("Saving preferences file (async): {}", filename);
+                // The save is counted, or the file marked as changed again, when the store
+                // reports that it has finished.
                 file.clear_changed();
                 self.store.save_async(filename, file.content());
             }
         }
+        failed
     }
 
     /// Load and cache a [`PreferencesFile`]. If the file is already loaded, it will be returned
@@ -118,14 +892,26 @@ impl Preferences {
     /// * `filename` - The name of the preferences file, without the file extension.
     pub fn get<'a>(&'a mut self, filename: &str) -> Option<&'a PreferencesFile> {
         if !self.files.contains_key(filename) {
-            if let Some(table) = self.store.load(filename) {
-                self.files.insert(filename.to_owned(), table);
+            if let Some(file) = self.load(filename) {
+                self.insert_file(filename, file);
             };
         }
 
         self.files.get(filename)
     }
 
+    /// Returns a [`PreferencesFile`] only if it is already loaded. Unlike [`get`](Self::get),
+    /// this never loads the file from the store, so it only needs `&self`, and systems which
+    /// just read preferences can take `Res<Preferences>` rather than `ResMut`. Use
+    /// [`preload`](Self::preload) or [`PreferencesPlugin`](crate::PreferencesPlugin) to make
+    /// sure the file is loaded first.
+    ///
+    /// # Arguments
+    /// * `filename` - The name of the preferences file, without the file extension.
+    pub fn peek(&self, filename: &str) -> Option<&PreferencesFile> {
+        self.files.get(filename)
+    }
+
     /// Load and cache a [`PreferencesFile`], or create it if it does not exist. If the file is
     /// already loaded, it will be returned immediately. If the file exists but is not loaded, it
     /// will be loaded and returned. If the file does not exist, a new [`PreferencesFile`] will be
@@ -138,13 +924,850 @@ impl Preferences {
     /// * `filename` - The name of the preferences file, without the file extension.
     pub fn get_mut<'a>(&'a mut self, filename: &str) -> Option<&'a mut PreferencesFile> {
         if !self.files.contains_key(filename) {
-            if let Some(table) = self.store.load(filename) {
-                self.files.insert(filename.to_owned(), table);
-            } else {
-                self.files.insert(filename.to_owned(), self.store.create());
+            let file = self.load(filename).unwrap_or_else(|| self.store.create());
+            self.insert_file(filename, file);
+        }
+
+        self.files.get_mut(filename)
+    }
+
+    /// Load and cache the [`PreferencesFile`] described by the schema `S`, or create it if it
+    /// does not exist. A newly created file is filled in with
+    /// [`PreferencesSchema::apply_defaults`] before required keys are checked.
+    ///
+    /// The first call also registers [`PreferencesSchema::validate`] as the file's validator,
+    /// unless one was already set with [`Preferences::set_validator`], so invalid files are not
+    /// saved. If the file was already loaded without the schema, the defaults it lacks are merged
+    /// in and its required keys are checked.
+    pub fn get_schema_mut<S: PreferencesSchema>(&mut self) -> Option<&mut PreferencesFile> {
+        let filename = S::filename();
+        if self.schemas.insert(filename.to_owned()) {
+            self.validators
+                .entry(filename.to_owned())
+                .or_insert_with(|| {
+                    Box::new(|file: &PreferencesFile| {
+                        S::validate(&file.root()).map_err(|errors| errors.join("; "))
+                    })
+                });
+            if let Some(file) = self.files.get_mut(filename) {
+                let mut defaults = self.store.create();
+                S::apply_defaults(&mut defaults.root_mut());
+                file.merge_from(&defaults.content(), false);
+                file.take_replaced();
+                self.check_required(filename, false);
             }
         }
+        if !self.files.contains_key(filename) {
+            let file = self.load(filename).unwrap_or_else(|| {
+                let mut file = self.store.create();
+                S::apply_defaults(&mut file.root_mut());
+                file
+            });
+            self.insert_file(filename, file);
+        }
 
         self.files.get_mut(filename)
     }
 }
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    /// Store which serves preferences files from memory and discards saves.
+    #[derive(Default)]
+    struct TestStore(HashMap<String, String>);
+
+    impl TestStore {
+        fn with_file(mut self, filename: &str, toml_str: &str) -> Self {
+            self.0.insert(filename.to_owned(), toml_str.to_owned());
+            self
+        }
+    }
+
+    impl PreferencesStore for TestStore {
+        fn is_valid(&self) -> bool {
+            true
+        }
+
+        fn create(&self) -> PreferencesFile {
+            PreferencesFile::new()
+        }
+
+        fn load(&mut self, filename: &str) -> Option<PreferencesFile> {
+            self.load_async(filename)
+        }
+
+        fn load_async(&self, filename: &str) -> Option<PreferencesFile> {
+            self.0.get(filename).map(|s| s.parse().unwrap())
+        }
+
+        fn save(&self, _filename: &str, _file: &PreferencesFile) -> Result<(), PreferencesError> {
+            Ok(())
+        }
+
+        fn save_async(&self, _filename: &str, _file: PreferencesFileContent) {}
+    }
+
+    fn tuning_prefs() -> Preferences {
+        Preferences::with_store(TestStore::default().with_file(
+            "tuning",
+            "[combat]\ndamage_multiplier = 1.5\nlabel = \"normal\"\n",
+        ))
+    }
+
+    #[test]
+    fn test_require_present() {
+        let mut prefs = tuning_prefs();
+        prefs.require::<f32>(
+            "tuning",
+            "combat.damage_multiplier",
+            RequiredSeverity::Panic,
+        );
+        assert!(prefs.get("tuning").is_some());
+        assert!(!prefs.has_missing());
+    }
+
+    #[test]
+    fn test_require_log() {
+        let mut prefs = tuning_prefs();
+        prefs.require::<f32>("tuning", "combat.armor", RequiredSeverity::Log);
+        assert!(prefs.get("tuning").is_some());
+        assert!(!prefs.has_missing());
+    }
+
+    #[test]
+    fn test_require_message() {
+        let mut prefs = tuning_prefs();
+        prefs.require::<f32>("tuning", "combat.armor", RequiredSeverity::Message);
+        prefs.require::<f32>("tuning", "combat.label", RequiredSeverity::Message);
+        prefs.require::<f32>("other", "combat.armor", RequiredSeverity::Message);
+        assert!(prefs.get("tuning").is_some());
+        assert_eq!(
+            prefs.drain_missing(),
+            vec![
+                RequiredPreferenceMissing {
+                    filename: "tuning".to_owned(),
+                    path: "combat.armor".to_owned(),
+                },
+                RequiredPreferenceMissing {
+                    filename: "tuning".to_owned(),
+                    path: "combat.label".to_owned(),
+                },
+            ]
+        );
+        assert!(!prefs.has_missing());
+    }
+
+    #[test]
+    fn test_require_checks_loaded_file() {
+        let mut prefs = tuning_prefs();
+        assert!(prefs.get_mut("new").is_some());
+        prefs.require::<bool>("new", "ui.enabled", RequiredSeverity::Message);
+        assert!(prefs.has_missing());
+    }
+
+    #[test]
+    fn test_require_ungrouped_key() {
+        let mut prefs = Preferences::with_store(
+            TestStore::default().with_file("tuning", "version = 3\n[combat]\narmor = 2\n"),
+        );
+        prefs.require::<i32>("tuning", "version", RequiredSeverity::Message);
+        prefs.require::<i32>("tuning", "armor", RequiredSeverity::Message);
+        assert!(prefs.get("tuning").is_some());
+        assert_eq!(
+            prefs.drain_missing(),
+            vec![RequiredPreferenceMissing {
+                filename: "tuning".to_owned(),
+                path: "armor".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_require_rechecks_replaced_file() {
+        let mut prefs = tuning_prefs();
+        prefs.require::<f32>(
+            "tuning",
+            "combat.damage_multiplier",
+            RequiredSeverity::Message,
+        );
+        assert!(prefs.get_mut("tuning").is_some());
+        assert!(!prefs.has_missing());
+
+        let file = prefs.get_mut("tuning").unwrap();
+        file.set_all(&toml::toml! { [combat] label = "hard" })
+            .unwrap();
+        assert!(prefs.has_missing());
+        assert_eq!(
+            prefs.drain_missing(),
+            vec![RequiredPreferenceMissing {
+                filename: "tuning".to_owned(),
+                path: "combat.damage_multiplier".to_owned(),
+            }]
+        );
+
+        // Setting single keys doesn't cause a check; replacing a group does.
+        assert!(prefs.drain_missing().is_empty());
+        let file = prefs.get_mut("tuning").unwrap();
+        file.get_group_mut("combat").unwrap().set("label", "easy");
+        assert!(!prefs.has_missing());
+        let file = prefs.get_mut("tuning").unwrap();
+        file.get_group_mut("combat")
+            .unwrap()
+            .replace_from_value(PrefsValue::Table(Default::default()));
+        assert!(prefs.has_missing());
+        assert_eq!(prefs.drain_missing().len(), 1);
+        assert!(!prefs.has_missing());
+    }
+
+    #[test]
+    fn test_require_fill_from_defaults() {
+        let mut prefs = tuning_prefs();
+        let mut defaults = PreferencesFile::new();
+        defaults.get_group_mut("combat").unwrap().set("armor", 2.5);
+        prefs.set_defaults("tuning", defaults);
+        prefs.require::<f32>("tuning", "combat.armor", RequiredSeverity::Fill);
+        prefs.require::<f32>("tuning", "combat.speed", RequiredSeverity::Fill);
+
+        // The key in the defaults is filled in; the other is reported.
+        let file = prefs.get("tuning").unwrap();
+        assert_eq!(file.get_path::<f32>("combat.armor"), Some(2.5));
+        assert!(file.get_path::<f32>("combat.speed").is_none());
+        assert_eq!(
+            prefs.drain_missing(),
+            vec![RequiredPreferenceMissing {
+                filename: "tuning".to_owned(),
+                path: "combat.speed".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "combat.armor, combat.label")]
+    fn test_require_panic() {
+        let mut prefs = tuning_prefs();
+        prefs.require::<f32>("tuning", "combat.armor", RequiredSeverity::Panic);
+        prefs.require::<f32>("tuning", "combat.label", RequiredSeverity::Log);
+        prefs.get("tuning");
+    }
+
+    #[test]
+    fn test_loader() {
+        let mut prefs = tuning_prefs();
+        prefs.set_loader("tuning", |file| {
+            let mut combat = file.get_group_mut("combat").unwrap();
+            combat.remove("label");
+            combat.set("armor", 2.0);
+        });
+        prefs.require::<f32>("tuning", "combat.armor", RequiredSeverity::Panic);
+        let combat = prefs.get("tuning").unwrap().get_group("combat").unwrap();
+        assert!(combat.get::<String>("label").is_none());
+        assert_eq!(combat.get::<f32>("armor"), Some(2.0));
+    }
+
+    struct CombatSchema;
+
+    impl PreferencesSchema for CombatSchema {
+        fn filename() -> &'static str {
+            "combat"
+        }
+
+        fn apply_defaults(file: &mut PreferencesGroupMut) {
+            file.get_group_mut("combat")
+                .unwrap()
+                .set("damage_multiplier", 1.0);
+        }
+
+        fn validate(file: &PreferencesGroup) -> Result<(), Vec<String>> {
+            match file
+                .get_group("combat")
+                .and_then(|g| g.get::<f32>("damage_multiplier"))
+            {
+                Some(m) if m > 0.0 => Ok(()),
+                _ => Err(vec!["damage_multiplier must be positive".to_owned()]),
+            }
+        }
+    }
+
+    #[test]
+    fn test_schema() {
+        let mut prefs = tuning_prefs();
+        let file = prefs.get_schema_mut::<CombatSchema>().unwrap();
+        assert_eq!(
+            file.get_group("combat")
+                .unwrap()
+                .get::<f32>("damage_multiplier"),
+            Some(1.0)
+        );
+        assert!(prefs.save(false).is_empty());
+
+        prefs
+            .get_schema_mut::<CombatSchema>()
+            .unwrap()
+            .get_group_mut("combat")
+            .unwrap()
+            .set("damage_multiplier", -1.0);
+        let failed = prefs.save(false);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].filename, "combat");
+    }
+
+    #[test]
+    fn test_schema_after_load() {
+        let mut prefs = tuning_prefs();
+        prefs.set_validator("combat", |_| Err("locked".to_owned()));
+        prefs.get_mut("combat").unwrap().set_changed();
+
+        // Defaults are merged into the loaded file, and the user's validator is kept.
+        let file = prefs.get_schema_mut::<CombatSchema>().unwrap();
+        assert_eq!(
+            file.get_group("combat")
+                .unwrap()
+                .get::<f32>("damage_multiplier"),
+            Some(1.0)
+        );
+        let failed = prefs.save(false);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].reason, "locked");
+
+        // Later calls don't apply the defaults again.
+        prefs
+            .get_schema_mut::<CombatSchema>()
+            .unwrap()
+            .get_group_mut("combat")
+            .unwrap()
+            .remove("damage_multiplier");
+        let file = prefs.get_schema_mut::<CombatSchema>().unwrap();
+        assert!(file
+            .get_group("combat")
+            .unwrap()
+            .get::<f32>("damage_multiplier")
+            .is_none());
+    }
+
+    #[test]
+    fn test_counters() {
+        let mut prefs = tuning_prefs();
+        prefs
+            .counters()
+            .measure_bytes
+            .store(true, Ordering::Relaxed);
+        prefs.get_schema_mut::<CombatSchema>();
+        prefs.get("tuning");
+        prefs.get("missing");
+        assert_eq!(prefs.counters().loads.load(Ordering::Relaxed), 1);
+
+        prefs.save(true);
+        prefs
+            .get_mut("combat")
+            .unwrap()
+            .get_group_mut("combat")
+            .unwrap()
+            .set("damage_multiplier", 0.0);
+        prefs.save(false);
+        let counters = prefs.counters();
+        assert_eq!(counters.saves.load(Ordering::Relaxed), 2);
+        assert_eq!(counters.errors.load(Ordering::Relaxed), 1);
+        assert!(counters.bytes_written.load(Ordering::Relaxed) > 0);
+    }
+
+    #[test]
+    fn test_is_file_changed() {
+        let mut prefs = tuning_prefs();
+        prefs.get("tuning");
+        assert!(!prefs.is_file_changed("tuning"));
+        assert!(!prefs.is_file_changed("missing"));
+        assert!(!prefs.any_changed());
+
+        prefs
+            .get_mut("tuning")
+            .unwrap()
+            .get_group_mut("combat")
+            .unwrap()
+            .set("label", "hard");
+        assert!(prefs.is_file_changed("tuning"));
+        assert!(prefs.any_changed());
+
+        prefs.save(false);
+        assert!(!prefs.any_changed());
+    }
+
+    #[test]
+    fn test_loaded_files() {
+        let mut prefs = tuning_prefs();
+        assert_eq!(prefs.loaded_files().count(), 0);
+
+        prefs.get("tuning");
+        prefs.get("missing");
+        prefs.get_mut("audio").unwrap().set_changed();
+        let mut loaded: Vec<(&str, bool)> = prefs
+            .loaded_files()
+            .map(|(filename, file)| (filename, file.is_changed()))
+            .collect();
+        loaded.sort_unstable();
+        assert_eq!(loaded, [("audio", true), ("tuning", false)]);
+    }
+
+    #[test]
+    fn test_mark_sensitive() {
+        let mut prefs = Preferences::with_store(
+            TestStore::default()
+                .with_file("account", "[auth]\nuser = \"me\"\ntoken = \"s3cret\"\n"),
+        );
+        prefs.mark_sensitive("account", "auth", "token");
+        let output = format!("{:?}", prefs.get("account").unwrap());
+        assert!(output.contains("me"));
+        assert!(output.contains("<redacted>"));
+        assert!(!output.contains("s3cret"));
+
+        let file = prefs.get_mut("session").unwrap();
+        file.get_group_mut("auth").unwrap().set("token", "abc123");
+        prefs.mark_sensitive("session", "auth", "token");
+        let output = format!("{:?}", prefs.get("session").unwrap());
+        assert!(!output.contains("abc123"));
+    }
+
+    /// Store which fails every save.
+    struct FailingStore;
+
+    impl PreferencesStore for FailingStore {
+        fn is_valid(&self) -> bool {
+            true
+        }
+
+        fn create(&self) -> PreferencesFile {
+            PreferencesFile::new()
+        }
+
+        fn load(&mut self, _filename: &str) -> Option<PreferencesFile> {
+            None
+        }
+
+        fn load_async(&self, _filename: &str) -> Option<PreferencesFile> {
+            None
+        }
+
+        fn save(&self, _filename: &str, _file: &PreferencesFile) -> Result<(), PreferencesError> {
+            Err(PreferencesError::Storage("disk full".to_owned()))
+        }
+
+        fn save_async(&self, _filename: &str, _file: PreferencesFileContent) {}
+    }
+
+    #[test]
+    fn test_save_failure() {
+        let mut prefs = Preferences::with_store(FailingStore);
+        prefs
+            .get_mut("audio")
+            .unwrap()
+            .get_group_mut("volume")
+            .unwrap()
+            .set("master", 0.5);
+        let failed = prefs.save(false);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].filename, "audio");
+        assert!(failed[0].reason.contains("disk full"));
+        assert!(prefs.is_file_changed("audio"));
+    }
+
+    /// Store which records the names of the files it saves.
+    struct RecordingStore(Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl PreferencesStore for RecordingStore {
+        fn is_valid(&self) -> bool {
+            true
+        }
+
+        fn create(&self) -> PreferencesFile {
+            PreferencesFile::new()
+        }
+
+        fn load(&mut self, _filename: &str) -> Option<PreferencesFile> {
+            None
+        }
+
+        fn load_async(&self, _filename: &str) -> Option<PreferencesFile> {
+            None
+        }
+
+        fn save(&self, filename: &str, _file: &PreferencesFile) -> Result<(), PreferencesError> {
+            self.0.lock().unwrap().push(filename.to_owned());
+            Ok(())
+        }
+
+        fn save_async(&self, filename: &str, _file: PreferencesFileContent) {
+            self.0.lock().unwrap().push(filename.to_owned());
+        }
+    }
+
+    #[test]
+    fn test_set_validator() {
+        let saved = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut prefs = Preferences::with_store(RecordingStore(saved.clone()));
+        prefs.set_validator("audio", |file| {
+            match file.get_path::<f32>("mixer.master_volume") {
+                Some(volume) if !(0.0..=1.0).contains(&volume) => {
+                    Err(format!("master_volume out of range: {}", volume))
+                }
+                _ => Ok(()),
+            }
+        });
+        let set_volume = |prefs: &mut Preferences, volume: f32| {
+            prefs
+                .get_mut("audio")
+                .unwrap()
+                .get_group_mut("mixer")
+                .unwrap()
+                .set("master_volume", volume);
+        };
+
+        set_volume(&mut prefs, 1.5);
+        let failed = prefs.save(false);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].filename, "audio");
+        assert!(failed[0].reason.contains("out of range"));
+        assert!(prefs.is_file_changed("audio"));
+        assert!(saved.lock().unwrap().is_empty());
+
+        set_volume(&mut prefs, 0.5);
+        assert!(prefs.save(false).is_empty());
+        assert!(!prefs.is_file_changed("audio"));
+        assert_eq!(*saved.lock().unwrap(), vec!["audio".to_owned()]);
+    }
+
+    #[test]
+    fn test_save_immediate() {
+        use bevy::ecs::{system::Command, world::World};
+
+        use crate::SavePreferences;
+
+        let mut prefs = tuning_prefs();
+        prefs
+            .get_mut("tuning")
+            .unwrap()
+            .get_group_mut("combat")
+            .unwrap()
+            .set("damage_multiplier", 2.0);
+        let mut world = World::new();
+        world.insert_resource(prefs);
+        SavePreferences::Immediate.apply(&mut world);
+        assert!(!world.resource::<Preferences>().is_file_changed("tuning"));
+    }
+
+    #[test]
+    fn test_flush_all() {
+        let mut prefs = tuning_prefs();
+        assert_eq!(prefs.flush_all(), Ok(()));
+        prefs
+            .get_mut("tuning")
+            .unwrap()
+            .get_group_mut("physics")
+            .unwrap()
+            .set("gravity", 9.8);
+        assert_eq!(prefs.flush_all(), Ok(()));
+        assert!(!prefs.is_file_changed("tuning"));
+
+        let mut prefs = Preferences::with_store(FailingStore);
+        for filename in ["audio", "video"] {
+            prefs
+                .get_mut(filename)
+                .unwrap()
+                .get_group_mut("general")
+                .unwrap()
+                .set("enabled", true);
+        }
+        let Err(PreferencesError::SaveFailed(mut failed)) = prefs.flush_all() else {
+            panic!("expected flush_all to fail");
+        };
+        failed.sort_by(|a, b| a.filename.cmp(&b.filename));
+        assert_eq!(failed.len(), 2);
+        assert_eq!(failed[0].filename, "audio");
+        assert_eq!(failed[1].filename, "video");
+        assert!(prefs.is_file_changed("audio") && prefs.is_file_changed("video"));
+    }
+
+    #[test]
+    fn test_preload() {
+        IoTaskPool::get_or_init(Default::default);
+        let mut prefs = Preferences::with_store(
+            TestStore::default()
+                .with_file("audio", "[volume]\nmaster = 0.5\n")
+                .with_file("graphics", "[display]\nvsync = true\n"),
+        );
+        prefs.preload(&["audio", "graphics", "keybindings"]);
+        assert_eq!(prefs.counters().loads.load(Ordering::Relaxed), 2);
+        assert!(prefs.files.contains_key("audio"));
+        assert!(prefs.files.contains_key("graphics"));
+        assert!(!prefs.files.contains_key("keybindings"));
+
+        // Already loaded files are not read again.
+        prefs.preload(&["audio"]);
+        assert_eq!(prefs.counters().loads.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_total_estimated_size_bytes() {
+        let mut prefs = tuning_prefs();
+        assert_eq!(prefs.total_estimated_size_bytes(), 0);
+
+        let size = prefs.get("tuning").unwrap().estimated_size_bytes();
+        assert_eq!(size, prefs.get("tuning").unwrap().encode().unwrap().len());
+        assert_eq!(prefs.total_estimated_size_bytes(), size);
+
+        prefs
+            .get_mut("tuning")
+            .unwrap()
+            .get_group_mut("combat")
+            .unwrap()
+            .set("label", "a much longer label");
+        assert!(prefs.total_estimated_size_bytes() > size);
+    }
+
+    #[test]
+    fn test_register_validator() {
+        let mut prefs = Preferences::with_store(
+            TestStore::default().with_file("audio", "[mixer]\nmaster_volume = 1.5\n"),
+        );
+        prefs.register_validator("audio", "mixer", "master_volume", |value| {
+            match value {
+                PrefsValue::Float(f) => *f = f.clamp(0.0, 1.0),
+                _ => return Err("expected a number".to_string()),
+            }
+            Ok(())
+        });
+        prefs.register_validator("video", "display", "ui_scale", |value| match value {
+            PrefsValue::Float(f) if [1.0, 1.5, 2.0].contains(f) => Ok(()),
+            _ => Err("unsupported scale".to_string()),
+        });
+
+        // Hand-edited values are corrected on load.
+        let audio = prefs.get("audio").unwrap();
+        assert_eq!(audio.get_path::<f32>("mixer.master_volume"), Some(1.0));
+        assert!(prefs.is_file_changed("audio"));
+
+        let mut mixer = prefs
+            .get_mut("audio")
+            .unwrap()
+            .get_group_mut("mixer")
+            .unwrap();
+        mixer.set("master_volume", -0.5);
+        assert_eq!(mixer.get::<f32>("master_volume"), Some(0.0));
+        mixer.set("master_volume", "loud");
+        assert_eq!(mixer.get::<f32>("master_volume"), Some(0.0));
+
+        let mut display = prefs
+            .get_mut("video")
+            .unwrap()
+            .get_group_mut("display")
+            .unwrap();
+        assert!(matches!(
+            display.try_set("ui_scale", 3.0),
+            Err(PreferencesError::Invalid { .. })
+        ));
+        assert_eq!(display.try_set("ui_scale", 1.5), Ok(()));
+        assert_eq!(display.get::<f32>("ui_scale"), Some(1.5));
+    }
+
+    /// Store whose files contain the app name they were loaded for.
+    struct ProfileStore(String);
+
+    impl PreferencesStore for ProfileStore {
+        fn is_valid(&self) -> bool {
+            true
+        }
+
+        fn create(&self) -> PreferencesFile {
+            PreferencesFile::new()
+        }
+
+        fn load(&mut self, filename: &str) -> Option<PreferencesFile> {
+            self.load_async(filename)
+        }
+
+        fn load_async(&self, _filename: &str) -> Option<PreferencesFile> {
+            Some(
+                format!("[profile]\nname = \"{}\"\n", self.0)
+                    .parse()
+                    .unwrap(),
+            )
+        }
+
+        fn save(&self, _filename: &str, _file: &PreferencesFile) -> Result<(), PreferencesError> {
+            Ok(())
+        }
+
+        fn save_async(&self, _filename: &str, _file: PreferencesFileContent) {}
+
+        fn set_app_name(&mut self, app_name: &str) {
+            self.0 = app_name.to_owned();
+        }
+    }
+
+    #[test]
+    fn test_set_app_name() {
+        let mut prefs = Preferences::with_store(ProfileStore("org.example.alice".to_owned()));
+        let profile = |prefs: &mut Preferences| {
+            prefs
+                .get("settings")
+                .and_then(|file| file.get_group("profile"))
+                .and_then(|group| group.get::<String>("name"))
+        };
+        assert_eq!(profile(&mut prefs).as_deref(), Some("org.example.alice"));
+
+        prefs.set_app_name("org.example.bob");
+        assert_eq!(prefs.loaded_files().count(), 0);
+        assert_eq!(profile(&mut prefs).as_deref(), Some("org.example.bob"));
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut prefs = tuning_prefs();
+        assert!(prefs.diff("tuning").is_none());
+        let mut combat = prefs
+            .get_mut("tuning")
+            .unwrap()
+            .get_group_mut("combat")
+            .unwrap();
+        combat.set("damage_multiplier", 2.0);
+        combat.remove("label");
+        combat.set("armor", 10);
+
+        let diff = prefs.diff("tuning").unwrap();
+        assert_eq!(
+            diff.to_string(),
+            "+ combat.armor = 10\n~ combat.damage_multiplier: 1.5 -> 2.0\n- combat.label = \"normal\"\n"
+        );
+    }
+
+    /// Store which reports a file as changed externally, but can no longer parse it.
+    struct CorruptedStore;
+
+    impl PreferencesStore for CorruptedStore {
+        fn is_valid(&self) -> bool {
+            true
+        }
+
+        fn create(&self) -> PreferencesFile {
+            PreferencesFile::new()
+        }
+
+        fn load(&mut self, filename: &str) -> Option<PreferencesFile> {
+            self.load_async(filename)
+        }
+
+        fn load_async(&self, _filename: &str) -> Option<PreferencesFile> {
+            Some("[audio]\nvolume = 0.5\n".parse().unwrap())
+        }
+
+        fn reload(&self, _filename: &str) -> Option<PreferencesFile> {
+            None
+        }
+
+        fn save(&self, _filename: &str, _file: &PreferencesFile) -> Result<(), PreferencesError> {
+            Ok(())
+        }
+
+        fn save_async(&self, _filename: &str, _file: PreferencesFileContent) {}
+
+        fn take_external_changes(&self) -> Vec<String> {
+            vec!["settings".to_owned()]
+        }
+    }
+
+    /// Store whose file has been changed by another program since it was loaded.
+    struct EditedStore;
+
+    impl PreferencesStore for EditedStore {
+        fn is_valid(&self) -> bool {
+            true
+        }
+
+        fn create(&self) -> PreferencesFile {
+            PreferencesFile::new()
+        }
+
+        fn load(&mut self, filename: &str) -> Option<PreferencesFile> {
+            self.load_async(filename)
+        }
+
+        fn load_async(&self, _filename: &str) -> Option<PreferencesFile> {
+            Some("[audio]\nvolume = 0.5\n".parse().unwrap())
+        }
+
+        fn reload(&self, _filename: &str) -> Option<PreferencesFile> {
+            Some("[audio]\nvolume = 0.8\n".parse().unwrap())
+        }
+
+        fn save(&self, _filename: &str, _file: &PreferencesFile) -> Result<(), PreferencesError> {
+            Ok(())
+        }
+
+        fn save_async(&self, _filename: &str, _file: PreferencesFileContent) {}
+
+        fn take_external_changes(&self) -> Vec<String> {
+            vec!["settings".to_owned()]
+        }
+    }
+
+    #[test]
+    fn test_reload_keeps_unsaved_changes() {
+        let mut prefs = Preferences::with_store(EditedStore);
+        let file = prefs.get_mut("settings").unwrap();
+        file.get_group_mut("audio").unwrap().set("muted", true);
+        assert!(prefs.reload_external_changes().is_empty());
+        let file = prefs.peek("settings").unwrap();
+        assert_eq!(file.get_path::<f32>("audio.volume"), Some(0.5));
+        assert_eq!(file.get_path::<bool>("audio.muted"), Some(true));
+
+        // Once saved, the file is reloaded.
+        prefs.save(false);
+        assert_eq!(prefs.reload_external_changes(), vec!["settings".to_owned()]);
+        let file = prefs.peek("settings").unwrap();
+        assert_eq!(file.get_path::<f32>("audio.volume"), Some(0.8));
+    }
+
+    #[test]
+    fn test_reload_unreadable_file() {
+        let mut prefs = Preferences::with_store(CorruptedStore);
+        assert!(prefs.get("settings").is_some());
+        assert_eq!(prefs.reload_external_changes(), vec!["settings".to_owned()]);
+        let file = prefs.peek("settings").unwrap();
+        assert_eq!(file.get_path::<f32>("audio.volume"), Some(0.5));
+        assert_eq!(prefs.counters().loads.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_save_specific() {
+        let mut prefs = Preferences::with_store(TestStore::default());
+        for filename in ["graphics", "keybindings"] {
+            let file = prefs.get_mut(filename).unwrap();
+            file.get_group_mut("general").unwrap().set("version", 2);
+        }
+
+        assert!(prefs.save_specific("graphics", false).is_empty());
+        assert!(!prefs.is_file_changed("graphics"));
+        assert!(prefs.is_file_changed("keybindings"));
+
+        // Files which are not loaded are ignored.
+        assert!(prefs.save_specific("audio", true).is_empty());
+
+        prefs.save_specific_async("keybindings", false);
+        assert!(!prefs.is_file_changed("keybindings"));
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut prefs = tuning_prefs();
+        assert!(prefs.peek("tuning").is_none());
+
+        prefs.get("tuning").unwrap();
+        let file = prefs.peek("tuning").unwrap();
+        assert_eq!(
+            file.get_group("combat")
+                .unwrap()
+                .get::<f32>("damage_multiplier"),
+            Some(1.5)
+        );
+    }
+}
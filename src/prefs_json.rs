@@ -1,13 +1,147 @@
-use bevy::log::warn;
-use serde::{de::DeserializeOwned, Serialize};
+use bevy::{
+    color::Color,
+    log::{error, info, warn},
+    math::{IRect, IVec2, Quat, Rect, URect, UVec2, Vec2, Vec3},
+    platform::collections::{HashMap, HashSet},
+    transform::components::Transform,
+};
+use serde::{de::DeserializeOwned, Serialize, Serializer};
 use serde_json::{Map, Value as JsonValue};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    bytes::{bytes_from_value, bytes_to_base64, BYTES_SIZE_WARNING},
+    changed::ChangedFlags,
+    color::{color_from_value, color_to_hex},
+    datetime::TOML_DATETIME_FIELD,
+    diff::PrefsDiff,
+    duration::{duration_from_value, duration_to_string},
+    key::{debug_check_key, is_reserved_key, validate_key},
+    keybindings::{bindings_from_value, bindings_to_value, BindingMap},
+    value::{
+        contains_null, decode_large_ints, encode_large_ints, has_non_finite, non_finite_error,
+    },
+    GetError, PreferencesError, PrefsDateTime, PrefsEntries, PrefsValue, PrefsValueRef, ValueKind,
+};
+
+/// A function which checks whether a preferences value is valid, and may correct it in place.
+type Validator = Box<dyn Fn(&mut JsonValue) -> Result<(), String> + Send + Sync>;
+
+/// Validators for the keys within a preferences group, indexed by key name.
+#[derive(Default)]
+pub(crate) struct GroupValidators(HashMap<String, Validator>);
+
+impl GroupValidators {
+    /// Run the validator for `key`, if there is one.
+    fn check(&self, key: &str, value: &mut JsonValue) -> Result<(), String> {
+        match self.0.get(key) {
+            Some(validator) => validator(value),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::fmt::Debug for GroupValidators {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+/// Convert a serializable value into a JSON value. Returns `None` if the value serializes as
+/// `null`; callers treat this as removing the key, for consistency with the TOML backend.
+/// Values containing NaN or infinite floats are rejected.
+fn to_value<S: Serialize>(value: S) -> Result<Option<JsonValue>, PreferencesError> {
+    match serde_json::to_value(&value) {
+        // `serde_json` writes non-finite floats as `null`, so only then is it worth checking.
+        Ok(json) if contains_null(&json) && has_non_finite(&value) => Err(non_finite_error()),
+        Ok(JsonValue::Null) => Ok(None),
+        Ok(mut value) => {
+            collapse_datetimes(&mut value);
+            encode_large_ints(&mut value);
+            Ok(Some(value))
+        }
+        Err(e) => Err(PreferencesError::Serialize(e.to_string())),
+    }
+}
+
+/// Deserialize a stored JSON value. Integers too large for an `i64` are stored as strings, so if
+/// the value does not deserialize as-is, it is retried with those strings converted back to
+/// numbers.
+fn from_value<D: DeserializeOwned>(value: &JsonValue) -> Result<D, serde_json::Error> {
+    D::deserialize(value).or_else(|e| {
+        let mut decoded = value.clone();
+        if decode_large_ints(&mut decoded) {
+            serde_json::from_value(decoded)
+        } else {
+            Err(e)
+        }
+    })
+}
+
+/// Deserialize a stored JSON value into an existing one, reusing its allocations where the
+/// type supports it. Returns false if the value is not deserializable.
+fn from_value_in_place<D: DeserializeOwned>(value: &JsonValue, out: &mut D) -> bool {
+    if D::deserialize_in_place(value, out).is_ok() {
+        return true;
+    }
+    match from_value::<D>(value) {
+        Ok(value) => {
+            *out = value;
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Replace TOML datetimes, such as those serialized by [`PrefsDateTime`], with their RFC 3339
+/// string form. `serde_json` would otherwise store them as objects with a private field name.
+fn collapse_datetimes(value: &mut JsonValue) {
+    match value {
+        JsonValue::Object(map) => {
+            if map.len() == 1 {
+                if let Some(JsonValue::String(s)) = map.get(TOML_DATETIME_FIELD) {
+                    *value = JsonValue::String(s.clone());
+                    return;
+                }
+            }
+            map.values_mut().for_each(collapse_datetimes);
+        }
+        JsonValue::Array(array) => array.iter_mut().for_each(collapse_datetimes),
+        _ => {}
+    }
+}
+
+/// The error for a value which must serialize as a map, but did not.
+fn not_a_map() -> PreferencesError {
+    PreferencesError::Serialize("value must serialize as a struct or map".to_owned())
+}
+
+/// Convert a serializable value into a list item. Lists cannot hold `None`.
+fn to_item<S: Serialize>(value: S) -> Result<JsonValue, PreferencesError> {
+    to_value(value)?.ok_or_else(|| PreferencesError::Serialize("lists cannot hold None".to_owned()))
+}
+
+/// The error for a list index which is out of bounds.
+fn out_of_bounds(key: &str, index: usize, len: usize) -> PreferencesError {
+    PreferencesError::IndexOutOfBounds {
+        key: key.to_owned(),
+        index,
+        len,
+    }
+}
 
 /// Represents a single preferences file containing multiple groups of settings.
-#[derive(Debug, Default)]
+#[derive(Default)]
 pub struct JsonPreferencesFile {
     root: Map<String, JsonValue>,
-    changed: AtomicBool,
+    changed: ChangedFlags,
+    validators: HashMap<String, GroupValidators>,
+    /// Keys whose values are redacted in `Debug` output, indexed by group name.
+    sensitive: HashMap<String, HashSet<String>>,
 }
 
 impl JsonPreferencesFile {
@@ -28,12 +162,30 @@ impl JsonPreferencesFile {
         };
         Self {
             root,
-            changed: AtomicBool::new(false),
+            changed: ChangedFlags::default(),
+            validators: HashMap::default(),
+            sensitive: HashMap::default(),
+        }
+    }
+
+    /// Get the top level of the file as a preferences group. Its keys are the file's groups,
+    /// along with any values which are not in a group.
+    pub fn root(&self) -> JsonPreferencesGroup<'_> {
+        JsonPreferencesGroup { json: &self.root }
+    }
+
+    /// Get the top level of the file as a mutable preferences group.
+    pub fn root_mut(&mut self) -> JsonPreferencesGroupMut<'_> {
+        JsonPreferencesGroupMut {
+            json: &mut self.root,
+            changed: self.changed.file(),
+            replaced: self.changed.replaced(),
+            validators: None,
         }
     }
 
     /// Get a preferences group from the file, or `None` if the group does not exist.
-    pub fn get_group(&self, group: &str) -> Option<JsonPreferencesGroup> {
+    pub fn get_group(&self, group: &str) -> Option<JsonPreferencesGroup<'_>> {
         self.root
             .get(group)
             .and_then(|v| v.as_object())
@@ -47,27 +199,436 @@ impl JsonPreferencesFile {
             .root
             .entry(group.to_owned())
             .or_insert_with(|| JsonValue::Object(Map::new()));
+        let (changed, replaced) = self.changed.group_and_replaced(group);
         entry.as_object_mut().map(|json| JsonPreferencesGroupMut {
             json,
-            changed: &mut self.changed,
+            changed,
+            replaced,
+            validators: self.validators.get(group),
+        })
+    }
+
+    /// Rename a top-level preferences group. Returns true if the group was moved. If `to`
+    /// already exists, it is only replaced when `overwrite` is true. Validators and sensitive
+    /// keys are registered by group name, and are not moved with the group.
+    pub fn rename_group(&mut self, from: &str, to: &str, overwrite: bool) -> bool {
+        if from == to
+            || !self.root.get(from).is_some_and(JsonValue::is_object)
+            || (!overwrite && self.root.contains_key(to))
+        {
+            return false;
+        }
+        let Some(value) = self.root.remove(from) else {
+            return false;
+        };
+        self.root.insert(to.to_owned(), value);
+        self.changed.set_group(from);
+        self.changed.set_group(to);
+        true
+    }
+
+    /// The names of the top-level groups in the file, in the order they are stored. Keys which
+    /// are not groups are skipped.
+    pub fn group_names(&self) -> Vec<&str> {
+        self.root
+            .iter()
+            .filter(|(_, value)| value.is_object())
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    /// Get a nested preferences group by the sequence of group names leading to it, or `None`
+    /// if any group along the path does not exist. An empty path refers to the top level of
+    /// the file.
+    pub fn get_group_at_path(&self, path: &[&str]) -> Option<JsonPreferencesGroup<'_>> {
+        let mut json = &self.root;
+        for name in path {
+            json = json.get(*name)?.as_object()?;
+        }
+        Some(JsonPreferencesGroup { json })
+    }
+
+    /// Get a mutable reference to a nested preferences group by the sequence of group names
+    /// leading to it, creating any missing groups along the way. Returns `None` if a name along
+    /// the path exists but is not a group. An empty path refers to the top level of the file.
+    pub fn get_group_at_path_mut(&mut self, path: &[&str]) -> Option<JsonPreferencesGroupMut<'_>> {
+        let Some((first, rest)) = path.split_first() else {
+            return Some(self.root_mut());
+        };
+        let (changed, replaced) = self.changed.group_and_replaced(first);
+        let mut json = self
+            .root
+            .entry(first.to_string())
+            .or_insert_with(|| JsonValue::Object(Map::new()))
+            .as_object_mut()?;
+        for name in rest {
+            json = json
+                .entry(name.to_string())
+                .or_insert_with(|| {
+                    changed.store(true, Ordering::Relaxed);
+                    JsonValue::Object(Map::new())
+                })
+                .as_object_mut()?;
+        }
+        Some(JsonPreferencesGroupMut {
+            json,
+            changed,
+            replaced,
+            validators: if rest.is_empty() {
+                self.validators.get(*first)
+            } else {
+                None
+            },
         })
     }
 
+    /// Register a validator for a key within a top-level preferences group. The validator is
+    /// called with the deserialized value whenever the key is set, and values which fail
+    /// validation are rejected with a warning. If the key already holds an invalid value, such
+    /// as one loaded from a hand-edited file, it is removed so that the app falls back to its
+    /// default.
+    ///
+    /// # Arguments
+    /// * `group` - the name of the preferences group.
+    /// * `key` - the name of the key within the group.
+    /// * `validator` - a function which returns an error message if the value is invalid.
+    pub fn set_validator<D, F>(&mut self, group: &str, key: &str, validator: F)
+    where
+        D: DeserializeOwned,
+        F: Fn(&D) -> Result<(), String> + Send + Sync + 'static,
+    {
+        let validator: Validator = Box::new(move |value| {
+            let value = from_value::<D>(value).map_err(|e| e.to_string())?;
+            validator(&value)
+        });
+        self.insert_validator(group, key, validator);
+    }
+
+    /// Register a validator for a key within a top-level preferences group which can correct
+    /// values as well as reject them. The validator is called with the value in
+    /// format-independent form whenever the key is set; if it modifies the value, such as by
+    /// clamping it to a range, the corrected value is stored instead. Values which are rejected
+    /// are not stored. The validator is also run on the current value, if there is one: a
+    /// corrected value marks the group as changed, and a rejected value is removed.
+    ///
+    /// # Arguments
+    /// * `group` - the name of the preferences group.
+    /// * `key` - the name of the key within the group.
+    /// * `validator` - a function which corrects the value, or returns an error message if the
+    ///   value is invalid.
+    pub fn register_validator<F>(&mut self, group: &str, key: &str, validator: F)
+    where
+        F: Fn(&mut PrefsValue) -> Result<(), String> + Send + Sync + 'static,
+    {
+        let validator: Validator = Box::new(move |value| {
+            let mut prefs_value = PrefsValue::from(&*value);
+            validator(&mut prefs_value)?;
+            *value = JsonValue::from(prefs_value);
+            Ok(())
+        });
+        self.insert_validator(group, key, validator);
+    }
+
+    /// Register a validator, and run it on the current value of the key.
+    fn insert_validator(&mut self, group: &str, key: &str, validator: Validator) {
+        if let Some(JsonValue::Object(table)) = self.root.get_mut(group) {
+            if let Some(value) = table.get_mut(key) {
+                let original = value.clone();
+                match validator(value) {
+                    Err(e) => {
+                        warn!("Dropping invalid preference {}.{}: {}", group, key, e);
+                        table.remove(key);
+                        self.changed.set_group(group);
+                    }
+                    Ok(()) if *value != original => {
+                        warn!("Corrected invalid preference {}.{}", group, key);
+                        self.changed.set_group(group);
+                    }
+                    Ok(()) => {}
+                }
+            }
+        }
+        self.validators
+            .entry(group.to_owned())
+            .or_default()
+            .0
+            .insert(key.to_owned(), validator);
+    }
+
+    /// Mark a key within a top-level preferences group as sensitive, such as an auth token.
+    /// Its value is shown as `"<redacted>"` in the file's `Debug` output.
+    pub fn mark_sensitive(&mut self, group: &str, key: &str) {
+        self.sensitive
+            .entry(group.to_owned())
+            .or_default()
+            .insert(key.to_owned());
+    }
+
+    /// Deserialize the entire file as a struct (or map), or `None` if it does not match `D`.
+    pub fn get_all<D: DeserializeOwned>(&self) -> Option<D> {
+        from_value(&JsonValue::Object(self.root.clone())).ok()
+    }
+
+    /// Replace the entire contents of the file with `value`, which must serialize as a struct or
+    /// map. The file is only marked as changed if the new contents are different. Returns an
+    /// error, leaving the file unchanged, if `value` cannot be serialized or is not a map.
+    pub fn set_all<S: Serialize>(&mut self, value: &S) -> Result<(), PreferencesError> {
+        let Some(JsonValue::Object(root)) = to_value(value)? else {
+            return Err(not_a_map());
+        };
+        let keys: HashSet<String> = self.root.keys().chain(root.keys()).cloned().collect();
+        for key in keys {
+            let (old, new) = (self.root.get(&key), root.get(&key));
+            if old == new {
+                continue;
+            }
+            if old.is_some_and(JsonValue::is_object) || new.is_some_and(JsonValue::is_object) {
+                self.changed.set_group(&key);
+            } else {
+                self.changed.set_file();
+            }
+        }
+        self.root = root;
+        self.changed.set_replaced();
+        Ok(())
+    }
+
+    /// Empty every top-level group in the file, leaving the groups themselves in place. Keys
+    /// outside of any group are removed. Only groups which were not already empty are marked as
+    /// changed.
+    pub fn clear(&mut self) {
+        let mut changed_file = false;
+        for (key, value) in self.root.iter_mut() {
+            match value.as_object_mut() {
+                Some(group) if !group.is_empty() => {
+                    group.clear();
+                    self.changed.set_group(key);
+                }
+                Some(_) => {}
+                None => changed_file = true,
+            }
+        }
+        if changed_file {
+            self.root.retain(|_, value| value.is_object());
+            self.changed.set_file();
+        }
+        self.changed.set_replaced();
+    }
+
+    /// Merge the contents of another file into this one, such as a preset or the shipped
+    /// defaults. Groups which exist on both sides are merged recursively. Other keys which exist
+    /// on both sides are only replaced if `overwrite` is true, including where one side holds a
+    /// group and the other a value. Only groups which were modified are marked as changed.
+    pub fn merge_from(&mut self, content: &JsonPreferencesFileContent, overwrite: bool) {
+        for (key, value) in content.0.iter() {
+            match (self.root.get(key), value) {
+                (Some(JsonValue::Object(_)), JsonValue::Object(nested)) => {
+                    if let Some(mut group) = self.get_group_mut(key) {
+                        group.merge_from(&JsonPreferencesGroup { json: nested }, overwrite);
+                    }
+                }
+                (Some(_), _) if !overwrite => {}
+                (Some(old), new) if old == new => {}
+                (old, new) => {
+                    if old.is_some_and(JsonValue::is_object) || new.is_object() {
+                        self.changed.set_group(key);
+                    } else {
+                        self.changed.set_file();
+                    }
+                    self.root.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        self.changed.set_replaced();
+    }
+
+    /// Start a fluent chain of group lookups at the top level of the file, e.g.
+    /// `file.path().group("window").group("monitor").get::<i32>("index")`. Missing groups are
+    /// carried through the chain, and only the final `get` returns an `Option`.
+    pub fn path(&self) -> JsonPreferencesGroupRef<'_> {
+        JsonPreferencesGroupRef(Some(&self.root))
+    }
+
+    /// Start a fluent chain of group lookups at the named top-level group. This is shorthand for
+    /// `file.path().group(group)`, and never creates the group.
+    pub fn group(&self, group: &str) -> JsonPreferencesGroupRef<'_> {
+        self.path().group(group)
+    }
+
+    /// Get a value from a dot-separated path such as "window.monitor.position", where the last
+    /// segment is the key and the preceding segments are nested group names. Returns `None` if
+    /// any part of the path does not exist, or the value is not deserializable.
+    pub fn get_path<D: DeserializeOwned>(&self, path: &str) -> Option<D> {
+        self.get_path_segments(&path.split('.').collect::<Vec<_>>())
+    }
+
+    /// Like [`get_path`](Self::get_path), but takes the path as a list of segments, which allows
+    /// names that contain dots.
+    pub fn get_path_segments<D: DeserializeOwned>(&self, segments: &[&str]) -> Option<D> {
+        let (key, groups) = segments.split_last()?;
+        let mut json = &self.root;
+        for name in groups {
+            json = json.get(*name)?.as_object()?;
+        }
+        from_value(json.get(*key)?).ok()
+    }
+
+    /// Set a value at a dot-separated path such as "window.monitor.position", creating any
+    /// missing groups along the way. The file is marked as changed if the value is different
+    /// from the one stored. Returns an error if a segment of the path exists but is not a
+    /// group, or the value cannot be stored.
+    pub fn set_path<S: Serialize>(&mut self, path: &str, value: S) -> Result<(), PreferencesError> {
+        self.set_path_segments(&path.split('.').collect::<Vec<_>>(), value)
+    }
+
+    /// Like [`set_path`](Self::set_path), but takes the path as a list of segments, which allows
+    /// names that contain dots.
+    pub fn set_path_segments<S: Serialize>(
+        &mut self,
+        segments: &[&str],
+        value: S,
+    ) -> Result<(), PreferencesError> {
+        let Some((key, groups)) = segments.split_last() else {
+            return Err(PreferencesError::EmptyPath);
+        };
+        let Some(mut value) = to_value(value)? else {
+            let mut json = Some(&mut self.root);
+            for name in groups {
+                json = json
+                    .and_then(|t| t.get_mut(*name))
+                    .and_then(|v| v.as_object_mut());
+            }
+            if json.and_then(|t| t.remove(*key)).is_some() {
+                self.mark_path(groups);
+            }
+            return Ok(());
+        };
+        if let [group] = groups {
+            if let Some(validators) = self.validators.get(*group) {
+                validators
+                    .check(key, &mut value)
+                    .map_err(|reason| PreferencesError::Invalid {
+                        key: (*key).to_owned(),
+                        reason,
+                    })?;
+            }
+        }
+        let mut json = &mut self.root;
+        for (i, name) in groups.iter().enumerate() {
+            json = json
+                .entry(*name)
+                .or_insert_with(|| JsonValue::Object(Map::new()))
+                .as_object_mut()
+                .ok_or_else(|| PreferencesError::NotATable(segments[..=i].join(".")))?;
+        }
+        if json.get(*key) != Some(&value) {
+            json.insert((*key).to_owned(), value);
+            self.mark_path(groups);
+        }
+        Ok(())
+    }
+
+    /// Mark the group containing a path as changed: the top-level group named by the first
+    /// segment of `groups`, or the file itself if there are no groups.
+    fn mark_path(&mut self, groups: &[&str]) {
+        match groups.first() {
+            Some(group) => self.changed.set_group(group),
+            None => self.changed.set_file(),
+        }
+    }
+
     pub fn is_changed(&self) -> bool {
-        self.changed.load(Ordering::Relaxed)
+        self.changed.is_changed()
+    }
+
+    /// Compute the differences between `other`, such as a copy of this file's content taken
+    /// earlier, and the current contents of this file. Changes are listed from `other` to this
+    /// file, so a value which exists only here is added.
+    pub fn diff(&self, other: &JsonPreferencesFileContent) -> PrefsDiff {
+        PrefsDiff::between(&PrefsValue::from(&other.0), &PrefsValue::from(&self.root))
+    }
+
+    /// Apply the changes in a [`PrefsDiff`], such as one received from another device, marking
+    /// the changed groups. Groups along each path are created if they do not exist. Returns an
+    /// error if a segment of a path exists but is not a group; changes before it are kept.
+    pub fn apply_diff(&mut self, diff: &PrefsDiff) -> Result<(), PreferencesError> {
+        self.changed.set_replaced();
+        for (path, change) in &diff.changes {
+            let segments = PrefsDiff::segments(path);
+            let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+            let Some((key, groups)) = segments.split_last() else {
+                return Err(PreferencesError::EmptyPath);
+            };
+            let mut table = &mut self.root;
+            for (i, name) in groups.iter().enumerate() {
+                table = table
+                    .entry(*name)
+                    .or_insert_with(|| JsonValue::Object(Map::new()))
+                    .as_object_mut()
+                    .ok_or_else(|| PreferencesError::NotATable(segments[..=i].join(".")))?;
+            }
+            match change.new_value().cloned().map(JsonValue::from) {
+                Some(value) => table.insert((*key).to_owned(), value),
+                None => table.remove(*key),
+            };
+            self.mark_path(if change.is_group() { &segments } else { groups });
+        }
+        Ok(())
     }
 
     pub fn set_changed(&self) {
-        self.changed.store(true, Ordering::Relaxed);
+        self.changed.set_file();
     }
 
     pub fn clear_changed(&self) {
-        self.changed.store(false, Ordering::Relaxed);
+        self.changed.clear();
+    }
+
+    /// Check if the named top-level group has been changed.
+    pub fn is_group_changed(&self, group: &str) -> bool {
+        self.changed.is_group_changed(group)
     }
 
+    /// Returns the names of the top-level groups which have been changed, in sorted order.
+    /// Changes to keys which are not within a group are not included.
+    pub fn changed_groups(&self) -> Vec<&str> {
+        self.changed.changed_groups()
+    }
+
+    /// Clear the changed flag for the named top-level group. The file remains changed if any
+    /// other group has been changed.
+    pub fn clear_group_changed(&self, group: &str) {
+        self.changed.clear_group(group);
+    }
+
+    /// Check if keys which are not within a top-level group have been changed.
     #[allow(unused)]
-    pub(crate) fn encode(&self) -> String {
-        serde_json::to_string(&self.root).unwrap()
+    pub(crate) fn is_ungrouped_changed(&self) -> bool {
+        self.changed.is_file_changed()
+    }
+
+    /// Check if contents of the file were replaced in bulk, such as by `set_all`, since the
+    /// flag was last taken.
+    pub fn is_replaced(&self) -> bool {
+        self.changed.is_replaced()
+    }
+
+    /// Check if contents of the file were replaced in bulk, and clear the flag.
+    pub fn take_replaced(&self) -> bool {
+        self.changed.take_replaced()
+    }
+
+    pub(crate) fn encode(&self) -> Result<String, PreferencesError> {
+        encode_map(&self.root)
+    }
+
+    /// Estimate the size of the file in bytes, as it would be written by the store. This
+    /// serializes the file in memory but does not touch the disk. On the web, compare the total
+    /// from [`Preferences::total_estimated_size_bytes`](crate::Preferences::total_estimated_size_bytes)
+    /// against the LocalStorage quota, typically around 5 MB per site, to warn users before a
+    /// save fails.
+    pub fn estimated_size_bytes(&self) -> usize {
+        self.encode().map_or(0, |encoded| encoded.len())
     }
 
     /// Return a cloned copy of the content, for async saving.
@@ -76,14 +637,73 @@ impl JsonPreferencesFile {
     }
 }
 
+impl std::fmt::Debug for JsonPreferencesFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut root = self.root.clone();
+        for (group, keys) in self.sensitive.iter() {
+            if let Some(JsonValue::Object(group)) = root.get_mut(group) {
+                for key in keys {
+                    if let Some(value) = group.get_mut(key) {
+                        *value = JsonValue::String("<redacted>".to_owned());
+                    }
+                }
+            }
+        }
+        f.debug_struct("JsonPreferencesFile")
+            .field("root", &root)
+            .field("changed", &self.changed)
+            .field("validators", &self.validators)
+            .finish()
+    }
+}
+
+impl FromStr for JsonPreferencesFile {
+    type Err = serde_json::Error;
+
+    /// Parse a preferences file from a JSON string. This is mainly useful for test fixtures.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let root = serde_json::from_str::<Map<String, JsonValue>>(s)?;
+        Ok(Self {
+            root,
+            changed: ChangedFlags::default(),
+            validators: HashMap::default(),
+            sensitive: HashMap::default(),
+        })
+    }
+}
+
 /// Cloned contents of a [`PreferencesFile`]
 #[derive(Debug, Default, Clone)]
 pub struct JsonPreferencesFileContent(#[allow(unused)] pub(crate) Map<String, JsonValue>);
 
 impl JsonPreferencesFileContent {
     #[allow(unused)]
-    pub(crate) fn encode(&self) -> String {
-        serde_json::to_string(&self.0).unwrap()
+    pub(crate) fn encode(&self) -> Result<String, PreferencesError> {
+        encode_map(&self.0)
+    }
+}
+
+/// Serialize a JSON object with its keys, and those of any nested objects, in sorted order, so
+/// that saving the same settings always produces the same bytes. Objects are already sorted
+/// unless another crate enables the `preserve_order` feature of `serde_json`.
+fn encode_map(map: &Map<String, JsonValue>) -> Result<String, PreferencesError> {
+    serde_json::to_string(&sorted_map(map)).map_err(|e| PreferencesError::Serialize(e.to_string()))
+}
+
+fn sorted_map(map: &Map<String, JsonValue>) -> Map<String, JsonValue> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by_key(|(key, _)| *key);
+    entries
+        .into_iter()
+        .map(|(key, value)| (key.clone(), sorted_json(value)))
+        .collect()
+}
+
+fn sorted_json(value: &JsonValue) -> JsonValue {
+    match value {
+        JsonValue::Object(map) => JsonValue::Object(sorted_map(map)),
+        JsonValue::Array(array) => JsonValue::Array(array.iter().map(sorted_json).collect()),
+        value => value.clone(),
     }
 }
 
@@ -94,27 +714,308 @@ pub struct JsonPreferencesGroup<'a> {
 pub struct JsonPreferencesGroupMut<'a> {
     json: &'a mut Map<String, JsonValue>,
     changed: &'a AtomicBool,
+    /// The file's flag for bulk replacements, which cause required keys to be checked again.
+    replaced: &'a AtomicBool,
+    validators: Option<&'a GroupValidators>,
+}
+
+/// A possibly-missing preferences group, used to chain group lookups without threading an
+/// `Option` through each step. See [`JsonPreferencesFile::path`].
+#[derive(Debug, Clone, Copy)]
+pub struct JsonPreferencesGroupRef<'a>(Option<&'a Map<String, JsonValue>>);
+
+impl<'a> JsonPreferencesGroupRef<'a> {
+    /// Step into a nested group. If the group does not exist or is not a table, the result
+    /// refers to a missing group.
+    pub fn group(self, key: &str) -> JsonPreferencesGroupRef<'a> {
+        JsonPreferencesGroupRef(self.0.and_then(|json| json.get(key)?.as_object()))
+    }
+
+    /// Get a key from the group as a deserializable value, or `None` if the group is missing,
+    /// the key does not exist, or the value is not deserializable.
+    pub fn get<D: DeserializeOwned>(self, key: &str) -> Option<D> {
+        from_value(self.0?.get(key)?).ok()
+    }
+
+    /// Get a key from the group as a deserializable value, or `default` if the group is
+    /// missing, the key does not exist, or the value is not deserializable.
+    pub fn get_or<D: DeserializeOwned>(self, key: &str, default: D) -> D {
+        self.get(key).unwrap_or(default)
+    }
+
+    /// Get a key from the group as a deserializable value, or the result of calling `f` if the
+    /// group is missing, the key does not exist, or the value is not deserializable.
+    pub fn get_or_else<D: DeserializeOwned>(self, key: &str, f: impl FnOnce() -> D) -> D {
+        self.get(key).unwrap_or_else(f)
+    }
+
+    /// Get a key from the group as a deserializable value, or the type's default value if the
+    /// group is missing, the key does not exist, or the value is not deserializable.
+    pub fn get_or_default<D: DeserializeOwned + Default>(self, key: &str) -> D {
+        self.get(key).unwrap_or_default()
+    }
+
+    /// Returns the group, or `None` if it is missing.
+    pub fn resolve(self) -> Option<JsonPreferencesGroup<'a>> {
+        self.0.map(|json| JsonPreferencesGroup { json })
+    }
 }
 
-impl JsonPreferencesGroup<'_> {
+impl Serialize for JsonPreferencesGroup<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.json.serialize(serializer)
+    }
+}
+
+impl<'a> JsonPreferencesGroup<'a> {
+    /// Iterate over the keys and values in the group, without deserializing them. This
+    /// includes reserved metadata keys.
+    pub fn entries(&self) -> PrefsEntries<'a> {
+        PrefsEntries::from(self.json)
+    }
+
+    /// Iterate over the keys in the group, skipping reserved metadata keys which begin with
+    /// [`RESERVED_KEY_PREFIX`](crate::RESERVED_KEY_PREFIX).
+    pub fn keys(&self) -> impl Iterator<Item = &'a str> + 'a {
+        self.keys_all().filter(|key| !is_reserved_key(key))
+    }
+
+    /// Iterate over all of the keys in the group, including reserved metadata keys.
+    pub fn keys_all(&self) -> impl Iterator<Item = &'a str> + 'a {
+        self.json.keys().map(String::as_str)
+    }
+
+    /// Iterate over the keys and values in the group, skipping reserved metadata keys. Use
+    /// [`entries`](Self::entries) to include them.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, PrefsValueRef<'a>)> + 'a {
+        self.entries().filter(|(key, _)| !is_reserved_key(key))
+    }
+
+    /// Return a format-independent copy of the contents of this group.
+    pub fn to_owned_value(&self) -> PrefsValue {
+        PrefsValue::from(self.json)
+    }
+
+    /// Deserialize the entire group as a struct (or map), or `None` if it does not match `T`.
+    pub fn read<T: DeserializeOwned>(&self) -> Option<T> {
+        from_value(&JsonValue::Object(self.json.clone())).ok()
+    }
+
     /// Get a key from the preferences group as a deserializable value, or `None` if the key does
     /// not exist or is not deserializable.
     pub fn get<D: DeserializeOwned>(&self, key: &str) -> Option<D> {
-        let value = self.json.get(key)?.clone();
-        serde_json::from_value::<D>(value).ok()
+        self.get_checked(key).ok()
+    }
+
+    /// Get a key from the preferences group as a deserializable value. Unlike [`get`](Self::get),
+    /// this distinguishes a key which is missing from one which holds the wrong type of value.
+    pub fn get_checked<D: DeserializeOwned>(&self, key: &str) -> Result<D, GetError> {
+        let value = self.json.get(key).ok_or(GetError::Missing)?;
+        from_value::<D>(value).map_err(|e| GetError::TypeMismatch {
+            found: ValueKind::from(value),
+            error: e.to_string(),
+        })
+    }
+
+    /// Get a key from the preferences group as a deserializable value, or `default` if the key
+    /// does not exist or is not deserializable. This does not modify the group.
+    pub fn get_or<D: DeserializeOwned>(&self, key: &str, default: D) -> D {
+        self.get(key).unwrap_or(default)
+    }
+
+    /// Deserialize a key into an existing value, returning true if it was updated. Types such as
+    /// `Vec` and `String` reuse their existing buffers. If the key does not exist, `out` is left
+    /// unchanged and false is returned; if it is not deserializable, false is returned and `out`
+    /// may have been partly updated. This is convenient for refreshing a settings struct which is
+    /// kept between frames.
+    pub fn get_into<D: DeserializeOwned>(&self, key: &str, out: &mut D) -> bool {
+        self.json
+            .get(key)
+            .is_some_and(|value| from_value_in_place(value, out))
+    }
+
+    /// Get an enum stored as a string, or `fallback` if the key does not exist or holds a
+    /// variant this version doesn't know about, such as one added by a newer version of the app
+    /// or removed from this one. Unknown variants are logged. To avoid overwriting them when
+    /// writing back settings the user hasn't touched, store the value with
+    /// [`set_preserving_unknown`](JsonPreferencesGroupMut::set_preserving_unknown).
+    pub fn get_enum_or<D: DeserializeOwned>(&self, key: &str, fallback: D) -> D {
+        match self.get_checked(key) {
+            Ok(value) => value,
+            Err(GetError::Missing) => fallback,
+            Err(GetError::TypeMismatch { error, .. }) => {
+                info!(
+                    "Unknown value for preference {}, using default: {}",
+                    key, error
+                );
+                fallback
+            }
+        }
+    }
+
+    /// Get a key from the preferences group as a deserializable value, or the result of calling
+    /// `f` if the key does not exist or is not deserializable. This does not modify the group.
+    pub fn get_or_else<D: DeserializeOwned>(&self, key: &str, f: impl FnOnce() -> D) -> D {
+        self.get(key).unwrap_or_else(f)
+    }
+
+    /// Get a key from the preferences group as a deserializable value, or the type's default
+    /// value if the key does not exist or is not deserializable. This does not modify the group.
+    pub fn get_or_default<D: DeserializeOwned + Default>(&self, key: &str) -> D {
+        self.get(key).unwrap_or_default()
+    }
+
+    /// Get the length of the list stored at `key`, or `None` if the key does not exist or is not
+    /// a list.
+    pub fn list_len(&self, key: &str) -> Option<usize> {
+        self.json.get(key)?.as_array().map(Vec::len)
+    }
+
+    /// Get an item from the list stored at `key` as a deserializable value, or `None` if the key
+    /// is not a list, the index is out of bounds, or the item is not deserializable.
+    pub fn get_item<D>(&self, key: &str, index: usize) -> Option<D>
+    where
+        D: DeserializeOwned,
+    {
+        from_value(self.json.get(key)?.as_array()?.get(index)?).ok()
+    }
+
+    /// Get a most-recently-used list stored at `key`, most recent first. Entries which cannot be
+    /// deserialized as `T` are skipped. Returns an empty list if the key is not a list.
+    pub fn get_mru<T: DeserializeOwned>(&self, key: &str) -> Vec<T> {
+        self.json
+            .get(key)
+            .and_then(|v| v.as_array())
+            .map(|array| array.iter().filter_map(|v| from_value(v).ok()).collect())
+            .unwrap_or_default()
     }
 
     /// Read a nested preferences group from the group, or `None` if the property does not exist or
     /// is not a table.
-    pub fn get_group(&self, key: &str) -> Option<JsonPreferencesGroup> {
+    pub fn get_group(&self, key: &str) -> Option<JsonPreferencesGroup<'a>> {
         self.json
             .get(key)
             .and_then(|v| v.as_object())
             .map(|json| JsonPreferencesGroup { json })
     }
+
+    /// Read a [`Transform`] stored as a nested group with the keys `position`, `rotation` and
+    /// `scale`. Returns `None` if the group does not exist; missing components default to the
+    /// identity transform.
+    pub fn get_transform(&self, key: &str) -> Option<Transform> {
+        let group = self.get_group(key)?;
+        Some(Transform {
+            translation: group.get::<Vec3>("position").unwrap_or(Vec3::ZERO),
+            rotation: group
+                .get::<[f32; 4]>("rotation")
+                .map(Quat::from_array)
+                .unwrap_or(Quat::IDENTITY),
+            scale: group.get::<Vec3>("scale").unwrap_or(Vec3::ONE),
+        })
+    }
+
+    /// Get the raw value of a key, without deserializing it.
+    #[cfg(feature = "raw-access")]
+    pub fn get_raw(&self, key: &str) -> Option<&JsonValue> {
+        self.json.get(key)
+    }
+
+    /// Read bytes stored by `set_bytes` as a base64 string. For migration, an array of
+    /// integers, as serde writes a `Vec<u8>`, is also accepted. Returns `None`, with a warning,
+    /// if the value can't be decoded.
+    pub fn get_bytes(&self, key: &str) -> Option<Vec<u8>> {
+        match bytes_from_value(PrefsValueRef::Json(self.json.get(key)?)) {
+            Ok(bytes) => Some(bytes),
+            Err(e) => {
+                warn!("Invalid bytes for {}: {}", key, e);
+                None
+            }
+        }
+    }
+
+    /// Read a [`Color`] stored as an `"#rrggbbaa"` sRGB hex string. For migration, an array of
+    /// 3 or 4 sRGB float components is also accepted.
+    pub fn get_color(&self, key: &str) -> Option<Color> {
+        color_from_value(PrefsValueRef::Json(self.json.get(key)?))
+    }
+
+    /// Read a [`BindingMap`] stored by `set_keybindings`. Bindings which can't be parsed, such as
+    /// unknown key names, are skipped with a warning, so that the rest of the map is kept.
+    pub fn get_keybindings(&self, key: &str) -> Option<BindingMap> {
+        self.get::<HashMap<String, PrefsValue>>(key)
+            .map(bindings_from_value)
+    }
+
+    /// Read a [`PrefsDateTime`], stored either as a TOML datetime or an RFC 3339 string.
+    pub fn get_datetime(&self, key: &str) -> Option<PrefsDateTime> {
+        self.get(key)
+    }
+
+    /// Read a [`Duration`] stored as a string such as `"90s"`, `"1.5m"` or `"250ms"`. The units
+    /// `ms`, `s`, `m` and `h` are accepted, and a bare number is read as seconds. Returns `None`,
+    /// with a warning, if the value is negative or can't be parsed.
+    pub fn get_duration(&self, key: &str) -> Option<Duration> {
+        match duration_from_value(PrefsValueRef::Json(self.json.get(key)?)) {
+            Ok(duration) => Some(duration),
+            Err(e) => {
+                warn!("Invalid duration for {}: {}", key, e);
+                None
+            }
+        }
+    }
+
+    /// Read a [`Rect`] stored as a 4-element array `[min_x, min_y, max_x, max_y]`.
+    pub fn get_rect(&self, key: &str) -> Option<Rect> {
+        let [min_x, min_y, max_x, max_y] = self.get::<[f32; 4]>(key)?;
+        Some(Rect {
+            min: Vec2::new(min_x, min_y),
+            max: Vec2::new(max_x, max_y),
+        })
+    }
+
+    /// Read an [`IRect`] stored as a 4-element array `[min_x, min_y, max_x, max_y]`.
+    pub fn get_irect(&self, key: &str) -> Option<IRect> {
+        let [min_x, min_y, max_x, max_y] = self.get::<[i32; 4]>(key)?;
+        Some(IRect {
+            min: IVec2::new(min_x, min_y),
+            max: IVec2::new(max_x, max_y),
+        })
+    }
+
+    /// Read a [`URect`] stored as a 4-element array `[min_x, min_y, max_x, max_y]`.
+    pub fn get_urect(&self, key: &str) -> Option<URect> {
+        let [min_x, min_y, max_x, max_y] = self.get::<[u32; 4]>(key)?;
+        Some(URect {
+            min: UVec2::new(min_x, min_y),
+            max: UVec2::new(max_x, max_y),
+        })
+    }
 }
 
 impl JsonPreferencesGroupMut<'_> {
+    /// Iterate over the keys and values in the group, without deserializing them. This includes
+    /// reserved metadata keys. Use the typed setters to modify values.
+    pub fn entries(&self) -> PrefsEntries<'_> {
+        PrefsEntries::from(&*self.json)
+    }
+
+    /// Iterate over the keys in the group, skipping reserved metadata keys which begin with
+    /// [`RESERVED_KEY_PREFIX`](crate::RESERVED_KEY_PREFIX).
+    pub fn keys(&self) -> impl Iterator<Item = &'_ str> + '_ {
+        self.keys_all().filter(|key| !is_reserved_key(key))
+    }
+
+    /// Iterate over all of the keys in the group, including reserved metadata keys.
+    pub fn keys_all(&self) -> impl Iterator<Item = &'_ str> + '_ {
+        self.json.keys().map(String::as_str)
+    }
+
+    /// Iterate over the keys and values in the group, skipping reserved metadata keys. Use
+    /// [`entries`](Self::entries) to include them.
+    pub fn iter(&self) -> impl Iterator<Item = (&'_ str, PrefsValueRef<'_>)> + '_ {
+        self.entries().filter(|(key, _)| !is_reserved_key(key))
+    }
+
     /// Delete a key from the preferences group.
     pub fn remove(&mut self, key: &str) {
         if self.json.remove(key).is_some() {
@@ -123,38 +1024,432 @@ impl JsonPreferencesGroupMut<'_> {
         }
     }
 
-    /// Get a key from the preferences group as a deserializable value, or `None` if the key does
-    /// not exist or is not deserializable.
-    pub fn get<D: DeserializeOwned>(&self, key: &str) -> Option<D> {
-        let value = self.json.get(key)?.clone();
-        serde_json::from_value::<D>(value).ok()
+    /// Remove every key from the preferences group, including nested groups. The group itself
+    /// remains, so it can still be written to. The file is only marked as changed if the group
+    /// was not already empty.
+    pub fn clear(&mut self) {
+        if !self.json.is_empty() {
+            self.json.clear();
+            self.replaced
+                .store(true, std::sync::atomic::Ordering::Relaxed);
+            self.changed
+                .store(true, std::sync::atomic::Ordering::Relaxed);
+        }
     }
 
-    /// Set a key in the preferences group to a serializable value, and mark the file as changed.
-    pub fn set<S: Serialize>(&mut self, key: &str, value: S) {
-        let value = serde_json::to_value(value).unwrap();
-        self.json.insert(key.to_owned(), value);
+    /// Move the value stored under `from` to `to`, without deserializing it. Returns true if a
+    /// value was moved. If `to` already exists, it is only replaced when `overwrite` is true.
+    /// Validators are not run, since the value is not changed.
+    pub fn rename_key(&mut self, from: &str, to: &str, overwrite: bool) -> bool {
+        if from == to || (!overwrite && self.json.contains_key(to)) {
+            return false;
+        }
+        let Some(value) = self.json.remove(from) else {
+            return false;
+        };
+        self.json.insert(to.to_owned(), value);
         self.changed
             .store(true, std::sync::atomic::Ordering::Relaxed);
+        true
     }
 
-    /// Convert `value` into a JSON value. If it is different than the current value, set the key
-    /// in the preferences group to the new value, and mark the file as changed.
-    pub fn set_if_changed<S: Serialize>(&mut self, key: &str, value: S) {
-        let value = serde_json::to_value(value).unwrap();
-        match self.json.get(key) {
-            Some(v) if v == &value => (),
-            _ => {
-                self.json.insert(key.to_owned(), value);
-                self.changed
-                    .store(true, std::sync::atomic::Ordering::Relaxed);
-            }
+    /// Remove a key from the preferences group and return its deserialized value. If the key
+    /// does not exist, or cannot be deserialized as `D`, `None` is returned and the group is left
+    /// unchanged.
+    pub fn take<D: DeserializeOwned>(&mut self, key: &str) -> Option<D> {
+        let value = self.get::<D>(key)?;
+        self.json.remove(key);
+        self.changed
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        Some(value)
+    }
+
+    /// Replace the entire contents of this group with `value`, which must be a table. The file
+    /// is only marked as changed if the new contents are different from the old.
+    pub fn replace_from_value(&mut self, value: PrefsValue) {
+        let JsonValue::Object(json) = JsonValue::from(value) else {
+            warn!("Preferences group can only be replaced by a table");
+            return;
+        };
+        if *self.json != json {
+            *self.json = json;
+            self.replaced
+                .store(true, std::sync::atomic::Ordering::Relaxed);
+            self.changed
+                .store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    /// Deserialize the entire group as a struct (or map), or `None` if it does not match `T`.
+    pub fn read<T: DeserializeOwned>(&self) -> Option<T> {
+        from_value(&JsonValue::Object(self.json.clone())).ok()
+    }
+
+    /// Serialize `value`, which must be a struct or map, and merge its fields into the group
+    /// key by key. Keys which are not fields of `value` are left alone, fields which serialize
+    /// as `None` remove their key, and nested structs are merged into nested groups. As with
+    /// [`set_if_changed`](Self::set_if_changed), the file is only marked as changed if a value
+    /// differs. Errors are logged.
+    pub fn write<T: Serialize>(&mut self, value: &T) {
+        match serde_json::to_value(value) {
+            Ok(JsonValue::Object(fields)) => self.merge(fields),
+            Ok(_) => error!("Preferences group can only be written from a struct or map"),
+            Err(e) => error!("Could not write preferences group: {}", e),
+        }
+    }
+
+    /// Merge `fields` into the group, recursing into nested groups.
+    fn merge(&mut self, fields: Map<String, JsonValue>) {
+        for (key, value) in fields {
+            match value {
+                JsonValue::Object(nested)
+                    if self.json.get(&key).is_some_and(JsonValue::is_object) =>
+                {
+                    if let Some(mut group) = self.get_group_mut(&key) {
+                        group.merge(nested);
+                    }
+                }
+                value => {
+                    if let Err(e) = self.copy_if_changed(&key, value) {
+                        error!("Could not set preference {}: {}", key, e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Merge the keys of another group into this one. Nested groups which exist on both sides
+    /// are merged recursively. Other keys which exist on both sides are only replaced if
+    /// `overwrite` is true, including where one side holds a group and the other a value. The
+    /// file is only marked as changed if the merge modified anything.
+    pub fn merge_from(&mut self, other: &JsonPreferencesGroup, overwrite: bool) {
+        for (key, value) in other.json.iter() {
+            match (self.json.get(key), value) {
+                (Some(JsonValue::Object(_)), JsonValue::Object(nested)) => {
+                    if let Some(mut group) = self.get_group_mut(key) {
+                        group.merge_from(&JsonPreferencesGroup { json: nested }, overwrite);
+                    }
+                }
+                (Some(_), _) if !overwrite => {}
+                _ => {
+                    if let Err(e) = self.copy_if_changed(key, value.clone()) {
+                        error!("Could not set preference {}: {}", key, e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Run the validator registered for `key`, if there is one. The validator may correct
+    /// `value` in place.
+    fn validate(&self, key: &str, value: &mut JsonValue) -> Result<(), PreferencesError> {
+        match self.validators.map(|v| v.check(key, value)) {
+            Some(Err(reason)) => Err(PreferencesError::Invalid {
+                key: key.to_owned(),
+                reason,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Get a key from the preferences group as a deserializable value, or `None` if the key does
+    /// not exist or is not deserializable.
+    pub fn get<D: DeserializeOwned>(&self, key: &str) -> Option<D> {
+        self.get_checked(key).ok()
+    }
+
+    /// Get a key from the preferences group as a deserializable value. Unlike [`get`](Self::get),
+    /// this distinguishes a key which is missing from one which holds the wrong type of value.
+    pub fn get_checked<D: DeserializeOwned>(&self, key: &str) -> Result<D, GetError> {
+        let value = self.json.get(key).ok_or(GetError::Missing)?;
+        from_value::<D>(value).map_err(|e| GetError::TypeMismatch {
+            found: ValueKind::from(value),
+            error: e.to_string(),
+        })
+    }
+
+    /// Get a key from the preferences group as a deserializable value, or `default` if the key
+    /// does not exist or is not deserializable. This does not modify the group.
+    pub fn get_or<D: DeserializeOwned>(&self, key: &str, default: D) -> D {
+        self.get(key).unwrap_or(default)
+    }
+
+    /// Deserialize a key into an existing value, returning true if it was updated. Types such as
+    /// `Vec` and `String` reuse their existing buffers. If the key does not exist, `out` is left
+    /// unchanged and false is returned; if it is not deserializable, false is returned and `out`
+    /// may have been partly updated. This is convenient for refreshing a settings struct which is
+    /// kept between frames.
+    pub fn get_into<D: DeserializeOwned>(&self, key: &str, out: &mut D) -> bool {
+        self.json
+            .get(key)
+            .is_some_and(|value| from_value_in_place(value, out))
+    }
+
+    /// Get an enum stored as a string, or `fallback` if the key does not exist or holds a
+    /// variant this version doesn't know about, such as one added by a newer version of the app
+    /// or removed from this one. Unknown variants are logged. To avoid overwriting them when
+    /// writing back settings the user hasn't touched, store the value with
+    /// [`set_preserving_unknown`](JsonPreferencesGroupMut::set_preserving_unknown).
+    pub fn get_enum_or<D: DeserializeOwned>(&self, key: &str, fallback: D) -> D {
+        match self.get_checked(key) {
+            Ok(value) => value,
+            Err(GetError::Missing) => fallback,
+            Err(GetError::TypeMismatch { error, .. }) => {
+                info!(
+                    "Unknown value for preference {}, using default: {}",
+                    key, error
+                );
+                fallback
+            }
+        }
+    }
+
+    /// Get a key from the preferences group as a deserializable value, or the result of calling
+    /// `f` if the key does not exist or is not deserializable. This does not modify the group.
+    pub fn get_or_else<D: DeserializeOwned>(&self, key: &str, f: impl FnOnce() -> D) -> D {
+        self.get(key).unwrap_or_else(f)
+    }
+
+    /// Get a key from the preferences group as a deserializable value, or the type's default
+    /// value if the key does not exist or is not deserializable. This does not modify the group.
+    pub fn get_or_default<D: DeserializeOwned + Default>(&self, key: &str) -> D {
+        self.get(key).unwrap_or_default()
+    }
+
+    /// Get the length of the list stored at `key`, or `None` if the key does not exist or is not
+    /// a list.
+    pub fn list_len(&self, key: &str) -> Option<usize> {
+        self.json.get(key)?.as_array().map(Vec::len)
+    }
+
+    /// Get an item from the list stored at `key` as a deserializable value, or `None` if the key
+    /// is not a list, the index is out of bounds, or the item is not deserializable.
+    pub fn get_item<D>(&self, key: &str, index: usize) -> Option<D>
+    where
+        D: DeserializeOwned,
+    {
+        from_value(self.json.get(key)?.as_array()?.get(index)?).ok()
+    }
+
+    /// Get a most-recently-used list stored at `key`, most recent first. Entries which cannot be
+    /// deserialized as `T` are skipped. Returns an empty list if the key is not a list.
+    pub fn get_mru<T: DeserializeOwned>(&self, key: &str) -> Vec<T> {
+        self.json
+            .get(key)
+            .and_then(|v| v.as_array())
+            .map(|array| array.iter().filter_map(|v| from_value(v).ok()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Move `value` to the front of the most-recently-used list stored at `key`, removing any
+    /// existing equal entry and truncating the list to `max_len` entries. Entries which cannot be
+    /// deserialized as `T` are dropped. The file is only marked as changed if the list changed.
+    pub fn push_mru<T>(&mut self, key: &str, value: T, max_len: usize)
+    where
+        T: Serialize + DeserializeOwned + PartialEq,
+    {
+        let mut list = self.get_mru::<T>(key);
+        list.retain(|v| v != &value);
+        list.insert(0, value);
+        list.truncate(max_len);
+        self.set_if_changed(key, list);
+    }
+
+    /// Append an item to the list stored at `key`, creating the list if the key does not exist,
+    /// and mark the file as changed. Returns an error, leaving the group unchanged, if the key
+    /// holds something other than a list, or if the new list is rejected by a validator.
+    pub fn push_item<S: Serialize>(&mut self, key: &str, value: S) -> Result<(), PreferencesError> {
+        let item = to_item(value)?;
+        self.edit_list(key, |array| {
+            array.push(item);
+            Ok(())
+        })
+    }
+
+    /// Insert an item into the list stored at `key` before `index`, creating the list if the key
+    /// does not exist, and mark the file as changed. Returns an error, leaving the group
+    /// unchanged, if `index` is greater than the length of the list; otherwise errors are as for
+    /// [`push_item`](Self::push_item).
+    pub fn insert_item<S: Serialize>(
+        &mut self,
+        key: &str,
+        index: usize,
+        value: S,
+    ) -> Result<(), PreferencesError> {
+        let item = to_item(value)?;
+        self.edit_list(key, |array| {
+            if index > array.len() {
+                return Err(out_of_bounds(key, index, array.len()));
+            }
+            array.insert(index, item);
+            Ok(())
+        })
+    }
+
+    /// Remove the item at `index` from the list stored at `key`, and mark the file as changed.
+    /// Returns an error, leaving the group unchanged, if the index is out of bounds; otherwise
+    /// errors are as for [`push_item`](Self::push_item).
+    pub fn remove_item(&mut self, key: &str, index: usize) -> Result<(), PreferencesError> {
+        self.edit_list(key, |array| {
+            if index >= array.len() {
+                return Err(out_of_bounds(key, index, array.len()));
+            }
+            array.remove(index);
+            Ok(())
+        })
+    }
+
+    /// Apply `edit` to a copy of the list stored at `key` (or an empty list, if the key does not
+    /// exist), then validate the result and store it.
+    fn edit_list(
+        &mut self,
+        key: &str,
+        edit: impl FnOnce(&mut Vec<JsonValue>) -> Result<(), PreferencesError>,
+    ) -> Result<(), PreferencesError> {
+        let mut array = match self.json.get(key) {
+            None => Vec::new(),
+            Some(JsonValue::Array(array)) => array.clone(),
+            Some(_) => return Err(PreferencesError::NotAList(key.to_owned())),
+        };
+        edit(&mut array)?;
+        let mut value = JsonValue::Array(array);
+        self.validate(key, &mut value)?;
+        self.json.insert(key.to_owned(), value);
+        self.changed.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Set a key in the preferences group to a serializable value, and mark the file as changed.
+    /// Setting a value of `None` removes the key. If the value cannot be stored, an error is
+    /// logged and the group is left unchanged.
+    /// Invalid key names (see [`validate_key`](crate::validate_key)) panic in debug builds.
+    pub fn set<S: Serialize>(&mut self, key: &str, value: S) {
+        debug_check_key(key);
+        if let Err(e) = self.try_set(key, value) {
+            error!("Could not set preference {}: {}", key, e);
+        }
+    }
+
+    /// Set a key in the preferences group to a serializable value, and mark the file as changed.
+    /// Returns an error, leaving the group unchanged, if the value cannot be converted into
+    /// the file format or is rejected by a validator, or if the key name is invalid (see
+    /// [`validate_key`](crate::validate_key)).
+    pub fn try_set<S: Serialize>(&mut self, key: &str, value: S) -> Result<(), PreferencesError> {
+        validate_key(key)?;
+        let Some(mut value) = to_value(value)? else {
+            self.remove(key);
+            return Ok(());
+        };
+        self.validate(key, &mut value)?;
+        self.json.insert(key.to_owned(), value);
+        self.changed
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Like [`set_if_changed`](Self::set_if_changed), but leaves the stored value alone if it
+    /// can't be read as a `T`, such as an enum variant added by a newer version of the app. This
+    /// is for writing back a value which was read with
+    /// [`get_enum_or`](JsonPreferencesGroup::get_enum_or) and may be the fallback; when the user
+    /// actively changes the setting, use [`set`](Self::set) instead, which always replaces it.
+    pub fn set_preserving_unknown<T: Serialize + DeserializeOwned>(&mut self, key: &str, value: T) {
+        if self
+            .json
+            .get(key)
+            .is_some_and(|stored| from_value::<T>(stored).is_err())
+        {
+            return;
+        }
+        self.set_if_changed(key, value);
+    }
+
+    /// Convert `value` into a JSON value. If it is different than the current value, set the key
+    /// in the preferences group to the new value, and mark the file as changed.
+    /// A value of `None` removes the key. If the value cannot be stored, an error is logged and
+    /// the group is left unchanged.
+    /// Invalid key names (see [`validate_key`](crate::validate_key)) panic in debug builds.
+    pub fn set_if_changed<S: Serialize>(&mut self, key: &str, value: S) {
+        debug_check_key(key);
+        if let Err(e) = self.try_set_if_changed(key, value) {
+            error!("Could not set preference {}: {}", key, e);
+        }
+    }
+
+    /// Like [`set_if_changed`](Self::set_if_changed), but returns an error, leaving the group
+    /// unchanged, if the value cannot be converted into the file format or is rejected by a
+    /// validator, or if the key name is invalid (see [`validate_key`](crate::validate_key)).
+    pub fn try_set_if_changed<S: Serialize>(
+        &mut self,
+        key: &str,
+        value: S,
+    ) -> Result<(), PreferencesError> {
+        validate_key(key)?;
+        self.copy_if_changed(key, value)
+    }
+
+    /// Like [`try_set_if_changed`](Self::try_set_if_changed), but without checking the key
+    /// name, for keys copied from another group or file.
+    fn copy_if_changed<S: Serialize>(
+        &mut self,
+        key: &str,
+        value: S,
+    ) -> Result<(), PreferencesError> {
+        let Some(mut value) = to_value(value)? else {
+            self.remove(key);
+            return Ok(());
+        };
+        self.validate(key, &mut value)?;
+        if self.json.get(key) != Some(&value) {
+            self.json.insert(key.to_owned(), value);
+            self.changed
+                .store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Read-modify-write a key in the preferences group. The current value (or the type's
+    /// default, if the key does not exist or is not deserializable) is passed to `f`, and the
+    /// result is written back. The file is only marked as changed if `f` altered the value.
+    pub fn update<T>(&mut self, key: &str, f: impl FnOnce(&mut T))
+    where
+        T: Serialize + DeserializeOwned + Default,
+    {
+        let mut value = self.get_or_default::<T>(key);
+        f(&mut value);
+        self.set_if_changed(key, value);
+    }
+
+    /// Like [`update`](Self::update), but only modifies the key if it already holds a value that
+    /// can be deserialized as `T`. Returns false, leaving the group unchanged, otherwise.
+    pub fn try_update<T>(&mut self, key: &str, f: impl FnOnce(&mut T)) -> bool
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let Some(mut value) = self.get::<T>(key) else {
+            return false;
+        };
+        f(&mut value);
+        self.set_if_changed(key, value);
+        true
+    }
+
+    /// Get an entry for a key in the preferences group, for in-place manipulation in the style
+    /// of [`HashMap::entry`](std::collections::HashMap::entry).
+    pub fn entry(&mut self, key: &str) -> JsonPrefEntry<'_> {
+        JsonPrefEntry {
+            group: JsonPreferencesGroupMut {
+                json: &mut *self.json,
+                changed: self.changed,
+                replaced: self.replaced,
+                validators: self.validators,
+            },
+            key: key.to_owned(),
         }
     }
 
     /// Read a nested preferences group from the group, or `None` if the property does not exist or
     /// is not a table.
-    pub fn get_group(&self, key: &str) -> Option<JsonPreferencesGroup> {
+    pub fn get_group(&self, key: &str) -> Option<JsonPreferencesGroup<'_>> {
         self.json
             .get(key)
             .and_then(|v| v.as_object())
@@ -172,6 +1467,1514 @@ impl JsonPreferencesGroupMut<'_> {
         entry.as_object_mut().map(|json| JsonPreferencesGroupMut {
             json,
             changed: self.changed,
+            replaced: self.replaced,
+            validators: None,
+        })
+    }
+
+    /// Read a [`Transform`] stored as a nested group with the keys `position`, `rotation` and
+    /// `scale`. Returns `None` if the group does not exist; missing components default to the
+    /// identity transform.
+    pub fn get_transform(&self, key: &str) -> Option<Transform> {
+        let group = self.get_group(key)?;
+        Some(Transform {
+            translation: group.get::<Vec3>("position").unwrap_or(Vec3::ZERO),
+            rotation: group
+                .get::<[f32; 4]>("rotation")
+                .map(Quat::from_array)
+                .unwrap_or(Quat::IDENTITY),
+            scale: group.get::<Vec3>("scale").unwrap_or(Vec3::ONE),
+        })
+    }
+
+    /// Get the raw value of a key, without deserializing it.
+    #[cfg(feature = "raw-access")]
+    pub fn get_raw(&self, key: &str) -> Option<&JsonValue> {
+        self.json.get(key)
+    }
+
+    /// Read bytes stored by `set_bytes` as a base64 string. For migration, an array of
+    /// integers, as serde writes a `Vec<u8>`, is also accepted. Returns `None`, with a warning,
+    /// if the value can't be decoded.
+    pub fn get_bytes(&self, key: &str) -> Option<Vec<u8>> {
+        match bytes_from_value(PrefsValueRef::Json(self.json.get(key)?)) {
+            Ok(bytes) => Some(bytes),
+            Err(e) => {
+                warn!("Invalid bytes for {}: {}", key, e);
+                None
+            }
+        }
+    }
+
+    /// Read a [`Color`] stored as an `"#rrggbbaa"` sRGB hex string. For migration, an array of
+    /// 3 or 4 sRGB float components is also accepted.
+    pub fn get_color(&self, key: &str) -> Option<Color> {
+        color_from_value(PrefsValueRef::Json(self.json.get(key)?))
+    }
+
+    /// Read a [`BindingMap`] stored by `set_keybindings`. Bindings which can't be parsed, such as
+    /// unknown key names, are skipped with a warning, so that the rest of the map is kept.
+    pub fn get_keybindings(&self, key: &str) -> Option<BindingMap> {
+        self.get::<HashMap<String, PrefsValue>>(key)
+            .map(bindings_from_value)
+    }
+
+    /// Read a [`PrefsDateTime`], stored either as a TOML datetime or an RFC 3339 string.
+    pub fn get_datetime(&self, key: &str) -> Option<PrefsDateTime> {
+        self.get(key)
+    }
+
+    /// Read a [`Duration`] stored as a string such as `"90s"`, `"1.5m"` or `"250ms"`. The units
+    /// `ms`, `s`, `m` and `h` are accepted, and a bare number is read as seconds. Returns `None`,
+    /// with a warning, if the value is negative or can't be parsed.
+    pub fn get_duration(&self, key: &str) -> Option<Duration> {
+        match duration_from_value(PrefsValueRef::Json(self.json.get(key)?)) {
+            Ok(duration) => Some(duration),
+            Err(e) => {
+                warn!("Invalid duration for {}: {}", key, e);
+                None
+            }
+        }
+    }
+
+    /// Read a [`Rect`] stored as a 4-element array `[min_x, min_y, max_x, max_y]`.
+    pub fn get_rect(&self, key: &str) -> Option<Rect> {
+        let [min_x, min_y, max_x, max_y] = self.get::<[f32; 4]>(key)?;
+        Some(Rect {
+            min: Vec2::new(min_x, min_y),
+            max: Vec2::new(max_x, max_y),
+        })
+    }
+
+    /// Read an [`IRect`] stored as a 4-element array `[min_x, min_y, max_x, max_y]`.
+    pub fn get_irect(&self, key: &str) -> Option<IRect> {
+        let [min_x, min_y, max_x, max_y] = self.get::<[i32; 4]>(key)?;
+        Some(IRect {
+            min: IVec2::new(min_x, min_y),
+            max: IVec2::new(max_x, max_y),
+        })
+    }
+
+    /// Read a [`URect`] stored as a 4-element array `[min_x, min_y, max_x, max_y]`.
+    pub fn get_urect(&self, key: &str) -> Option<URect> {
+        let [min_x, min_y, max_x, max_y] = self.get::<[u32; 4]>(key)?;
+        Some(URect {
+            min: UVec2::new(min_x, min_y),
+            max: UVec2::new(max_x, max_y),
         })
     }
+
+    /// Store a [`Transform`] as a nested group with the keys `position`, `rotation` (as a
+    /// 4-element array) and `scale`. The file is only marked as changed if one of the components
+    /// is different from the stored value.
+    pub fn set_transform(&mut self, key: &str, t: Transform) {
+        if self.json.get(key).is_some_and(|v| !v.is_object()) {
+            self.remove(key);
+        }
+        let mut group = self.get_group_mut(key).unwrap();
+        group.set_if_changed("position", t.translation);
+        group.set_if_changed("rotation", t.rotation.to_array());
+        group.set_if_changed("scale", t.scale);
+    }
+
+    /// Set a key to a raw value, without serializing it, marking the file as changed if it is
+    /// different from the stored value. Validators are not run.
+    #[cfg(feature = "raw-access")]
+    pub fn set_raw(&mut self, key: &str, value: JsonValue) {
+        if self.json.get(key) != Some(&value) {
+            self.json.insert(key.to_owned(), value);
+            self.changed
+                .store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    /// Store a binary blob, such as a thumbnail, as a base64 string, marking the file as changed
+    /// if it is different from the stored value. This is much more compact than serializing a
+    /// `Vec<u8>`, which writes an array of integers. A warning is logged for blobs larger than
+    /// [`BYTES_SIZE_WARNING`](crate::BYTES_SIZE_WARNING).
+    pub fn set_bytes(&mut self, key: &str, bytes: &[u8]) {
+        if bytes.len() > BYTES_SIZE_WARNING {
+            warn!(
+                "Storing {} bytes in preference {}; large values slow down saving",
+                bytes.len(),
+                key
+            );
+        }
+        self.set_if_changed(key, bytes_to_base64(bytes));
+    }
+
+    /// Store a [`Color`] as an `"#rrggbbaa"` hex string of 8-bit sRGB components, which is
+    /// stable and easy to edit by hand. Colors in other color spaces, such as linear RGB, are
+    /// converted to sRGB. The file is only marked as changed if the stored color is different.
+    pub fn set_color(&mut self, key: &str, color: Color) {
+        self.set_if_changed(key, color_to_hex(color));
+    }
+
+    /// Store a [`BindingMap`] as a table of action names, each holding a list of binding
+    /// strings such as `"Ctrl+KeyS"` or `"Mouse1"`. The file is only marked as changed if the
+    /// stored bindings are different.
+    pub fn set_keybindings(&mut self, key: &str, bindings: &BindingMap) {
+        self.set_if_changed(key, bindings_to_value(bindings));
+    }
+
+    /// Store a [`PrefsDateTime`], as a native datetime in TOML files or an RFC 3339 string in
+    /// JSON, marking the file as changed if it is different from the stored value.
+    pub fn set_datetime(&mut self, key: &str, datetime: PrefsDateTime) {
+        self.set_if_changed(key, datetime);
+    }
+
+    /// Store a [`Duration`] as a human-friendly string such as `"90s"` or `"5m"`, marking the
+    /// file as changed if it is different from the stored value.
+    pub fn set_duration(&mut self, key: &str, duration: Duration) {
+        self.set_if_changed(key, duration_to_string(duration));
+    }
+
+    /// Store a [`Rect`] as a 4-element array `[min_x, min_y, max_x, max_y]`, marking the file as
+    /// changed if it is different from the stored value.
+    pub fn set_rect(&mut self, key: &str, rect: Rect) {
+        self.set_if_changed(key, [rect.min.x, rect.min.y, rect.max.x, rect.max.y]);
+    }
+
+    /// Store an [`IRect`] as a 4-element array `[min_x, min_y, max_x, max_y]`, marking the file
+    /// as changed if it is different from the stored value.
+    pub fn set_irect(&mut self, key: &str, rect: IRect) {
+        self.set_if_changed(key, [rect.min.x, rect.min.y, rect.max.x, rect.max.y]);
+    }
+
+    /// Store a [`URect`] as a 4-element array `[min_x, min_y, max_x, max_y]`, marking the file
+    /// as changed if it is different from the stored value.
+    pub fn set_urect(&mut self, key: &str, rect: URect) {
+        self.set_if_changed(key, [rect.min.x, rect.min.y, rect.max.x, rect.max.y]);
+    }
+}
+
+/// A single key in a mutable preferences group, returned by
+/// [`JsonPreferencesGroupMut::entry`]. Values are read and written through serde, so unlike
+/// [`HashMap`](std::collections::HashMap) entries, the methods return values rather than
+/// references. The file is only marked as changed when a value is written.
+pub struct JsonPrefEntry<'a> {
+    group: JsonPreferencesGroupMut<'a>,
+    key: String,
+}
+
+impl JsonPrefEntry<'_> {
+    /// The key which this entry refers to.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns true if the key exists in the group.
+    pub fn is_occupied(&self) -> bool {
+        self.group.json.contains_key(&self.key)
+    }
+
+    /// If the key holds a value which can be deserialized as `D`, modify it with `f` and write
+    /// it back.
+    pub fn and_modify<D>(mut self, f: impl FnOnce(&mut D)) -> Self
+    where
+        D: Serialize + DeserializeOwned,
+    {
+        self.group.try_update(&self.key, f);
+        self
+    }
+
+    /// Return the value of the key, first storing `default` if the key does not exist or cannot
+    /// be deserialized as `D`.
+    pub fn or_insert<D>(self, default: D) -> D
+    where
+        D: Serialize + DeserializeOwned,
+    {
+        self.or_insert_with(|| default)
+    }
+
+    /// Return the value of the key, first storing the result of `f` if the key does not exist or
+    /// cannot be deserialized as `D`.
+    pub fn or_insert_with<D>(mut self, f: impl FnOnce() -> D) -> D
+    where
+        D: Serialize + DeserializeOwned,
+    {
+        if let Some(value) = self.group.get::<D>(&self.key) {
+            return value;
+        }
+        let value = f();
+        self.group.set(&self.key, &value);
+        value
+    }
+
+    /// Return the value of the key, first storing the type's default if the key does not exist
+    /// or cannot be deserialized as `D`.
+    pub fn or_default<D>(self) -> D
+    where
+        D: Serialize + DeserializeOwned + Default,
+    {
+        self.or_insert_with(D::default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preferences_group_mut_take() {
+        let mut json = Map::new();
+        json.insert("key".to_string(), JsonValue::from(42));
+        let changed = AtomicBool::new(false);
+        let mut group = JsonPreferencesGroupMut {
+            json: &mut json,
+            changed: &changed,
+            replaced: &AtomicBool::new(false),
+            validators: None,
+        };
+        assert_eq!(group.take::<i32>("missing"), None);
+        assert!(!changed.load(Ordering::Relaxed));
+
+        assert_eq!(group.take::<i32>("key"), Some(42));
+        assert!(group.get::<i32>("key").is_none());
+        assert!(changed.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_preferences_group_mut_take_type_mismatch() {
+        let mut json = Map::new();
+        json.insert("key".to_string(), JsonValue::from("value"));
+        let changed = AtomicBool::new(false);
+        let mut group = JsonPreferencesGroupMut {
+            json: &mut json,
+            changed: &changed,
+            replaced: &AtomicBool::new(false),
+            validators: None,
+        };
+        assert_eq!(group.take::<i32>("key"), None);
+        assert_eq!(group.get::<String>("key").unwrap(), "value");
+        assert!(!changed.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_preferences_group_mut_transform() {
+        let mut json = Map::new();
+        let changed = AtomicBool::new(false);
+        let mut group = JsonPreferencesGroupMut {
+            json: &mut json,
+            changed: &changed,
+            replaced: &AtomicBool::new(false),
+            validators: None,
+        };
+        let transform = Transform::from_xyz(1.0, 2.0, 3.0)
+            .with_rotation(Quat::from_rotation_y(0.5))
+            .with_scale(Vec3::splat(2.0));
+        group.set_transform("camera", transform);
+        assert_eq!(group.get_transform("camera").unwrap(), transform);
+        assert!(changed.load(Ordering::Relaxed));
+
+        changed.store(false, Ordering::Relaxed);
+        group.set_transform("camera", transform);
+        assert!(!changed.load(Ordering::Relaxed));
+
+        let group = JsonPreferencesGroup { json: &json };
+        assert_eq!(group.get_transform("camera").unwrap(), transform);
+        assert!(group.get_transform("missing").is_none());
+    }
+
+    #[test]
+    fn test_preferences_file_validator() {
+        let mut prefs = JsonPreferencesFile::from_string(r#"{"audio":{"volume":3.0}}"#, "test");
+        prefs.set_validator("audio", "volume", |v: &f32| {
+            if (0.0..=1.0).contains(v) {
+                Ok(())
+            } else {
+                Err(format!("volume {v} out of range"))
+            }
+        });
+        assert!(prefs
+            .get_group("audio")
+            .unwrap()
+            .get::<f32>("volume")
+            .is_none());
+        assert!(prefs.is_changed());
+
+        prefs.clear_changed();
+        let mut group = prefs.get_group_mut("audio").unwrap();
+        group.set("volume", 1.5);
+        group.set_if_changed("volume", "loud");
+        assert!(group.get::<f32>("volume").is_none());
+        group.set("volume", 0.5);
+        assert_eq!(group.get::<f32>("volume"), Some(0.5));
+        assert!(prefs.is_changed());
+    }
+
+    #[test]
+    fn test_preferences_group_get_or() {
+        let mut json = Map::new();
+        json.insert("volume".to_string(), JsonValue::from(0.5));
+        json.insert("name".to_string(), JsonValue::from(true));
+        let group = JsonPreferencesGroup { json: &json };
+        assert_eq!(group.get_or("volume", 1.0), 0.5);
+        assert_eq!(group.get_or("missing", 1.0), 1.0);
+        assert_eq!(group.get_or_default::<f32>("missing"), 0.0);
+        assert_eq!(group.get_or_default::<String>("name"), "");
+        assert_eq!(
+            group.get_or_else("name", || "default".to_string()),
+            "default"
+        );
+
+        let changed = AtomicBool::new(false);
+        let group = JsonPreferencesGroupMut {
+            json: &mut json,
+            changed: &changed,
+            replaced: &AtomicBool::new(false),
+            validators: None,
+        };
+        assert_eq!(group.get_or_else("missing", || 2), 2);
+        assert!(group.json.get("missing").is_none());
+        assert!(!changed.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_preferences_group_mut_rect() {
+        let mut json = Map::new();
+        let changed = AtomicBool::new(false);
+        let mut group = JsonPreferencesGroupMut {
+            json: &mut json,
+            changed: &changed,
+            replaced: &AtomicBool::new(false),
+            validators: None,
+        };
+        let rect = Rect::new(10.0, 20.0, 110.0, 220.0);
+        group.set_rect("bounds", rect);
+        group.set_irect("ibounds", IRect::new(-1, -2, 3, 4));
+        group.set_urect("ubounds", URect::new(1, 2, 3, 4));
+        assert_eq!(group.get_rect("bounds"), Some(rect));
+        assert!(changed.load(Ordering::Relaxed));
+
+        changed.store(false, Ordering::Relaxed);
+        group.set_rect("bounds", rect);
+        assert!(!changed.load(Ordering::Relaxed));
+
+        let group = JsonPreferencesGroup { json: &json };
+        assert_eq!(group.get_irect("ibounds"), Some(IRect::new(-1, -2, 3, 4)));
+        assert_eq!(group.get_urect("ubounds"), Some(URect::new(1, 2, 3, 4)));
+        assert_eq!(group.get_urect("ibounds"), None);
+    }
+
+    #[test]
+    fn test_preferences_group_mut_try_set_unserializable() {
+        let mut json = Map::new();
+        let changed = AtomicBool::new(false);
+        let mut group = JsonPreferencesGroupMut {
+            json: &mut json,
+            changed: &changed,
+            replaced: &AtomicBool::new(false),
+            validators: None,
+        };
+        let value = std::collections::BTreeMap::from([((1, 2), "a")]);
+        assert!(matches!(
+            group.try_set("key", &value),
+            Err(PreferencesError::Serialize(_))
+        ));
+        group.set("key", &value);
+        group.set_if_changed("key", &value);
+        assert!(group.get::<String>("key").is_none());
+        assert!(!changed.load(Ordering::Relaxed));
+
+        assert_eq!(group.try_set("key", "value"), Ok(()));
+        assert!(changed.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_preferences_group_mut_update() {
+        let mut json = Map::new();
+        json.insert("name".to_string(), JsonValue::from("value"));
+        let changed = AtomicBool::new(false);
+        let mut group = JsonPreferencesGroupMut {
+            json: &mut json,
+            changed: &changed,
+            replaced: &AtomicBool::new(false),
+            validators: None,
+        };
+        group.update("count", |count: &mut i32| *count += 1);
+        group.update("count", |count: &mut i32| *count += 1);
+        assert_eq!(group.get::<i32>("count"), Some(2));
+        assert!(changed.load(Ordering::Relaxed));
+
+        changed.store(false, Ordering::Relaxed);
+        group.update("count", |_: &mut i32| {});
+        assert!(!changed.load(Ordering::Relaxed));
+
+        assert!(!group.try_update("name", |count: &mut i32| *count += 1));
+        assert_eq!(group.get::<String>("name").unwrap(), "value");
+        assert!(group.try_update("count", |count: &mut i32| *count += 1));
+        assert_eq!(group.get::<i32>("count"), Some(3));
+    }
+
+    #[test]
+    fn test_preferences_file_path() {
+        let mut prefs = JsonPreferencesFile::new();
+        prefs
+            .set_path("window.monitor.position", IVec2::new(10, 20))
+            .unwrap();
+        assert!(prefs.is_changed());
+        assert_eq!(
+            prefs.get_path::<IVec2>("window.monitor.position"),
+            Some(IVec2::new(10, 20))
+        );
+        assert_eq!(
+            prefs
+                .get_group("window")
+                .unwrap()
+                .get_group("monitor")
+                .unwrap()
+                .get::<IVec2>("position"),
+            Some(IVec2::new(10, 20))
+        );
+        assert!(prefs.get_path::<IVec2>("window.position").is_none());
+
+        prefs.clear_changed();
+        prefs
+            .set_path("window.monitor.position", IVec2::new(10, 20))
+            .unwrap();
+        assert!(!prefs.is_changed());
+
+        prefs
+            .set_path_segments(&["recent", "file.txt"], true)
+            .unwrap();
+        assert_eq!(
+            prefs.get_path_segments::<bool>(&["recent", "file.txt"]),
+            Some(true)
+        );
+
+        assert_eq!(
+            prefs.set_path("window.monitor.position.x", 1),
+            Err(PreferencesError::NotATable(
+                "window.monitor.position".to_string()
+            ))
+        );
+        assert_eq!(
+            prefs.set_path_segments(&[], 1),
+            Err(PreferencesError::EmptyPath)
+        );
+    }
+
+    #[test]
+    fn test_preferences_group_mut_set_none() {
+        let mut json = Map::new();
+        let changed = AtomicBool::new(false);
+        let mut group = JsonPreferencesGroupMut {
+            json: &mut json,
+            changed: &changed,
+            replaced: &AtomicBool::new(false),
+            validators: None,
+        };
+        group.set("position", Some(IVec2::new(1, 2)));
+        assert_eq!(group.get::<IVec2>("position"), Some(IVec2::new(1, 2)));
+        assert_eq!(
+            group.get::<Option<IVec2>>("position"),
+            Some(Some(IVec2::new(1, 2)))
+        );
+
+        group.set("position", Option::<IVec2>::None);
+        assert!(group.get::<Option<IVec2>>("position").is_none());
+
+        changed.store(false, Ordering::Relaxed);
+        group.set_if_changed("position", Option::<IVec2>::None);
+        assert!(!changed.load(Ordering::Relaxed));
+        group.set_if_changed("position", Some(IVec2::new(1, 2)));
+        group.set_if_changed("position", Option::<IVec2>::None);
+        assert!(group.get::<IVec2>("position").is_none());
+        assert!(changed.load(Ordering::Relaxed));
+
+        let mut prefs = JsonPreferencesFile::new();
+        prefs.set_path("window.position", IVec2::new(1, 2)).unwrap();
+        prefs.clear_changed();
+        prefs
+            .set_path("window.position", Option::<IVec2>::None)
+            .unwrap();
+        assert!(prefs.get_path::<IVec2>("window.position").is_none());
+        assert!(prefs.is_changed());
+    }
+
+    #[test]
+    fn test_preferences_file_from_str() {
+        let file = JsonPreferencesFile::from_str(r#"{"window": {"size": [800, 600]}}"#).unwrap();
+        assert!(!file.is_changed());
+        assert_eq!(
+            file.get_group("window").unwrap().get::<UVec2>("size"),
+            Some(UVec2::new(800, 600))
+        );
+        assert!(JsonPreferencesFile::from_str("[1, 2]").is_err());
+    }
+
+    #[test]
+    fn test_preferences_group_mut_list() {
+        let mut json = Map::new();
+        let changed = AtomicBool::new(false);
+        let mut group = JsonPreferencesGroupMut {
+            json: &mut json,
+            changed: &changed,
+            replaced: &AtomicBool::new(false),
+            validators: None,
+        };
+        assert_eq!(group.list_len("recent"), None);
+        group.push_item("recent", "b.txt").unwrap();
+        assert!(changed.load(Ordering::Relaxed));
+        group.insert_item("recent", 0, "a.txt").unwrap();
+        group.push_item("recent", "c.txt").unwrap();
+        assert_eq!(group.list_len("recent"), Some(3));
+        assert_eq!(
+            group.get_item::<String>("recent", 0),
+            Some("a.txt".to_string())
+        );
+        assert_eq!(group.get_item::<String>("recent", 3), None);
+
+        changed.store(false, Ordering::Relaxed);
+        group.remove_item("recent", 1).unwrap();
+        assert!(changed.load(Ordering::Relaxed));
+        assert_eq!(
+            group.get::<Vec<String>>("recent"),
+            Some(vec!["a.txt".to_string(), "c.txt".to_string()])
+        );
+
+        assert_eq!(
+            group.remove_item("recent", 2),
+            Err(PreferencesError::IndexOutOfBounds {
+                key: "recent".to_string(),
+                index: 2,
+                len: 2
+            })
+        );
+        assert!(group.insert_item("recent", 3, "d.txt").is_err());
+        assert!(group.push_item("recent", Option::<i32>::None).is_err());
+
+        group.set("count", 1);
+        assert_eq!(
+            group.push_item("count", 2),
+            Err(PreferencesError::NotAList("count".to_string()))
+        );
+        assert_eq!(group.get::<i32>("count"), Some(1));
+    }
+
+    #[test]
+    fn test_preferences_group_mut_mru() {
+        let mut json = Map::new();
+        let changed = AtomicBool::new(false);
+        let mut group = JsonPreferencesGroupMut {
+            json: &mut json,
+            changed: &changed,
+            replaced: &AtomicBool::new(false),
+            validators: None,
+        };
+        group.push_mru("recent", "a.txt".to_string(), 3);
+        group.push_mru("recent", "b.txt".to_string(), 3);
+        group.push_mru("recent", "c.txt".to_string(), 3);
+        group.push_mru("recent", "a.txt".to_string(), 3);
+        assert_eq!(
+            group.get_mru::<String>("recent"),
+            ["a.txt", "c.txt", "b.txt"]
+        );
+        group.push_mru("recent", "d.txt".to_string(), 3);
+        assert_eq!(
+            group.get_mru::<String>("recent"),
+            ["d.txt", "a.txt", "c.txt"]
+        );
+
+        // Re-opening the most recent entry is not a change.
+        changed.store(false, Ordering::Relaxed);
+        group.push_mru("recent", "d.txt".to_string(), 3);
+        assert!(!changed.load(Ordering::Relaxed));
+
+        // Entries which fail to deserialize are skipped.
+        group.push_item("recent", 5).unwrap();
+        assert_eq!(group.list_len("recent"), Some(4));
+        assert_eq!(group.get_mru::<String>("recent").len(), 3);
+    }
+
+    #[test]
+    fn test_preferences_file_path_chain() {
+        let file = JsonPreferencesFile::from_str(
+            r#"{"window": {"monitor": {"index": 2}}, "audio": {"volume": 0.5}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            file.path()
+                .group("window")
+                .group("monitor")
+                .get::<i32>("index"),
+            Some(2)
+        );
+        assert_eq!(file.path().group("audio").get::<f32>("volume"), Some(0.5));
+        assert_eq!(
+            file.path()
+                .group("nope")
+                .group("monitor")
+                .get::<i32>("index"),
+            None
+        );
+        assert_eq!(
+            file.path().group("audio").group("volume").get::<f32>("x"),
+            None
+        );
+        assert!(file.path().group("window").resolve().is_some());
+        assert!(file.path().group("missing").resolve().is_none());
+    }
+
+    #[test]
+    fn test_preferences_file_group_get_or() {
+        let file = JsonPreferencesFile::from_str(r#"{"window": {"fullscreen": true}}"#).unwrap();
+        assert!(file.group("window").get_or("fullscreen", false));
+        assert_eq!(
+            file.group("window").get_or("size", UVec2::splat(800)),
+            UVec2::splat(800)
+        );
+        assert_eq!(
+            file.group("missing")
+                .get_or_else("size", || UVec2::splat(600)),
+            UVec2::splat(600)
+        );
+        assert_eq!(file.group("missing").get_or_default::<i32>("count"), 0);
+        assert!(file.get_group("missing").is_none());
+    }
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Layout {
+        sidebar: bool,
+    }
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct EditorSettings {
+        theme: String,
+        font_size: f32,
+        layout: Layout,
+        last_file: Option<String>,
+    }
+
+    #[test]
+    fn test_preferences_group_read_write() {
+        let mut settings = EditorSettings {
+            theme: "dark".to_string(),
+            font_size: 12.0,
+            layout: Layout { sidebar: true },
+            last_file: Some("main.rs".to_string()),
+        };
+        let mut json = Map::new();
+        let changed = AtomicBool::new(false);
+        let mut group = JsonPreferencesGroupMut {
+            json: &mut json,
+            changed: &changed,
+            replaced: &AtomicBool::new(false),
+            validators: None,
+        };
+        group.set("plugin", "vim");
+        group.get_group_mut("layout").unwrap().set("width", 200);
+        group.write(&settings);
+        assert!(changed.load(Ordering::Relaxed));
+        assert_eq!(group.read::<EditorSettings>(), Some(settings));
+
+        // Rewriting the same values is not a change.
+        settings = group.read().unwrap();
+        changed.store(false, Ordering::Relaxed);
+        group.write(&settings);
+        assert!(!changed.load(Ordering::Relaxed));
+
+        // None removes the key; other keys written by someone else are preserved.
+        settings.last_file = None;
+        group.write(&settings);
+        assert!(changed.load(Ordering::Relaxed));
+        assert_eq!(group.get::<String>("last_file"), None);
+        assert_eq!(group.get::<String>("plugin"), Some("vim".to_string()));
+        assert_eq!(
+            group.get_group("layout").unwrap().get::<i32>("width"),
+            Some(200)
+        );
+
+        let group = JsonPreferencesGroup { json: &json };
+        assert_eq!(group.read::<EditorSettings>(), Some(settings));
+        assert_eq!(group.read::<i32>(), None);
+    }
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct AllSettings {
+        editor: EditorSettings,
+        volume: f32,
+    }
+
+    #[test]
+    fn test_preferences_file_set_all() {
+        let settings = AllSettings {
+            editor: EditorSettings {
+                theme: "light".to_string(),
+                font_size: 14.0,
+                layout: Layout { sidebar: false },
+                last_file: None,
+            },
+            volume: 0.25,
+        };
+        let mut file = JsonPreferencesFile::new();
+        file.set_all(&settings).unwrap();
+        assert!(file.is_changed());
+        assert_eq!(
+            file.group("editor").get::<String>("theme"),
+            Some("light".to_string())
+        );
+        assert_eq!(file.get_all::<AllSettings>(), Some(settings));
+
+        file.clear_changed();
+        assert!(file
+            .set_all(&file.get_all::<AllSettings>().unwrap())
+            .is_ok());
+        assert!(!file.is_changed());
+
+        assert!(matches!(
+            file.set_all(&[1, 2, 3]),
+            Err(PreferencesError::Serialize(_))
+        ));
+        assert!(file.get_all::<AllSettings>().is_some());
+    }
+
+    #[test]
+    fn test_preferences_file_changed_groups() {
+        let mut file = JsonPreferencesFile::new();
+        file.get_group_mut("window")
+            .unwrap()
+            .set("size", UVec2::new(800, 600));
+        file.get_group_mut("session")
+            .unwrap()
+            .get_group_mut("tabs")
+            .unwrap()
+            .set("count", 3);
+        assert!(file.is_changed());
+        assert!(file.is_group_changed("window"));
+        assert!(!file.is_group_changed("audio"));
+        assert_eq!(file.changed_groups(), ["session", "window"]);
+
+        file.clear_group_changed("window");
+        assert_eq!(file.changed_groups(), ["session"]);
+        assert!(file.is_changed());
+        file.clear_group_changed("session");
+        assert!(!file.is_changed());
+
+        file.set_path("audio.volume", 0.5).unwrap();
+        assert_eq!(file.changed_groups(), ["audio"]);
+        file.clear_changed();
+
+        // Changes outside of any group mark only the file.
+        file.root_mut().set("version", 2);
+        assert!(file.is_changed());
+        assert!(file.changed_groups().is_empty());
+    }
+
+    #[test]
+    fn test_preferences_group_mut_try_set_if_changed() {
+        let mut json = Map::new();
+        let changed = AtomicBool::new(false);
+        let mut group = JsonPreferencesGroupMut {
+            json: &mut json,
+            changed: &changed,
+            replaced: &AtomicBool::new(false),
+            validators: None,
+        };
+        // Maps with integer keys are stored with string keys.
+        let ids =
+            std::collections::BTreeMap::from([(1, "one".to_string()), (2, "two".to_string())]);
+        assert_eq!(group.try_set_if_changed("ids", &ids), Ok(()));
+        assert!(changed.load(Ordering::Relaxed));
+        assert_eq!(group.get("ids"), Some(ids.clone()));
+        changed.store(false, Ordering::Relaxed);
+        assert_eq!(group.try_set_if_changed("ids", &ids), Ok(()));
+        assert!(!changed.load(Ordering::Relaxed));
+
+        // Maps with keys which are not strings or integers are rejected.
+        let by_pos = std::collections::HashMap::from([(IVec2::new(1, 2), "a")]);
+        assert!(matches!(
+            group.try_set_if_changed("by_pos", &by_pos),
+            Err(PreferencesError::Serialize(_))
+        ));
+        group.set_if_changed("by_pos", &by_pos);
+        assert!(!changed.load(Ordering::Relaxed));
+
+        // Struct fields which are `None` are omitted.
+        let settings = EditorSettings {
+            theme: "dark".to_string(),
+            font_size: 12.0,
+            layout: Layout { sidebar: true },
+            last_file: None,
+        };
+        assert_eq!(group.try_set_if_changed("editor", &settings), Ok(()));
+        assert_eq!(group.get::<EditorSettings>("editor"), Some(settings));
+        assert!(group
+            .get_group("editor")
+            .unwrap()
+            .get::<String>("last_file")
+            .is_none());
+    }
+
+    #[test]
+    fn test_get_checked() {
+        let mut json = serde_json::Map::new();
+        let changed = AtomicBool::new(false);
+        let mut group = JsonPreferencesGroupMut {
+            json: &mut json,
+            changed: &changed,
+            replaced: &AtomicBool::new(false),
+            validators: None,
+        };
+        group.set("name", "editor");
+        group.set("count", 3);
+
+        assert_eq!(
+            group.get_checked::<String>("name"),
+            Ok("editor".to_string())
+        );
+        assert_eq!(group.get_checked::<i32>("missing"), Err(GetError::Missing));
+        assert!(matches!(
+            group.get_checked::<i32>("name"),
+            Err(GetError::TypeMismatch {
+                found: ValueKind::String,
+                ..
+            })
+        ));
+        assert!(group.get::<i32>("name").is_none());
+
+        let group = JsonPreferencesGroup { json: &json };
+        assert_eq!(group.get_checked::<i32>("count"), Ok(3));
+    }
+
+    #[test]
+    fn test_rename_key() {
+        let mut file = JsonPreferencesFile::new();
+        let mut window = file.get_group_mut("window").unwrap();
+        window.set("fullscreen", true);
+        window.set("vsync", false);
+        file.clear_changed();
+
+        let mut window = file.get_group_mut("window").unwrap();
+        assert!(!window.rename_key("missing", "window_mode", false));
+        assert!(!window.rename_key("fullscreen", "vsync", false));
+        assert!(!file.is_changed());
+
+        let mut window = file.get_group_mut("window").unwrap();
+        assert!(window.rename_key("fullscreen", "window_mode", false));
+        assert_eq!(window.get::<bool>("window_mode"), Some(true));
+        assert!(window.get::<bool>("fullscreen").is_none());
+        assert!(window.rename_key("window_mode", "vsync", true));
+        assert_eq!(window.get::<bool>("vsync"), Some(true));
+        assert!(file.is_group_changed("window"));
+        file.clear_changed();
+
+        assert!(!file.rename_group("missing", "display", false));
+        file.root_mut().set("version", 2);
+        file.clear_changed();
+        assert!(!file.rename_group("version", "display", false));
+        assert!(file.rename_group("window", "display", false));
+        assert_eq!(file.changed_groups(), ["display", "window"]);
+        assert_eq!(
+            file.path().group("display").get::<bool>("vsync"),
+            Some(true)
+        );
+        assert!(file.get_group("window").is_none());
+    }
+
+    #[test]
+    fn test_merge_from() {
+        let mut file: JsonPreferencesFile =
+            r#"{"audio": {"volume": 0.8, "mixer": {"music": 1.0}}, "video": {"mode": "window"}}"#
+                .parse()
+                .unwrap();
+        let defaults: JsonPreferencesFile = r#"{"audio": {"volume": 0.5, "muted": false, "mixer": {"music": 0.5, "sfx": 0.5}}, "video": {"mode": {"width": 800}}, "input": {"sensitivity": 2}}"#.parse().unwrap();
+
+        file.merge_from(&defaults.content(), false);
+        assert_eq!(file.changed_groups(), ["audio", "input"]);
+        let audio = file.path().group("audio");
+        assert_eq!(audio.get::<f32>("volume"), Some(0.8));
+        assert_eq!(audio.get::<bool>("muted"), Some(false));
+        assert_eq!(audio.group("mixer").get::<f32>("music"), Some(1.0));
+        assert_eq!(audio.group("mixer").get::<f32>("sfx"), Some(0.5));
+        assert_eq!(
+            file.path().group("video").get::<String>("mode"),
+            Some("window".to_string())
+        );
+        assert_eq!(
+            file.path().group("input").get::<i32>("sensitivity"),
+            Some(2)
+        );
+
+        // Merging again changes nothing.
+        file.clear_changed();
+        file.merge_from(&defaults.content(), false);
+        assert!(!file.is_changed());
+
+        file.merge_from(&defaults.content(), true);
+        assert_eq!(file.changed_groups(), ["audio", "video"]);
+        let audio = file.path().group("audio");
+        assert_eq!(audio.get::<f32>("volume"), Some(0.5));
+        assert_eq!(audio.group("mixer").get::<f32>("music"), Some(0.5));
+        assert_eq!(
+            file.path().group("video").group("mode").get::<i32>("width"),
+            Some(800)
+        );
+    }
+
+    #[test]
+    fn test_entry() {
+        let mut json = serde_json::Map::new();
+        let changed = AtomicBool::new(false);
+        let mut group = JsonPreferencesGroupMut {
+            json: &mut json,
+            changed: &changed,
+            replaced: &AtomicBool::new(false),
+            validators: None,
+        };
+
+        let entry = group.entry("launches");
+        assert_eq!(entry.key(), "launches");
+        assert!(!entry.is_occupied());
+        assert_eq!(entry.and_modify(|n: &mut i32| *n += 1).or_insert(1), 1);
+        assert!(changed.swap(false, Ordering::Relaxed));
+
+        assert_eq!(
+            group
+                .entry("launches")
+                .and_modify(|n: &mut i32| *n += 1)
+                .or_insert(1),
+            2
+        );
+        assert!(changed.swap(false, Ordering::Relaxed));
+
+        // Reading an existing value does not mark the group as changed.
+        assert!(group.entry("launches").is_occupied());
+        assert_eq!(group.entry("launches").or_default::<i32>(), 2);
+        assert_eq!(
+            group
+                .entry("launches")
+                .or_insert_with(|| -> i32 { unreachable!() }),
+            2
+        );
+        assert!(!changed.load(Ordering::Relaxed));
+
+        assert_eq!(group.entry("name").or_default::<String>(), "");
+        assert_eq!(group.get::<String>("name"), Some(String::new()));
+        assert!(changed.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_group_names() {
+        let mut file = JsonPreferencesFile::new();
+        assert!(file.group_names().is_empty());
+        file.get_group_mut("window").unwrap();
+        file.get_group_mut("audio").unwrap().set("volume", 0.5);
+        file.root_mut().set("version", 2);
+        let mut names = file.group_names();
+        names.sort_unstable();
+        assert_eq!(names, ["audio", "window"]);
+    }
+
+    #[test]
+    fn test_get_group_at_path() {
+        let mut file = JsonPreferencesFile::new();
+        assert!(file.get_group_at_path(&["editor", "panels"]).is_none());
+
+        file.get_group_at_path_mut(&["editor", "panels", "outline"])
+            .unwrap()
+            .set("visible", true);
+        assert_eq!(file.changed_groups(), ["editor"]);
+        assert_eq!(
+            file.get_group_at_path(&["editor", "panels", "outline"])
+                .unwrap()
+                .get::<bool>("visible"),
+            Some(true)
+        );
+
+        file.root_mut().set("version", 2);
+        assert!(file.get_group_at_path_mut(&["version", "minor"]).is_none());
+        assert_eq!(
+            file.get_group_at_path(&[]).unwrap().get::<i32>("version"),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut file = JsonPreferencesFile::new();
+        let mut session = file.get_group_mut("session").unwrap();
+        session.set("project", "demo");
+        session.get_group_mut("tabs").unwrap().set("count", 3);
+        file.get_group_mut("audio").unwrap();
+        file.clear_changed();
+
+        let mut session = file.get_group_mut("session").unwrap();
+        session.clear();
+        assert!(session.get::<String>("project").is_none());
+        assert!(session.get_group("tabs").is_none());
+        assert_eq!(file.changed_groups(), ["session"]);
+
+        // The group is still present, and clearing it again does nothing.
+        file.clear_changed();
+        let mut session = file.get_group_mut("session").unwrap();
+        session.clear();
+        session.set("project", "other");
+        assert_eq!(file.changed_groups(), ["session"]);
+        assert!(file.get_group("session").is_some());
+
+        file.root_mut().set("version", 2);
+        file.clear_changed();
+        file.clear();
+        assert_eq!(file.changed_groups(), ["session"]);
+        assert!(file.is_changed());
+        assert!(file.get_group("session").is_some());
+        assert!(file.get_group("audio").is_some());
+        assert!(file.path().get::<i32>("version").is_none());
+    }
+
+    #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Keybind {
+        action: String,
+        key: String,
+        modifiers: Vec<String>,
+        repeat: Option<u32>,
+    }
+
+    #[test]
+    fn test_array_of_structs() {
+        let jump = Keybind {
+            action: "jump".to_string(),
+            key: "Space".to_string(),
+            modifiers: Vec::new(),
+            repeat: None,
+        };
+        let save = Keybind {
+            action: "save".to_string(),
+            key: "S".to_string(),
+            modifiers: vec!["Ctrl".to_string()],
+            repeat: Some(2),
+        };
+
+        for binds in [
+            Vec::new(),
+            vec![jump.clone()],
+            vec![jump.clone(), save.clone()],
+        ] {
+            let mut file = JsonPreferencesFile::new();
+            file.get_group_mut("input").unwrap().set("binds", &binds);
+            assert_eq!(
+                file.path().group("input").get::<Vec<Keybind>>("binds"),
+                Some(binds.clone())
+            );
+
+            // The list also survives a round trip through the file format.
+            let reloaded: JsonPreferencesFile = file.encode().unwrap().parse().unwrap();
+            assert_eq!(
+                reloaded.path().group("input").get::<Vec<Keybind>>("binds"),
+                Some(binds)
+            );
+        }
+
+        let mut file = JsonPreferencesFile::new();
+        let mut input = file.get_group_mut("input").unwrap();
+        input.push_item("binds", &jump).unwrap();
+        input.push_item("binds", &save).unwrap();
+        assert_eq!(input.list_len("binds"), Some(2));
+        assert_eq!(input.get_item::<Keybind>("binds", 1), Some(save));
+    }
+
+    #[test]
+    #[cfg(feature = "raw-access")]
+    fn test_raw_access() {
+        let mut json = serde_json::Map::new();
+        let changed = AtomicBool::new(false);
+        let mut group = JsonPreferencesGroupMut {
+            json: &mut json,
+            changed: &changed,
+            replaced: &AtomicBool::new(false),
+            validators: None,
+        };
+        assert!(group.get_raw("size").is_none());
+        group.set_raw("size", serde_json::json!([1, 2]));
+        assert!(changed.swap(false, Ordering::Relaxed));
+        assert_eq!(group.get::<Vec<i32>>("size"), Some(vec![1, 2]));
+        group.set_raw("size", serde_json::json!([1, 2]));
+        assert!(!changed.load(Ordering::Relaxed));
+
+        let group = JsonPreferencesGroup { json: &json };
+        assert_eq!(group.get_raw("size"), Some(&serde_json::json!([1, 2])));
+    }
+
+    #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Account {
+        name: String,
+        steam_id: u64,
+    }
+
+    #[test]
+    fn test_large_unsigned_integers() {
+        let account = Account {
+            name: "player".to_string(),
+            steam_id: u64::MAX,
+        };
+        let mut file = JsonPreferencesFile::new();
+        let mut steam = file.get_group_mut("steam").unwrap();
+        steam.set("id", u64::MAX);
+        steam.set("id128", u128::from(u64::MAX));
+        steam.set("account", &account);
+        assert_eq!(steam.get::<u64>("id"), Some(u64::MAX));
+        assert_eq!(steam.get::<u128>("id128"), Some(u128::from(u64::MAX)));
+        assert_eq!(steam.get::<Account>("account"), Some(account.clone()));
+
+        // Values too large for an i64 are stored as decimal strings.
+        assert_eq!(
+            steam.get::<String>("id"),
+            Some("18446744073709551615".to_string())
+        );
+
+        let reloaded: JsonPreferencesFile = file.encode().unwrap().parse().unwrap();
+        let steam = reloaded.path().group("steam");
+        assert_eq!(steam.get::<u64>("id"), Some(u64::MAX));
+        assert_eq!(steam.get::<Account>("account"), Some(account));
+
+        // Values written as plain integers are still readable.
+        let legacy: JsonPreferencesFile = r#"{"steam": {"id": 42, "hash": 18446744073709551615}}"#
+            .parse()
+            .unwrap();
+        let steam = legacy.path().group("steam");
+        assert_eq!(steam.get::<u64>("id"), Some(42));
+        assert_eq!(steam.get::<u64>("hash"), Some(u64::MAX));
+    }
+
+    #[test]
+    fn test_reserved_keys_hidden() {
+        let mut file = JsonPreferencesFile::new();
+        let mut audio = file.get_group_mut("audio").unwrap();
+        audio.set("_version", 2);
+        audio.set("volume", 0.5);
+        audio.set("_comment", "Edited by hand");
+        audio.set("muted", false);
+
+        assert_eq!(audio.keys().collect::<Vec<_>>(), ["muted", "volume"]);
+        assert_eq!(audio.keys_all().count(), 4);
+
+        let audio = file.get_group("audio").unwrap();
+        assert_eq!(audio.keys().collect::<Vec<_>>(), ["muted", "volume"]);
+        assert_eq!(
+            audio.iter().map(|(key, _)| key).collect::<Vec<_>>(),
+            ["muted", "volume"]
+        );
+        assert_eq!(audio.entries().count(), 4);
+        assert_eq!(audio.get::<i32>("_version"), Some(2));
+    }
+
+    #[test]
+    fn test_try_set_invalid_key() {
+        let mut file = JsonPreferencesFile::new();
+        let mut audio = file.get_group_mut("audio").unwrap();
+        assert!(matches!(
+            audio.try_set("", 0.5),
+            Err(PreferencesError::InvalidKey { .. })
+        ));
+        assert!(matches!(
+            audio.try_set(&"k".repeat(crate::MAX_KEY_LENGTH + 1), 0.5),
+            Err(PreferencesError::InvalidKey { .. })
+        ));
+        assert!(matches!(
+            audio.try_set_if_changed("master.volume", 0.5),
+            Err(PreferencesError::InvalidKey { .. })
+        ));
+        assert_eq!(audio.keys_all().count(), 0);
+    }
+
+    #[test]
+    fn test_non_finite_floats_rejected() {
+        let mut file = JsonPreferencesFile::new();
+        let mut audio = file.get_group_mut("audio").unwrap();
+        audio.set("volume", 0.5);
+        audio.set("position", Vec3::ONE);
+        file.clear_changed();
+
+        let mut audio = file.get_group_mut("audio").unwrap();
+        for bad in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert!(audio.try_set("volume", bad).is_err());
+            assert!(audio.try_set("volume", f64::from(bad)).is_err());
+            assert!(audio.try_set_if_changed("volume", bad).is_err());
+            assert!(audio.try_set("position", Vec3::new(1.0, bad, 1.0)).is_err());
+            assert!(audio
+                .try_set_if_changed("position", Vec3::new(bad, 1.0, 1.0))
+                .is_err());
+        }
+        assert_eq!(audio.get::<f32>("volume"), Some(0.5));
+        assert_eq!(audio.get::<Vec3>("position"), Some(Vec3::ONE));
+        assert!(!file.is_changed());
+    }
+
+    #[test]
+    fn test_color() {
+        let mut file = JsonPreferencesFile::new();
+        let mut theme = file.get_group_mut("theme").unwrap();
+        theme.set_color("accent", Color::srgba_u8(32, 64, 128, 255));
+        theme.set("legacy", [0.0, 1.0, 0.0]);
+        assert_eq!(theme.get::<String>("accent"), Some("#204080ff".to_string()));
+
+        let reloaded: JsonPreferencesFile = file.encode().unwrap().parse().unwrap();
+        let theme = reloaded.get_group("theme").unwrap();
+        assert_eq!(
+            theme.get_color("accent"),
+            Some(Color::srgba_u8(32, 64, 128, 255))
+        );
+        assert_eq!(theme.get_color("legacy"), Some(Color::srgb(0.0, 1.0, 0.0)));
+        assert_eq!(theme.get_color("missing"), None);
+
+        file.clear_changed();
+        file.get_group_mut("theme")
+            .unwrap()
+            .set_color("accent", Color::srgba_u8(32, 64, 128, 255));
+        assert!(!file.is_changed());
+    }
+
+    #[test]
+    fn test_keybindings() {
+        use bevy::input::{keyboard::KeyCode, mouse::MouseButton};
+
+        use crate::Binding;
+
+        let mut bindings = BindingMap::default();
+        bindings.insert(
+            "jump".to_string(),
+            vec![Binding::key(KeyCode::Space), Binding::key(KeyCode::KeyW)],
+        );
+        bindings.insert(
+            "save".to_string(),
+            vec![Binding::key(KeyCode::KeyS).with_ctrl()],
+        );
+        bindings.insert("fire".to_string(), vec![Binding::mouse(MouseButton::Left)]);
+
+        let mut file = JsonPreferencesFile::new();
+        let mut input = file.get_group_mut("input").unwrap();
+        input.set_keybindings("bindings", &bindings);
+        file.clear_changed();
+        file.get_group_mut("input")
+            .unwrap()
+            .set_keybindings("bindings", &bindings);
+        assert!(!file.is_changed());
+        let reloaded: JsonPreferencesFile = file.encode().unwrap().parse().unwrap();
+        let input = reloaded.get_group("input").unwrap();
+        assert_eq!(input.get_keybindings("bindings"), Some(bindings));
+        assert_eq!(input.get_keybindings("missing"), None);
+
+        // Unknown names are skipped without losing the rest of the map.
+        let file: JsonPreferencesFile = r#"{
+            "input": {
+                "bindings": {
+                    "jump": ["Space", "KeyBogus"],
+                    "save": "Ctrl+KeyS",
+                    "fire": ["Mouse1", 3]
+                }
+            }
+        }"#
+        .parse()
+        .unwrap();
+        let bindings = file
+            .get_group("input")
+            .unwrap()
+            .get_keybindings("bindings")
+            .unwrap();
+        assert_eq!(bindings["jump"], vec![Binding::key(KeyCode::Space)]);
+        assert_eq!(
+            bindings["save"],
+            vec![Binding::key(KeyCode::KeyS).with_ctrl()]
+        );
+        assert_eq!(bindings["fire"], vec![Binding::mouse(MouseButton::Left)]);
+    }
+
+    #[test]
+    fn test_duration() {
+        use std::time::Duration;
+
+        let mut file = JsonPreferencesFile::new();
+        let mut replay = file.get_group_mut("replay").unwrap();
+        replay.set_duration("length", Duration::from_secs(90));
+        replay.set("legacy", 2.5);
+        replay.set("negative", "-3s");
+        assert_eq!(replay.get::<String>("length"), Some("90s".to_string()));
+
+        let reloaded: JsonPreferencesFile = file.encode().unwrap().parse().unwrap();
+        let replay = reloaded.get_group("replay").unwrap();
+        assert_eq!(replay.get_duration("length"), Some(Duration::from_secs(90)));
+        assert_eq!(
+            replay.get_duration("legacy"),
+            Some(Duration::from_millis(2500))
+        );
+        assert_eq!(replay.get_duration("negative"), None);
+        assert_eq!(replay.get_duration("missing"), None);
+    }
+
+    #[test]
+    fn test_bytes() {
+        let mut file = JsonPreferencesFile::new();
+        let mut layout = file.get_group_mut("layout").unwrap();
+        layout.set_bytes("dock", &[1, 2, 3, 250]);
+        layout.set("legacy", vec![1u8, 2, 3]);
+        layout.set("invalid", "%%%");
+        assert_eq!(layout.get::<String>("dock"), Some("AQID+g==".to_string()));
+
+        let reloaded: JsonPreferencesFile = file.encode().unwrap().parse().unwrap();
+        let layout = reloaded.get_group("layout").unwrap();
+        assert_eq!(layout.get_bytes("dock"), Some(vec![1, 2, 3, 250]));
+        assert_eq!(layout.get_bytes("legacy"), Some(vec![1, 2, 3]));
+        assert_eq!(layout.get_bytes("invalid"), None);
+        assert_eq!(layout.get_bytes("missing"), None);
+    }
+
+    #[test]
+    fn test_enum_compatibility() {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        enum QualityV1 {
+            Low,
+            High,
+        }
+
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        enum QualityV2 {
+            Low,
+            High,
+            Ultra,
+        }
+
+        // A newer version writes a variant which the older one doesn't know.
+        let mut file = JsonPreferencesFile::new();
+        let mut video = file.get_group_mut("video").unwrap();
+        video.set("quality", QualityV2::Ultra);
+        file.clear_changed();
+
+        // The older version falls back, and writing back the fallback keeps the file as it was.
+        let mut video = file.get_group_mut("video").unwrap();
+        let quality = video.get_enum_or("quality", QualityV1::Low);
+        assert_eq!(quality, QualityV1::Low);
+        video.set_preserving_unknown("quality", quality);
+        assert_eq!(video.get::<String>("quality"), Some("Ultra".to_string()));
+        assert!(!file.is_changed());
+
+        // Reading it again in the newer version gets the user's choice back.
+        let video = file.get_group("video").unwrap();
+        assert_eq!(
+            video.get_enum_or("quality", QualityV2::Low),
+            QualityV2::Ultra
+        );
+
+        // Known values are replaced as usual, and so is an unknown one the user changes.
+        let mut video = file.get_group_mut("video").unwrap();
+        video.set_preserving_unknown("preset", QualityV1::High);
+        assert_eq!(video.get_enum_or("preset", QualityV1::Low), QualityV1::High);
+        video.set_preserving_unknown("preset", QualityV1::Low);
+        assert_eq!(video.get_enum_or("preset", QualityV1::High), QualityV1::Low);
+        video.set("quality", QualityV1::High);
+        assert_eq!(
+            video.get_enum_or("quality", QualityV1::Low),
+            QualityV1::High
+        );
+
+        // A variant removed in a newer version is kept the same way.
+        video.set("quality", QualityV2::Ultra);
+        video.set("legacy", "Medium");
+        assert_eq!(video.get_enum_or("legacy", QualityV2::Low), QualityV2::Low);
+        video.set_preserving_unknown("legacy", QualityV2::Low);
+        assert_eq!(video.get::<String>("legacy"), Some("Medium".to_string()));
+    }
+
+    #[test]
+    fn test_get_into() {
+        let mut file = JsonPreferencesFile::new();
+        let mut window = file.get_group_mut("window").unwrap();
+        window.set("size", [800, 600]);
+        window.set("title", "Game");
+
+        let mut size = [0, 0];
+        assert!(window.get_into("size", &mut size));
+        assert_eq!(size, [800, 600]);
+        assert!(!window.get_into("title", &mut size));
+        assert!(!window.get_into("missing", &mut size));
+        assert_eq!(size, [800, 600]);
+
+        let window = file.get_group("window").unwrap();
+        let mut title = String::new();
+        assert!(window.get_into("title", &mut title));
+        assert_eq!(title, "Game");
+
+        // Existing buffers are reused.
+        let mut sizes: Vec<u32> = Vec::with_capacity(64);
+        assert!(window.get_into("size", &mut sizes));
+        assert_eq!(sizes, [800, 600]);
+        assert!(sizes.capacity() >= 64);
+        let mut title = String::with_capacity(64);
+        assert!(window.get_into("title", &mut title));
+        assert_eq!(title, "Game");
+        assert!(title.capacity() >= 64);
+    }
+
+    #[test]
+    fn test_diff() {
+        const OLD: &str =
+            r#"{"window": {"size": [800, 600], "title": "Game", "maximized": false}}"#;
+        let mut file: JsonPreferencesFile = OLD.parse().unwrap();
+        let old = file.content();
+
+        let mut window = file.get_group_mut("window").unwrap();
+        window.set("size", "fullscreen");
+        window.set("maximized", true);
+        window.remove("title");
+        let mut audio = file.get_group_mut("audio").unwrap();
+        let mut effects = audio.get_group_mut("effects").unwrap();
+        effects.set("reverb", 0.5);
+
+        let diff = file.diff(&old);
+        let effects = PrefsValue::Table([("reverb".to_string(), PrefsValue::Float(0.5))].into());
+        assert_eq!(
+            diff.added().collect::<Vec<_>>(),
+            [(
+                "audio",
+                &PrefsValue::Table([("effects".to_string(), effects)].into())
+            )]
+        );
+        assert_eq!(
+            diff.removed().collect::<Vec<_>>(),
+            [("window.title", &PrefsValue::String("Game".to_string()))]
+        );
+        assert_eq!(
+            diff.modified().map(|(path, ..)| path).collect::<Vec<_>>(),
+            ["window.maximized", "window.size"]
+        );
+        assert_eq!(
+            diff.changes["window.size"],
+            crate::PrefsChange::Modified {
+                old: PrefsValue::Array(vec![PrefsValue::Integer(800), PrefsValue::Integer(600)]),
+                new: PrefsValue::String("fullscreen".to_string()),
+            }
+        );
+        assert!(file.diff(&file.content()).is_empty());
+
+        // Applying the diff to the old contents brings them up to date.
+        let mut other: JsonPreferencesFile = OLD.parse().unwrap();
+        other.apply_diff(&diff).unwrap();
+        assert!(other.diff(&file.content()).is_empty());
+        assert_eq!(other.changed_groups(), ["audio", "window"]);
+    }
+
+    #[test]
+    fn test_encode_sorted() {
+        let mut first = JsonPreferencesFile::new();
+        let mut video = first.get_group_mut("video").unwrap();
+        video.set("vsync", true);
+        video.set("gamma", 1.2);
+        first.get_group_mut("audio").unwrap().set("volume", 0.5);
+
+        let mut second = JsonPreferencesFile::new();
+        second.get_group_mut("audio").unwrap().set("volume", 0.5);
+        let mut video = second.get_group_mut("video").unwrap();
+        video.set("gamma", 1.2);
+        video.set("vsync", true);
+
+        let encoded = first.encode().unwrap();
+        assert_eq!(encoded, second.encode().unwrap());
+        assert!(encoded.find("audio") < encoded.find("video"));
+        assert!(encoded.find("gamma") < encoded.find("vsync"));
+
+        // Saving again after a load gives the same bytes.
+        let reloaded: JsonPreferencesFile = encoded.parse().unwrap();
+        assert_eq!(reloaded.encode().unwrap(), encoded);
+    }
 }
@@ -0,0 +1,52 @@
+//! Reading and writing preferences files in [JSON5](https://json5.org) format.
+//!
+//! JSON5 files are loaded into the same in-memory representation as TOML files, so the
+//! [`prefs_toml`](crate::prefs_toml) API is used to access them. Only the file format differs:
+//! JSON5 allows comments, trailing commas and unquoted keys, which makes hand-edited files
+//! more forgiving.
+
+use serde_json::Value as JsonValue;
+
+use crate::{PreferencesError, PrefsValue};
+
+/// Parse the text of a JSON5 preferences file. Nulls are dropped, since they can't be
+/// represented in a preferences table.
+pub(crate) fn parse_json5(text: &str) -> Result<toml::Table, String> {
+    let value = json5::from_str::<JsonValue>(text).map_err(|e| e.to_string())?;
+    match PrefsValue::from(&value).into_toml() {
+        Some(toml::Value::Table(table)) => Ok(table),
+        _ => Err("Preferences file must be an object".to_string()),
+    }
+}
+
+/// Serialize a preferences table in JSON5 format. Pretty-printed JSON is valid JSON5, and is
+/// written with indentation so that the file is easy to edit by hand.
+pub(crate) fn serialize_json5(table: &toml::Table) -> Result<String, PreferencesError> {
+    serde_json::to_string_pretty(&PrefsValue::from(table))
+        .map_err(|e| PreferencesError::Serialize(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json5_round_trip() {
+        let text = r#"{
+            // Window placement, restored on startup.
+            window: {
+                position: [100, 200,],
+                'title': "Main",
+            },
+            audio: { volume: 0.5, muted: false },
+        }"#;
+        let table = parse_json5(text).unwrap();
+        assert_eq!(table["window"]["position"][1].as_integer(), Some(200));
+        assert_eq!(table["window"]["title"].as_str(), Some("Main"));
+        assert_eq!(table["audio"]["volume"].as_float(), Some(0.5));
+
+        let saved = serialize_json5(&table).unwrap();
+        assert_eq!(parse_json5(&saved).unwrap(), table);
+        assert!(parse_json5("[1, 2]").is_err());
+    }
+}
@@ -0,0 +1,42 @@
+//! Reading and writing preferences files on disk in compact JSON format.
+//!
+//! Like JSON5 files, JSON files are loaded into the same in-memory representation as TOML files,
+//! so the [`prefs_toml`](crate::prefs_toml) API is used to access them. They are written without
+//! indentation, which suits machine-generated files that are not meant to be edited by hand.
+
+use serde_json::Value as JsonValue;
+
+use crate::{PreferencesError, PrefsValue};
+
+/// Parse the text of a JSON preferences file. Nulls are dropped, since they can't be
+/// represented in a preferences table.
+pub(crate) fn parse_json(text: &str) -> Result<toml::Table, String> {
+    let value = serde_json::from_str::<JsonValue>(text).map_err(|e| e.to_string())?;
+    match PrefsValue::from(&value).into_toml() {
+        Some(toml::Value::Table(table)) => Ok(table),
+        _ => Err("Preferences file must be an object".to_string()),
+    }
+}
+
+/// Serialize a preferences table as compact JSON.
+pub(crate) fn serialize_json(table: &toml::Table) -> Result<String, PreferencesError> {
+    serde_json::to_string(&PrefsValue::from(table))
+        .map_err(|e| PreferencesError::Serialize(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_round_trip() {
+        let table = parse_json(r#"{"tiles":{"count":12,"seed":"a1b2"},"scale":1.5}"#).unwrap();
+        assert_eq!(table["tiles"]["count"].as_integer(), Some(12));
+        assert_eq!(table["scale"].as_float(), Some(1.5));
+
+        let saved = serialize_json(&table).unwrap();
+        assert!(!saved.contains('\n'));
+        assert_eq!(parse_json(&saved).unwrap(), table);
+        assert!(parse_json("[1, 2]").is_err());
+    }
+}
@@ -0,0 +1,345 @@
+use bevy::{
+    app::{App, PostUpdate, PreStartup, PreUpdate},
+    ecs::{
+        change_detection::DetectChanges,
+        message::MessageReader,
+        resource::Resource,
+        schedule::IntoScheduleConfigs,
+        system::{Commands, Local, Res, ResMut},
+    },
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    Preferences, PreferencesFileChanged, PreferencesSchema, PreferencesSystem, StartAutosaveTimer,
+};
+
+/// Extension methods for [`App`] which keep a resource in sync with a preferences file.
+pub trait PreferencesAppExt {
+    /// Insert the resource `R`, read from the preferences file described by its
+    /// [`PreferencesSchema`], and write it back to the file whenever it changes.
+    ///
+    /// The resource is loaded in `PreStartup`, so it is available to startup systems. It is read
+    /// from the whole file with `get_all`, so a file which is missing fields only loads if the
+    /// struct has `#[serde(default)]`; otherwise `R::default()` is used. Changes are written in
+    /// `PostUpdate`, so that changes made by any system during the frame are included, and start
+    /// the autosave timer. The fields are merged into the file key by key, so the file is only
+    /// marked as changed if a value is different, and keys in the file which are not fields of
+    /// `R`, such as a `_version` key, are kept.
+    ///
+    /// This needs a [`Preferences`] resource, such as the one inserted by
+    /// [`PreferencesPlugin`](crate::PreferencesPlugin), and [`AutosavePrefsPlugin`](crate::AutosavePrefsPlugin)
+    /// for changes to be saved.
+    fn insert_resource_from_prefs<R>(&mut self) -> &mut Self
+    where
+        R: Resource + Default + PreferencesSchema + Serialize + DeserializeOwned;
+
+    /// Bind the resource `R` to its preferences file in both directions. Like
+    /// [`insert_resource_from_prefs`](Self::insert_resource_from_prefs), the resource is loaded
+    /// at startup, and each field is written back with `set_if_changed` in `PostUpdate` whenever
+    /// the resource changes, so an unchanged value never causes a save. In addition, the
+    /// resource is read again in `PreUpdate` whenever the file is reloaded after being changed by
+    /// another program (see [`PreferencesFileChanged`]), so that edits to the file take effect.
+    ///
+    /// The resource is converted with serde rather than `Reflect`, like every other value stored
+    /// in a preferences file, so that it is stored in the same format as values written with
+    /// `set` and doesn't need the app's type registry.
+    fn bind_resource_to_prefs<R>(&mut self) -> &mut Self
+    where
+        R: Resource + Default + PreferencesSchema + Serialize + DeserializeOwned;
+}
+
+impl PreferencesAppExt for App {
+    fn insert_resource_from_prefs<R>(&mut self) -> &mut Self
+    where
+        R: Resource + Default + PreferencesSchema + Serialize + DeserializeOwned,
+    {
+        self.add_systems(PreStartup, load_resource_from_prefs::<R>)
+            .add_systems(PostUpdate, save_resource_to_prefs::<R>)
+    }
+
+    fn bind_resource_to_prefs<R>(&mut self) -> &mut Self
+    where
+        R: Resource + Default + PreferencesSchema + Serialize + DeserializeOwned,
+    {
+        self.insert_resource_from_prefs::<R>()
+            .add_message::<PreferencesFileChanged>()
+            .add_systems(
+                PreUpdate,
+                reload_resource_from_prefs::<R>.after(PreferencesSystem::LoadPreferences),
+            )
+    }
+}
+
+fn load_resource_from_prefs<R>(mut prefs: ResMut<Preferences>, mut commands: Commands)
+where
+    R: Resource + Default + PreferencesSchema + DeserializeOwned,
+{
+    let resource = prefs
+        .get_schema_mut::<R>()
+        .and_then(|file| file.get_all::<R>())
+        .unwrap_or_default();
+    commands.insert_resource(resource);
+}
+
+fn reload_resource_from_prefs<R>(
+    mut changes: MessageReader<PreferencesFileChanged>,
+    prefs: Res<Preferences>,
+    mut resource: ResMut<R>,
+) where
+    R: Resource + PreferencesSchema + DeserializeOwned,
+{
+    let reloaded = changes
+        .read()
+        .filter(|change| change.filename == R::filename())
+        .count()
+        > 0;
+    if !reloaded {
+        return;
+    }
+    if let Some(value) = prefs
+        .peek(R::filename())
+        .and_then(|file| file.get_all::<R>())
+    {
+        *resource = value;
+    }
+}
+
+fn save_resource_to_prefs<R>(
+    resource: Res<R>,
+    mut prefs: ResMut<Preferences>,
+    mut commands: Commands,
+    mut has_run: Local<bool>,
+) where
+    R: Resource + Default + PreferencesSchema + Serialize + DeserializeOwned,
+{
+    if !resource.is_changed() {
+        return;
+    }
+    // The file was loaded, and its schema registered, by `load_resource_from_prefs`.
+    let Some(file) = prefs.get_mut(R::filename()) else {
+        return;
+    };
+    // On the first run the resource counts as changed because it was just inserted. Skip it if
+    // it still holds the loaded value, so that fields filled in by `#[serde(default)]` don't
+    // cause a save, but keep changes made by startup systems or the first frame.
+    if !std::mem::replace(&mut *has_run, true) {
+        let loaded = file.get_all::<R>().unwrap_or_default();
+        if serde_json::to_value(&loaded).ok() == serde_json::to_value(&*resource).ok() {
+            return;
+        }
+    }
+    file.root_mut().write(&*resource);
+    if file.is_changed() {
+        commands.queue(StartAutosaveTimer);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use bevy::{
+        app::{Startup, Update},
+        time::Time,
+    };
+    use serde::Deserialize;
+
+    use crate::{AutosavePrefsPlugin, PreferencesGroup, PreferencesGroupMut, StoreChain};
+
+    use super::*;
+
+    #[derive(Resource, Default, Serialize, Deserialize, Debug, PartialEq)]
+    #[serde(default)]
+    struct AudioSettings {
+        volume: f32,
+        muted: bool,
+    }
+
+    impl PreferencesSchema for AudioSettings {
+        fn filename() -> &'static str {
+            "audio"
+        }
+
+        fn apply_defaults(file: &mut PreferencesGroupMut) {
+            file.set("volume", 0.8);
+        }
+
+        fn validate(_file: &PreferencesGroup) -> Result<(), Vec<String>> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_insert_resource_from_prefs() {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .insert_resource(Preferences::with_store(StoreChain::new(Vec::new())))
+            .add_plugins(AutosavePrefsPlugin)
+            .insert_resource_from_prefs::<AudioSettings>();
+        app.finish();
+        app.update();
+
+        assert_eq!(
+            *app.world().resource::<AudioSettings>(),
+            AudioSettings {
+                volume: 0.8,
+                muted: false
+            }
+        );
+
+        app.world_mut().resource_mut::<AudioSettings>().muted = true;
+        app.update();
+        let mut prefs = app.world_mut().resource_mut::<Preferences>();
+        let file = prefs.get("audio").unwrap();
+        assert_eq!(file.root().get::<bool>("muted"), Some(true));
+
+        // Writing back an unchanged resource does not change the file.
+        file.clear_changed();
+        app.world_mut().resource_mut::<AudioSettings>().muted = true;
+        app.update();
+        assert!(!app
+            .world()
+            .resource::<Preferences>()
+            .is_file_changed("audio"));
+    }
+
+    #[test]
+    fn test_startup_changes_written() {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .insert_resource(Preferences::with_store(StoreChain::new(Vec::new())))
+            .add_plugins(AutosavePrefsPlugin)
+            .insert_resource_from_prefs::<AudioSettings>()
+            .add_systems(Startup, |mut audio: ResMut<AudioSettings>| {
+                audio.muted = true;
+            });
+        app.finish();
+        app.update();
+
+        let mut prefs = app.world_mut().resource_mut::<Preferences>();
+        let file = prefs.get("audio").unwrap();
+        assert_eq!(file.root().get::<bool>("muted"), Some(true));
+        assert!(file.is_changed());
+    }
+
+    #[test]
+    fn test_loaded_resource_not_written() {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .insert_resource(Preferences::with_store(VersionedStore))
+            .add_plugins(AutosavePrefsPlugin)
+            .insert_resource_from_prefs::<AudioSettings>();
+        app.finish();
+        app.update();
+
+        // `muted` was filled in by `#[serde(default)]`, which doesn't change the file.
+        let mut prefs = app.world_mut().resource_mut::<Preferences>();
+        let file = prefs.get("audio").unwrap();
+        assert_eq!(file.root().get::<bool>("muted"), None);
+        assert!(!file.is_changed());
+    }
+
+    #[test]
+    fn test_bind_resource_writes_update_changes() {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .insert_resource(Preferences::with_store(StoreChain::new(Vec::new())))
+            .add_plugins(AutosavePrefsPlugin)
+            .bind_resource_to_prefs::<AudioSettings>()
+            .add_systems(
+                Update,
+                |mut audio: ResMut<AudioSettings>, mut frame: Local<u32>| {
+                    *frame += 1;
+                    if *frame == 1 {
+                        audio.volume = 0.25;
+                    }
+                },
+            );
+        app.finish();
+        app.update();
+
+        // The change made in `Update` is written back in `PostUpdate` of the same frame.
+        let mut prefs = app.world_mut().resource_mut::<Preferences>();
+        let file = prefs.get("audio").unwrap();
+        assert_eq!(file.root().get::<f32>("volume"), Some(0.25));
+        assert!(file.is_changed());
+    }
+
+    #[test]
+    fn test_bind_resource_reads_reloaded_file() {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .insert_resource(Preferences::with_store(StoreChain::new(Vec::new())))
+            .add_plugins(AutosavePrefsPlugin)
+            .bind_resource_to_prefs::<AudioSettings>();
+        app.finish();
+        app.update();
+
+        // Stand in for a file which was reloaded after another program changed it.
+        let mut prefs = app.world_mut().resource_mut::<Preferences>();
+        let file = prefs.get_mut("audio").unwrap();
+        file.root_mut().set("volume", 0.5);
+        file.root_mut().set("muted", false);
+        file.clear_changed();
+        app.world_mut().write_message(PreferencesFileChanged {
+            filename: "audio".to_owned(),
+        });
+        app.update();
+
+        // Writing the reloaded values back doesn't change the file.
+        assert_eq!(app.world().resource::<AudioSettings>().volume, 0.5);
+        assert!(!app
+            .world()
+            .resource::<Preferences>()
+            .is_file_changed("audio"));
+    }
+
+    /// Store which serves an audio file with a key which is not a field of `AudioSettings`.
+    struct VersionedStore;
+
+    impl crate::PreferencesStore for VersionedStore {
+        fn is_valid(&self) -> bool {
+            true
+        }
+
+        fn create(&self) -> crate::PreferencesFile {
+            crate::PreferencesFile::new()
+        }
+
+        fn load(&mut self, filename: &str) -> Option<crate::PreferencesFile> {
+            self.load_async(filename)
+        }
+
+        fn load_async(&self, _filename: &str) -> Option<crate::PreferencesFile> {
+            Some("_version = 2\nvolume = 0.5\n".parse().unwrap())
+        }
+
+        fn save(
+            &self,
+            _filename: &str,
+            _file: &crate::PreferencesFile,
+        ) -> Result<(), crate::PreferencesError> {
+            Ok(())
+        }
+
+        fn save_async(&self, _filename: &str, _file: crate::PreferencesFileContent) {}
+    }
+
+    #[test]
+    fn test_extra_keys_kept() {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .insert_resource(Preferences::with_store(VersionedStore))
+            .add_plugins(AutosavePrefsPlugin)
+            .insert_resource_from_prefs::<AudioSettings>();
+        app.finish();
+        app.update();
+        assert_eq!(app.world().resource::<AudioSettings>().volume, 0.5);
+
+        app.world_mut().resource_mut::<AudioSettings>().muted = true;
+        app.update();
+        let mut prefs = app.world_mut().resource_mut::<Preferences>();
+        let file = prefs.get("audio").unwrap();
+        assert_eq!(file.root().get::<bool>("muted"), Some(true));
+        assert_eq!(file.root().get::<i32>("_version"), Some(2));
+    }
+}
@@ -0,0 +1,97 @@
+use bevy::{
+    app::{App, Plugin},
+    ecs::{resource::Resource, system::Command, world::World},
+    prelude::{Deref, DerefMut},
+};
+
+use crate::{report_save_failures, Preferences};
+
+/// Preferences which belong to a single scene or level, such as camera angles or editor state,
+/// kept apart from the app's global [`Preferences`]. The files are stored under
+/// `{app_name}/scenes/{scene_name}`, and are used through `Res<ScenePreferences>` the same way
+/// as the global resource.
+#[derive(Resource, Deref, DerefMut)]
+pub struct ScenePreferences(pub Preferences);
+
+impl ScenePreferences {
+    /// Construct the preferences for a scene.
+    ///
+    /// # Arguments
+    /// * `app_name` - The name of the application, as passed to [`Preferences::new`].
+    /// * `scene_name` - The name of the scene.
+    pub fn new(app_name: &str, scene_name: &str) -> Self {
+        Self(Preferences::new(&format!("{app_name}/scenes/{scene_name}")))
+    }
+}
+
+/// Plugin which inserts a [`ScenePreferences`] resource for a scene when the app finishes
+/// building. Use [`UnloadScenePreferences`] when the scene is unloaded.
+///
+/// If a `ScenePreferences` resource has already been inserted, it is used instead.
+pub struct ScenePrefsPlugin {
+    app_name: String,
+    scene_name: String,
+}
+
+impl ScenePrefsPlugin {
+    /// Construct a new scene preferences plugin.
+    ///
+    /// # Arguments
+    /// * `app_name` - The name of the application, as passed to [`Preferences::new`].
+    /// * `scene_name` - The name of the scene.
+    pub fn new(app_name: &str, scene_name: &str) -> Self {
+        Self {
+            app_name: app_name.to_owned(),
+            scene_name: scene_name.to_owned(),
+        }
+    }
+}
+
+impl Plugin for ScenePrefsPlugin {
+    fn build(&self, _app: &mut App) {}
+
+    fn finish(&self, app: &mut App) {
+        let world = app.world_mut();
+        if !world.contains_resource::<ScenePreferences>() {
+            world.insert_resource(ScenePreferences::new(&self.app_name, &self.scene_name));
+        }
+    }
+}
+
+/// A Command which saves any changed scene preferences, then removes the [`ScenePreferences`]
+/// resource. Queue this when the scene is unloaded, and insert a new `ScenePreferences` for the
+/// next scene.
+pub struct UnloadScenePreferences;
+
+impl Command for UnloadScenePreferences {
+    fn apply(self, world: &mut World) {
+        if let Some(prefs) = world.remove_resource::<ScenePreferences>() {
+            let failed = prefs.save(false);
+            report_save_failures(world, failed);
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use crate::StoreChain;
+
+    use super::*;
+
+    #[test]
+    fn test_unload_scene_preferences() {
+        let mut app = App::new();
+        app.insert_resource(ScenePreferences(Preferences::with_store(StoreChain::new(
+            Vec::new(),
+        ))))
+        .add_plugins(ScenePrefsPlugin::new("org.example.test", "level1"));
+        app.finish();
+
+        let mut scene = app.world_mut().resource_mut::<ScenePreferences>();
+        let file = scene.get_mut("editor").unwrap();
+        file.get_group_mut("camera").unwrap().set("zoom", 2.0);
+
+        UnloadScenePreferences.apply(app.world_mut());
+        assert!(!app.world().contains_resource::<ScenePreferences>());
+    }
+}
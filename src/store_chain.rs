@@ -0,0 +1,217 @@
+use std::time::SystemTime;
+
+use crate::{
+    prefs::PreferencesStore, PreferencesError, PreferencesFile, PreferencesFileContent,
+    PreferencesFileCorrupt, PreferencesSaveFailed,
+};
+
+/// A boxed [`PreferencesStore`] which can be held in a [`StoreChain`].
+pub type BoxedPreferencesStore = Box<dyn PreferencesStore + Send + Sync + 'static>;
+
+/// PreferencesStore which combines several stores, tried in order. Files are loaded from the
+/// first store which has them, and saved to every valid store. For example, a chain of a
+/// user's home directory followed by a read-only store of bundled defaults will load the
+/// user's settings when they exist, fall back to the defaults otherwise, and save changes to
+/// the home directory.
+pub struct StoreChain {
+    stores: Vec<BoxedPreferencesStore>,
+}
+
+impl StoreChain {
+    /// Construct a new chain of preferences stores.
+    ///
+    /// # Arguments
+    /// * `stores` - The stores to use, in order of priority.
+    pub fn new(stores: Vec<BoxedPreferencesStore>) -> Self {
+        Self { stores }
+    }
+}
+
+impl PreferencesStore for StoreChain {
+    /// Returns true if any store in the chain is valid.
+    fn is_valid(&self) -> bool {
+        self.stores.iter().any(|store| store.is_valid())
+    }
+
+    /// Create a new, empty preferences file using the first valid store.
+    fn create(&self) -> PreferencesFile {
+        self.stores
+            .iter()
+            .find(|store| store.is_valid())
+            .map(|store| store.create())
+            .unwrap_or_default()
+    }
+
+    /// Save a preferences file to every valid store in the chain. If any store fails, the
+    /// others are still written, and the first error is returned.
+    fn save(&self, filename: &str, file: &PreferencesFile) -> Result<(), PreferencesError> {
+        let mut result = Ok(());
+        for store in self.stores.iter().filter(|store| store.is_valid()) {
+            if let Err(e) = store.save(filename, file) {
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+
+    /// Save a preferences file to every valid store in the chain, in another thread.
+    fn save_async(&self, filename: &str, file: PreferencesFileContent) {
+        for store in self.stores.iter().filter(|store| store.is_valid()) {
+            store.save_async(filename, file.clone());
+        }
+    }
+
+    /// Load a preferences file from the first store which has it.
+    fn load(&mut self, filename: &str) -> Option<PreferencesFile> {
+        self.stores
+            .iter_mut()
+            .find_map(|store| store.load(filename))
+    }
+
+    /// Load a preferences file from the first store which has it, in another thread.
+    fn load_async(&self, filename: &str) -> Option<PreferencesFile> {
+        self.stores
+            .iter()
+            .find_map(|store| store.load_async(filename))
+    }
+
+    /// Reload a preferences file from the first store which has it.
+    fn reload(&self, filename: &str) -> Option<PreferencesFile> {
+        self.stores.iter().find_map(|store| store.reload(filename))
+    }
+
+    /// The names of files which were changed externally in any store in the chain.
+    fn take_external_changes(&self) -> Vec<String> {
+        self.stores
+            .iter()
+            .flat_map(|store| store.take_external_changes())
+            .collect()
+    }
+
+    /// The files which could not be saved in another thread by any store in the chain.
+    fn take_save_failures(&self) -> Vec<PreferencesSaveFailed> {
+        self.stores
+            .iter()
+            .flat_map(|store| store.take_save_failures())
+            .collect()
+    }
+
+    /// The files which were saved in another thread by any store in the chain.
+    fn take_completed_saves(&self) -> Vec<(String, usize)> {
+        self.stores
+            .iter()
+            .flat_map(|store| store.take_completed_saves())
+            .collect()
+    }
+
+    /// The files which could not be parsed by any store in the chain.
+    fn take_corrupt_files(&self) -> Vec<PreferencesFileCorrupt> {
+        self.stores
+            .iter()
+            .flat_map(|store| store.take_corrupt_files())
+            .collect()
+    }
+
+    /// The modification time of a file in the first store which has one.
+    fn modified(&self, filename: &str) -> Option<SystemTime> {
+        self.stores
+            .iter()
+            .find_map(|store| store.modified(filename))
+    }
+
+    /// Pass the new app name to every store in the chain.
+    fn set_app_name(&mut self, app_name: &str) {
+        for store in &mut self.stores {
+            store.set_app_name(app_name);
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::*;
+
+    /// Store which serves at most one file, and counts saves.
+    struct TestStore {
+        valid: bool,
+        contents: Option<&'static str>,
+        saves: Arc<AtomicUsize>,
+    }
+
+    impl TestStore {
+        fn boxed(
+            valid: bool,
+            contents: Option<&'static str>,
+        ) -> (BoxedPreferencesStore, Arc<AtomicUsize>) {
+            let saves = Arc::new(AtomicUsize::new(0));
+            let store = TestStore {
+                valid,
+                contents,
+                saves: saves.clone(),
+            };
+            (Box::new(store), saves)
+        }
+    }
+
+    impl PreferencesStore for TestStore {
+        fn is_valid(&self) -> bool {
+            self.valid
+        }
+
+        fn create(&self) -> PreferencesFile {
+            PreferencesFile::new()
+        }
+
+        fn load(&mut self, filename: &str) -> Option<PreferencesFile> {
+            self.load_async(filename)
+        }
+
+        fn load_async(&self, _filename: &str) -> Option<PreferencesFile> {
+            self.contents.map(|s| s.parse().unwrap())
+        }
+
+        fn save(&self, _filename: &str, _file: &PreferencesFile) -> Result<(), PreferencesError> {
+            self.saves.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn save_async(&self, _filename: &str, _file: PreferencesFileContent) {
+            self.saves.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn modified(&self, _filename: &str) -> Option<SystemTime> {
+            self.contents
+                .map(|_| SystemTime::UNIX_EPOCH + Duration::from_secs(1000))
+        }
+    }
+
+    #[test]
+    fn test_store_chain() {
+        let (user, user_saves) = TestStore::boxed(true, None);
+        let (broken, broken_saves) = TestStore::boxed(false, None);
+        let (defaults, defaults_saves) = TestStore::boxed(true, Some("[audio]\nvolume = 0.5\n"));
+        let mut chain = StoreChain::new(vec![user, broken, defaults]);
+        assert!(chain.is_valid());
+
+        let file = chain.load("settings").unwrap();
+        assert_eq!(file.path().group("audio").get::<f32>("volume"), Some(0.5));
+        assert_eq!(
+            chain.modified("settings"),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1000))
+        );
+
+        chain.save("settings", &file).unwrap();
+        chain.save_async("settings", file.content());
+        assert_eq!(user_saves.load(Ordering::Relaxed), 2);
+        assert_eq!(broken_saves.load(Ordering::Relaxed), 0);
+        assert_eq!(defaults_saves.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,117 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use bevy::log::{error, warn};
+use figment::Figment;
+
+use crate::{
+    prefs::PreferencesStore, PreferencesError, PreferencesFile, PreferencesFileContent, PrefsValue,
+};
+
+/// PreferencesStore which reads preferences from a [`Figment`], so that they can be sourced from
+/// any of `figment`'s providers, such as config files, environment variables or command-line
+/// arguments. Each preferences file is read from the top-level key of the same name, so the
+/// `"settings"` file holds the dictionary at `settings` in the figment.
+///
+/// This store is read-only: saves are discarded. To keep the user's changes, place it after a
+/// writable store in a [`StoreChain`](crate::StoreChain).
+pub struct StoreFigment {
+    figment: Figment,
+    warned: AtomicBool,
+}
+
+impl StoreFigment {
+    /// Construct a new figment preferences store.
+    ///
+    /// # Arguments
+    /// * `figment` - The figment to read preferences from.
+    pub fn new(figment: Figment) -> Self {
+        Self {
+            figment,
+            warned: AtomicBool::new(false),
+        }
+    }
+
+    /// Log a warning the first time a save is discarded.
+    fn discard(&self, filename: &str) {
+        if !self.warned.swap(true, Ordering::Relaxed) {
+            warn!(
+                "Figment preferences store is read-only, not saving: {}",
+                filename
+            );
+        }
+    }
+}
+
+impl PreferencesStore for StoreFigment {
+    fn is_valid(&self) -> bool {
+        true
+    }
+
+    fn create(&self) -> PreferencesFile {
+        PreferencesFile::new()
+    }
+
+    /// Discard the file. This always succeeds.
+    fn save(&self, filename: &str, _file: &PreferencesFile) -> Result<(), PreferencesError> {
+        self.discard(filename);
+        Ok(())
+    }
+
+    /// Discard the file.
+    fn save_async(&self, filename: &str, _file: PreferencesFileContent) {
+        self.discard(filename);
+    }
+
+    fn load(&mut self, filename: &str) -> Option<PreferencesFile> {
+        self.load_async(filename)
+    }
+
+    /// Extract a preferences file from the figment. Returns `None` if the figment has no value
+    /// for `filename`, or if the value is not a dictionary.
+    fn load_async(&self, filename: &str) -> Option<PreferencesFile> {
+        let value = match self.figment.extract_inner::<PrefsValue>(filename) {
+            Ok(value) => value,
+            Err(e) if e.missing() => return None,
+            Err(e) => {
+                error!("Error reading preferences from figment: {}", e);
+                return None;
+            }
+        };
+        let mut file = PreferencesFile::new();
+        if let Err(e) = file.set_all(&value) {
+            error!("Error reading preferences from figment: {}", e);
+            return None;
+        }
+        file.clear_changed();
+        Some(file)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use figment::providers::Serialized;
+
+    use super::*;
+
+    #[test]
+    fn test_store_figment() {
+        let figment = Figment::new()
+            .merge(Serialized::default("settings.audio.volume", 0.5))
+            .merge(Serialized::default("settings.audio.volume", 0.75))
+            .merge(Serialized::default("settings.window.size", [800, 600]))
+            .merge(Serialized::default("other", 1));
+        let mut store = StoreFigment::new(figment);
+        assert!(store.is_valid());
+
+        let file = store.load("settings").unwrap();
+        assert!(!file.is_changed());
+        assert_eq!(file.path().group("audio").get::<f32>("volume"), Some(0.75));
+        assert_eq!(
+            file.path().group("window").get::<Vec<u32>>("size"),
+            Some(vec![800, 600])
+        );
+        assert!(store.load("missing").is_none());
+        assert!(store.load("other").is_none());
+        assert_eq!(store.save("settings", &file), Ok(()));
+    }
+}
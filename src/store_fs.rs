@@ -1,4 +1,13 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use bevy::{
     log::{error, info, warn},
@@ -9,16 +18,107 @@ use directories::BaseDirs;
 
 use crate::{
     prefs::PreferencesStore,
-    prefs_toml::{load_toml_file, serialize_table},
-    PreferencesFile, PreferencesFileContent,
+    prefs_toml::{
+        flatten_table, parse_toml, read_prefs_file, unflatten_table, TomlPreferencesFileContent,
+    },
+    CompressionAlgorithm, PreferencesError, PreferencesFile, PreferencesFileContent,
+    PreferencesFileCorrupt, PreferencesSaveFailed,
 };
 
+#[cfg(feature = "hot-reload")]
+use crate::hot_reload::FsWatcher;
+#[cfg(feature = "json5")]
+use crate::prefs_json5::{parse_json5, serialize_json5};
+use crate::prefs_json_file::{parse_json, serialize_json};
+
+/// The text format of files written by [`StoreFs`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FsFormat {
+    /// Files are written as TOML, with the extension `.toml`.
+    #[default]
+    Toml,
+    /// Files are written as compact JSON, with the extension `.json`. This suits
+    /// machine-generated files, such as caches, which are not meant to be edited by hand.
+    Json,
+    /// Files are written as pretty-printed JSON5, with the extension `.json5`. Comments, trailing
+    /// commas and unquoted keys are accepted on load, but comments are not preserved on save.
+    /// Requires the `json5` cargo feature.
+    #[cfg(feature = "json5")]
+    Json5,
+}
+
+impl FsFormat {
+    /// Every format which is available, in the order they are tried when looking for a file.
+    const ALL: &[FsFormat] = &[
+        FsFormat::Toml,
+        FsFormat::Json,
+        #[cfg(feature = "json5")]
+        FsFormat::Json5,
+    ];
+
+    /// The default file extension for this format, without the leading dot.
+    fn extension(self) -> &'static str {
+        match self {
+            FsFormat::Toml => "toml",
+            FsFormat::Json => "json",
+            #[cfg(feature = "json5")]
+            FsFormat::Json5 => "json5",
+        }
+    }
+}
+
 /// PreferencesStore which uses the local filesystem. Preferences will be located in the
 /// OS-specific directory for user preferences.
 pub struct StoreFs {
     base_path: Option<PathBuf>,
+    extension: Option<String>,
+    file_formats: HashMap<String, FsFormat>,
+    durable_writes: bool,
+    encoding: FsEncoding,
+    saves: Arc<AsyncSaves>,
+    corrupt: Mutex<Vec<PreferencesFileCorrupt>>,
+    #[cfg(feature = "hot-reload")]
+    watcher: Option<Arc<FsWatcher>>,
 }
 
+/// How files are encoded, other than their format. This is cloned into each asynchronous save, so that files are
+/// encoded on the `IoTaskPool` rather than the main thread.
+#[derive(Clone)]
+struct FsEncoding {
+    flat_keys: bool,
+    /// The format of files which don't have their own.
+    format: FsFormat,
+    compression: CompressionAlgorithm,
+    file_header: Option<FileHeader>,
+}
+
+/// A function which generates the comment block written at the top of each TOML file.
+type FileHeader = Arc<dyn Fn() -> String + Send + Sync>;
+
+/// Bookkeeping shared with saves which are running on the `IoTaskPool`.
+#[derive(Default)]
+struct AsyncSaves {
+    /// The sequence number of the next save.
+    next: AtomicU64,
+    /// The sequence number of the newest save written to each path, so that a save which
+    /// finishes late does not replace a newer one.
+    written: Mutex<HashMap<PathBuf, u64>>,
+    /// The number of asynchronous saves which have not finished.
+    pending: AtomicUsize,
+    /// Asynchronous saves which failed, waiting to be reported.
+    failures: Mutex<Vec<PreferencesSaveFailed>>,
+    /// Asynchronous saves which finished, with the number of bytes written, waiting to be
+    /// reported.
+    completed: Mutex<Vec<(String, usize)>>,
+}
+
+/// The prefix of each line of a generated file header, which tells it apart from comments
+/// written by hand.
+const HEADER_PREFIX: &str = "##";
+
+/// The number of backups of corrupt files which are kept for each file. Older ones are deleted.
+const MAX_CORRUPT_BACKUPS: usize = 2;
+
 impl StoreFs {
     /// Construct a new filesystem preferences store.
     ///
@@ -27,18 +127,297 @@ impl StoreFs {
     ///   preferences directory so as not to confuse it with other applications' preferences.
     ///   To ensure global uniqueness, it is recommended to use a reverse domain name, e.g.
     ///   "com.example.myapp".
-    pub(crate) fn new(app_name: &str) -> Self {
+    pub fn new(app_name: &str) -> Self {
+        Self::from_base_path(Self::base_path(app_name))
+    }
+
+    /// Construct a filesystem preferences store which keeps its files in `path`, rather than
+    /// in the OS-specific directory for user preferences. This is useful for portable installs
+    /// which keep their settings next to the executable, and for tests.
+    pub fn at_path(path: impl Into<PathBuf>) -> Self {
+        Self::from_base_path(Some(path.into()))
+    }
+
+    fn from_base_path(base_path: Option<PathBuf>) -> Self {
         Self {
-            base_path: if let Some(base_dirs) = BaseDirs::new() {
-                let prefs_path = base_dirs.preference_dir().join(app_name);
-                info!("Preferences path: {:?}", prefs_path);
-                Some(prefs_path)
-            } else {
-                warn!("Could not find user configuration directories");
-                None
+            base_path,
+            extension: None,
+            file_formats: HashMap::new(),
+            durable_writes: true,
+            encoding: FsEncoding {
+                flat_keys: false,
+                format: FsFormat::Toml,
+                compression: CompressionAlgorithm::None,
+                file_header: None,
             },
+            saves: Arc::default(),
+            corrupt: Mutex::default(),
+            #[cfg(feature = "hot-reload")]
+            watcher: None,
+        }
+    }
+
+    /// The preferences directory for an app name.
+    fn base_path(app_name: &str) -> Option<PathBuf> {
+        if let Some(base_dirs) = BaseDirs::new() {
+            let prefs_path = base_dirs.preference_dir().join(app_name);
+            info!("Preferences path: {:?}", prefs_path);
+            Some(prefs_path)
+        } else {
+            warn!("Could not find user configuration directories");
+            None
         }
     }
+
+    /// Store each file as a single flat table of dotted keys, such as
+    /// `"window.position" = [100, 200]`, rather than as nested TOML tables. This gives one line
+    /// per preference, which is friendlier to line-based diffing. Groups are re-nested on load,
+    /// so the in-memory API is unchanged. Dots within key names are escaped with a backslash.
+    pub fn with_flat_keys(mut self, flat_keys: bool) -> Self {
+        self.encoding.flat_keys = flat_keys;
+        self
+    }
+
+    /// Write files in a format other than TOML. Files which already exist in another format are
+    /// still loaded, but are saved in the new format, alongside the old file.
+    pub fn with_format(mut self, format: FsFormat) -> Self {
+        self.encoding.format = format;
+        self
+    }
+
+    /// Write one file in a different format from the rest, such as a machine-generated cache
+    /// kept as compact JSON next to settings which are edited by hand as TOML.
+    ///
+    /// # Arguments
+    /// * `filename` - The name of the preferences file, without the file extension.
+    /// * `format` - The format to write the file in.
+    pub fn with_file_format(mut self, filename: &str, format: FsFormat) -> Self {
+        self.file_formats.insert(filename.to_owned(), format);
+        self
+    }
+
+    /// Use a file extension other than the default for the format, such as `conf`. The extension
+    /// is given without the leading dot. This does not change the format files are written in,
+    /// and does not apply to files given their own format by `with_file_format`.
+    pub fn with_extension(mut self, extension: &str) -> Self {
+        self.extension = Some(extension.to_owned());
+        self
+    }
+
+    /// The file extension, without the leading dot or any compression suffix.
+    fn extension(&self) -> &str {
+        self.extension
+            .as_deref()
+            .unwrap_or_else(|| self.encoding.format.extension())
+    }
+
+    /// Compress each file before writing it. Compressed files gain an extra extension, such as
+    /// `.toml.lz4`, and are decompressed transparently on load. If a compressed file does not
+    /// exist, the uncompressed file is loaded instead, so that existing preferences
+    /// survive enabling compression.
+    pub fn with_compression(mut self, algorithm: CompressionAlgorithm) -> Self {
+        self.encoding.compression = algorithm;
+        self
+    }
+
+    /// Write a comment block at the top of each TOML file, such as the app and crate versions
+    /// and the time of the save, to help identify files attached to bug reports. The function is
+    /// called on every save, and each line it returns is written as a comment starting with
+    /// `##`. The header from the previous save is replaced rather than duplicated, so leading
+    /// comments starting with `##` should not be used for anything else.
+    pub fn with_file_header(mut self, header: impl Fn() -> String + Send + Sync + 'static) -> Self {
+        self.encoding.file_header = Some(Arc::new(header));
+        self
+    }
+
+    /// Flush each file to the disk before it replaces the old one, so that a power loss just
+    /// after saving can't leave an empty or partly written file behind. On Unix, the directory
+    /// is also flushed, so that the rename itself survives. This is on by default; turning it
+    /// off makes saves faster, at the cost of that safety.
+    #[doc(alias = "durable_writes")]
+    pub fn with_durable_writes(mut self, durable_writes: bool) -> Self {
+        self.durable_writes = durable_writes;
+        self
+    }
+
+    /// Watch the preferences directory for files which are changed by other programs, such as a
+    /// text editor. Changed files are reloaded, and reported with a
+    /// [`PreferencesFileChanged`](crate::PreferencesFileChanged) message, by
+    /// [`AutosavePrefsPlugin`](crate::AutosavePrefsPlugin). Requires the `hot-reload` cargo
+    /// feature.
+    #[cfg(feature = "hot-reload")]
+    pub fn with_hot_reload(mut self, hot_reload: bool) -> Self {
+        self.watcher = match &self.base_path {
+            Some(base_path) if hot_reload => FsWatcher::new(base_path).map(Arc::new),
+            _ => None,
+        };
+        self
+    }
+
+    /// The format a preferences file is written in.
+    fn format(&self, filename: &str) -> FsFormat {
+        self.file_formats
+            .get(filename)
+            .copied()
+            .unwrap_or(self.encoding.format)
+    }
+
+    /// The file extension for a format, without the leading dot or any compression suffix.
+    fn format_extension(&self, format: FsFormat) -> &str {
+        if format == self.encoding.format {
+            self.extension()
+        } else {
+            format.extension()
+        }
+    }
+
+    /// The name of a preferences file on disk, including extensions.
+    fn file_name(&self, filename: &str) -> String {
+        format!(
+            "{filename}.{}{}",
+            self.format_extension(self.format(filename)),
+            self.encoding.compression.suffix()
+        )
+    }
+
+    /// The path and format of the file to load for a preferences file. The file in the format
+    /// it is written in is preferred, but a file in any other format is loaded if that one does
+    /// not exist. If a compressed file does not exist, the uncompressed file is used.
+    fn file_path(&self, filename: &str) -> Option<(PathBuf, FsFormat)> {
+        let base_path = self.base_path.as_ref()?;
+        let format = self.format(filename);
+        let formats = std::iter::once(format).chain(
+            FsFormat::ALL
+                .iter()
+                .copied()
+                .filter(|other| *other != format),
+        );
+        for format in formats {
+            let extension = self.format_extension(format);
+            let suffix = self.encoding.compression.suffix();
+            for file_name in [
+                format!("{filename}.{extension}{suffix}"),
+                format!("{filename}.{extension}"),
+            ] {
+                let file_path = base_path.join(file_name);
+                if file_path.exists() {
+                    return Some((file_path, format));
+                }
+            }
+        }
+        Some((
+            base_path.join(format!("{filename}.{}", self.format_extension(format))),
+            format,
+        ))
+    }
+
+    /// The name of the preferences file stored in a file on disk, or `None` if it does not have
+    /// the extension of any format.
+    fn file_stem<'a>(&self, file_name: &'a str) -> Option<&'a str> {
+        let file_name = file_name
+            .strip_suffix(self.encoding.compression.suffix())
+            .unwrap_or(file_name);
+        FsFormat::ALL
+            .iter()
+            .map(|format| self.format_extension(*format))
+            .find_map(|extension| file_name.strip_suffix(extension)?.strip_suffix('.'))
+    }
+
+    /// The names of the preferences files in the preferences directory, in any format, without
+    /// their file extensions.
+    pub fn list(&self) -> Vec<String> {
+        let Some(entries) = self
+            .base_path
+            .as_ref()
+            .and_then(|base_path| std::fs::read_dir(base_path).ok())
+        else {
+            return Vec::new();
+        };
+        let mut names: Vec<String> = entries
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter_map(|file_name| self.file_stem(&file_name).map(str::to_owned))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Read and parse a preferences file. If it can't be decompressed or parsed and `back_up` is
+    /// true, it is moved aside to a backup and reported by `take_corrupt_files`; otherwise it is left alone.
+    fn read(&self, filename: &str, back_up: bool) -> Option<PreferencesFile> {
+        let (file_path, format) = self.file_path(filename)?;
+        let parsed = match read_prefs_file(&file_path) {
+            Ok(None) => return None,
+            Ok(Some(text)) => match format {
+                FsFormat::Toml => {
+                    parse_toml(&text).map(|(table, document)| (table, Some(document)))
+                }
+                FsFormat::Json => parse_json(&text).map(|table| (table, None)),
+                #[cfg(feature = "json5")]
+                FsFormat::Json5 => parse_json5(&text).map(|table| (table, None)),
+            },
+            Err(reason) => Err(reason),
+        };
+        let (table, document) = match parsed {
+            Ok(parsed) => parsed,
+            Err(reason) => {
+                error!("Error parsing preferences file {:?}: {}", file_path, reason);
+                if back_up {
+                    let backup = back_up_corrupt_file(&file_path);
+                    self.corrupt.lock().unwrap().push(PreferencesFileCorrupt {
+                        filename: filename.to_owned(),
+                        backup: backup.map(|backup| backup.display().to_string()),
+                        reason,
+                    });
+                }
+                return None;
+            }
+        };
+        let table = if self.encoding.flat_keys {
+            unflatten_table(table)
+        } else {
+            table
+        };
+        Some(PreferencesFile::from_table(table).with_document(document))
+    }
+
+    /// Take a sequence number for a save, which orders it against other saves of the same file.
+    fn next_save(&self) -> u64 {
+        self.saves.next.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+impl FsEncoding {
+    /// Serialize the contents of a file, flattening it if flat keys are enabled, and compressing
+    /// it if compression is enabled.
+    fn encode(
+        &self,
+        format: FsFormat,
+        contents: &PreferencesFileContent,
+    ) -> Result<Vec<u8>, PreferencesError> {
+        let flattened;
+        let contents = if self.flat_keys {
+            flattened = TomlPreferencesFileContent(flatten_table(&contents.0), contents.1.clone());
+            &flattened
+        } else {
+            contents
+        };
+        let text = match format {
+            FsFormat::Toml => {
+                let text = contents.encode()?;
+                match &self.file_header {
+                    Some(header) => replace_header(&text, &header()),
+                    None => text,
+                }
+            }
+            FsFormat::Json => serialize_json(&contents.0)?,
+            #[cfg(feature = "json5")]
+            FsFormat::Json5 => serialize_json5(&contents.0)?,
+        };
+        self.compression
+            .compress(text.into_bytes())
+            .map_err(PreferencesError::Storage)
+    }
 }
 
 impl PreferencesStore for StoreFs {
@@ -56,61 +435,90 @@ impl PreferencesStore for StoreFs {
     /// # Arguments
     /// * `filename` - the name of the file to be saved
     /// * `contents` - the contents of the file
-    fn save(&self, filename: &str, contents: &PreferencesFile) {
-        if let Some(base_path) = &self.base_path {
-            // Recursively create the preferences directory if it doesn't exist.
-            let mut dir_builder = std::fs::DirBuilder::new();
-            dir_builder.recursive(true);
-            if let Err(e) = dir_builder.create(base_path.clone()) {
-                warn!("Could not create preferences directory: {:?}", e);
-                return;
-            }
-
-            // Save preferences to temp file
-            let temp_path = base_path.join(format!("{filename}.toml.new"));
-            if let Err(e) = std::fs::write(&temp_path, serialize_table(&contents.table)) {
-                error!("Error saving preferences file: {}", e);
-            }
-
-            // Replace old prefs file with new one.
-            let file_path = base_path.join(format!("{filename}.toml"));
-            if let Err(e) = std::fs::rename(&temp_path, file_path) {
-                warn!("Could not save preferences file: {:?}", e);
-            }
+    fn save(&self, filename: &str, contents: &PreferencesFile) -> Result<(), PreferencesError> {
+        let Some(base_path) = &self.base_path else {
+            return Ok(());
+        };
+        let data = self
+            .encoding
+            .encode(self.format(filename), &contents.content())?;
+        let file_path = write_file(
+            base_path,
+            &self.file_name(filename),
+            &data,
+            self.next_save(),
+            self.durable_writes,
+            &self.saves,
+        )
+        .map_err(|e| PreferencesError::Storage(e.to_string()))?;
+        #[cfg(feature = "hot-reload")]
+        if let Some(watcher) = &self.watcher {
+            watcher.record_save(&file_path);
         }
+        #[cfg(not(feature = "hot-reload"))]
+        let _ = file_path;
+        Ok(())
     }
 
-    /// Save all changed `PreferenceFile`s to disk in another thread.
+    /// Save a `PreferenceFile` to disk on the `IoTaskPool`, without waiting for it to be
+    /// written. Files which could not be saved are reported by `take_save_failures`. If the same
+    /// file is saved again before the first save finishes, the newer contents win.
     ///
     /// # Arguments
     /// * `filename` - the name of the file to be saved
     /// * `contents` - the contents of the file
     fn save_async(&self, filename: &str, contents: PreferencesFileContent) {
-        if let Some(base_path) = &self.base_path {
-            IoTaskPool::get().scope(|scope| {
-                scope.spawn(async {
-                    // Recursively create the preferences directory if it doesn't exist.
-                    let mut dir_builder = std::fs::DirBuilder::new();
-                    dir_builder.recursive(true);
-                    if let Err(e) = dir_builder.create(base_path.clone()) {
-                        warn!("Could not create preferences directory: {:?}", e);
-                        return;
+        let Some(base_path) = self.base_path.clone() else {
+            return;
+        };
+        let filename = filename.to_owned();
+        let file_name = self.file_name(&filename);
+        let format = self.format(&filename);
+        let seq = self.next_save();
+        let durable_writes = self.durable_writes;
+        let encoding = self.encoding.clone();
+        let saves = self.saves.clone();
+        #[cfg(feature = "hot-reload")]
+        let watcher = self.watcher.clone();
+        saves.pending.fetch_add(1, Ordering::AcqRel);
+        IoTaskPool::get()
+            .spawn(async move {
+                let result = encoding.encode(format, &contents).and_then(|data| {
+                    write_file(&base_path, &file_name, &data, seq, durable_writes, &saves)
+                        .map(|file_path| (file_path, data.len()))
+                        .map_err(|e| PreferencesError::Storage(e.to_string()))
+                });
+                match result {
+                    Ok((file_path, len)) => {
+                        #[cfg(feature = "hot-reload")]
+                        if let Some(watcher) = &watcher {
+                            watcher.record_save(&file_path);
+                        }
+                        #[cfg(not(feature = "hot-reload"))]
+                        let _ = file_path;
+                        saves.completed.lock().unwrap().push((filename, len));
                     }
-
-                    // Save preferences to temp file
-                    let temp_path = base_path.join(format!("{filename}.toml.new"));
-                    if let Err(e) = std::fs::write(&temp_path, serialize_table(&contents.0)) {
-                        error!("Error saving preferences file: {}", e);
+                    Err(e) => {
+                        error!("Error saving preferences file {}: {}", filename, e);
+                        saves.failures.lock().unwrap().push(PreferencesSaveFailed {
+                            filename,
+                            reason: e.to_string(),
+                        });
                     }
+                }
+                saves.pending.fetch_sub(1, Ordering::AcqRel);
+            })
+            .detach();
+    }
 
-                    // Replace old prefs file with new one.
-                    let file_path = base_path.join(format!("{filename}.toml"));
-                    if let Err(e) = std::fs::rename(&temp_path, file_path) {
-                        warn!("Could not save preferences file: {:?}", e);
-                    }
-                });
-            });
-        }
+    /// Files which could not be saved by `save_async` since the last call.
+    fn take_save_failures(&self) -> Vec<PreferencesSaveFailed> {
+        std::mem::take(&mut *self.saves.failures.lock().unwrap())
+    }
+
+    /// Files which `save_async` has written since the last call.
+    fn take_completed_saves(&self) -> Vec<(String, usize)> {
+        std::mem::take(&mut *self.saves.completed.lock().unwrap())
     }
 
     /// Deserialize a preferences file from disk. If the file does not exist, `None` will
@@ -119,11 +527,474 @@ impl PreferencesStore for StoreFs {
     /// # Arguments
     /// * `filename` - The name of the preferences file, without the file extension.
     fn load(&mut self, filename: &str) -> Option<PreferencesFile> {
-        let Some(base_path) = &self.base_path else {
-            return None;
+        self.load_async(filename)
+    }
+
+    /// Deserialize a preferences file from disk, without exclusive access to the store. If the
+    /// file can't be parsed, it is moved aside to a backup and `None` is returned, so that the
+    /// app starts again with an empty file.
+    ///
+    /// # Arguments
+    /// * `filename` - The name of the preferences file, without the file extension.
+    fn load_async(&self, filename: &str) -> Option<PreferencesFile> {
+        self.read(filename, true)
+    }
+
+    /// Deserialize a preferences file which is already loaded. A file which can't be parsed is
+    /// logged and left on disk.
+    ///
+    /// # Arguments
+    /// * `filename` - The name of the preferences file, without the file extension.
+    fn reload(&self, filename: &str) -> Option<PreferencesFile> {
+        self.read(filename, false)
+    }
+
+    /// Files which could not be parsed since the last call.
+    fn take_corrupt_files(&self) -> Vec<PreferencesFileCorrupt> {
+        std::mem::take(&mut *self.corrupt.lock().unwrap())
+    }
+
+    /// The modification time of a preferences file on disk.
+    fn modified(&self, filename: &str) -> Option<SystemTime> {
+        let (file_path, _) = self.file_path(filename)?;
+        std::fs::metadata(file_path).ok()?.modified().ok()
+    }
+
+    /// Move to the preferences directory for a different app name. If hot reloading is enabled,
+    /// the new directory is watched instead.
+    fn set_app_name(&mut self, app_name: &str) {
+        self.base_path = Self::base_path(app_name);
+        #[cfg(feature = "hot-reload")]
+        if self.watcher.is_some() {
+            self.watcher = self
+                .base_path
+                .as_deref()
+                .and_then(FsWatcher::new)
+                .map(Arc::new);
+        }
+    }
+
+    /// The names of files which were changed by another program, if hot reloading is enabled.
+    #[cfg(feature = "hot-reload")]
+    fn take_external_changes(&self) -> Vec<String> {
+        let Some(watcher) = &self.watcher else {
+            return Vec::new();
         };
+        watcher
+            .changed_paths()
+            .iter()
+            .filter_map(|path| self.file_stem(path.file_name()?.to_str()?))
+            .map(str::to_owned)
+            .collect()
+    }
+}
+
+/// Write a file atomically, by writing a temp file and renaming it over the old one. Each save
+/// writes its own temp file, named after its sequence number, so that saves of the same file
+/// running at the same time don't interleave. If a newer save of the file has already been
+/// written, this one is discarded. If `durable` is true, the temp file is flushed to the disk
+/// before it is renamed, and on Unix the directory is flushed afterwards. Returns the path of
+/// the file.
+fn write_file(
+    base_path: &Path,
+    file_name: &str,
+    data: &[u8],
+    seq: u64,
+    durable: bool,
+    saves: &AsyncSaves,
+) -> std::io::Result<PathBuf> {
+    // Recursively create the preferences directory if it doesn't exist.
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .create(base_path)?;
+
+    // Save preferences to temp file
+    let temp_path = base_path.join(format!("{file_name}.{seq}.new"));
+    let mut temp_file = std::fs::File::create(&temp_path)?;
+    temp_file.write_all(data)?;
+    if durable {
+        temp_file.sync_all()?;
+    }
+    drop(temp_file);
+
+    // Replace old prefs file with new one, unless a newer save got there first.
+    let file_path = base_path.join(file_name);
+    let mut written = saves.written.lock().unwrap();
+    if written.get(&file_path).is_some_and(|&newest| newest > seq) {
+        std::fs::remove_file(&temp_path)?;
+    } else {
+        std::fs::rename(&temp_path, &file_path)?;
+        written.insert(file_path.clone(), seq);
+    }
+    drop(written);
+
+    // Make sure the rename is on disk too. The new file is already in place, so a failure here
+    // is not reported as a failed save.
+    #[cfg(unix)]
+    if durable {
+        if let Err(e) = std::fs::File::open(base_path).and_then(|dir| dir.sync_all()) {
+            warn!("Could not flush preferences directory: {}", e);
+        }
+    }
+    Ok(file_path)
+}
+
+/// Move a file which could not be parsed to `{file_name}.corrupt-{timestamp}`, so that it is not
+/// overwritten when the file is next saved, and delete all but the newest backups. Returns the
+/// path of the backup, or `None` if the file could not be moved.
+fn back_up_corrupt_file(file_path: &Path) -> Option<PathBuf> {
+    let dir = file_path.parent()?;
+    let prefix = format!("{}.corrupt-", file_path.file_name()?.to_str()?);
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis());
+    let backup_path = dir.join(format!("{prefix}{timestamp}"));
+    if let Err(e) = std::fs::rename(file_path, &backup_path) {
+        error!("Could not back up corrupt preferences file: {}", e);
+        return None;
+    }
+    warn!("Corrupt preferences file was moved to {:?}", backup_path);
+
+    let mut backups: Vec<(u128, PathBuf)> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let timestamp = entry
+                .file_name()
+                .to_str()?
+                .strip_prefix(&prefix)?
+                .parse()
+                .ok()?;
+            Some((timestamp, entry.path()))
+        })
+        .collect();
+    backups.sort();
+    let excess = backups.len().saturating_sub(MAX_CORRUPT_BACKUPS);
+    for (_, path) in &backups[..excess] {
+        if let Err(e) = std::fs::remove_file(path) {
+            warn!("Could not delete old preferences backup: {}", e);
+        }
+    }
+    Some(backup_path)
+}
+
+/// Replace the generated header at the top of a TOML file, if any, with a new one.
+fn replace_header(text: &str, header: &str) -> String {
+    let mut body = text;
+    while body.starts_with(HEADER_PREFIX) {
+        body = body.split_once('\n').map_or("", |(_, rest)| rest);
+    }
+    let body = body
+        .strip_prefix("\r\n")
+        .or_else(|| body.strip_prefix('\n'))
+        .unwrap_or(body);
+    let mut result = String::new();
+    for line in header.lines() {
+        result.push_str(HEADER_PREFIX);
+        if !line.is_empty() {
+            result.push(' ');
+            result.push_str(line);
+        }
+        result.push('\n');
+    }
+    result.push('\n');
+    result.push_str(body);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use bevy::tasks::TaskPool;
+
+    use crate::Preferences;
 
-        let file_path = base_path.join(format!("{filename}.toml"));
-        load_toml_file(&file_path).map(PreferencesFile::from_table)
+    use super::*;
+
+    /// A new, empty directory for a test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("bevy_prefs_lite_{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// Wait for asynchronous saves to finish, or a few seconds to pass.
+    fn wait_for_saves(store: &StoreFs) {
+        let start = Instant::now();
+        while store.saves.pending.load(Ordering::Acquire) > 0
+            && start.elapsed() < Duration::from_secs(3)
+        {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_replace_header() {
+        let body = "# Set to false on the Steam Deck.\n[video]\nvsync = true\n";
+        let saved = replace_header(body, "My Game 1.2.0\nSaved 2024-03-01T12:30:00Z");
+        assert_eq!(
+            saved,
+            format!("## My Game 1.2.0\n## Saved 2024-03-01T12:30:00Z\n\n{body}")
+        );
+
+        // Saving again replaces the header instead of adding another.
+        let resaved = replace_header(&saved, "My Game 1.3.0");
+        assert_eq!(resaved, format!("## My Game 1.3.0\n\n{body}"));
+    }
+
+    #[test]
+    fn test_save_async() {
+        IoTaskPool::get_or_init(TaskPool::new);
+        let dir = temp_dir("save_async");
+        let store = StoreFs::at_path(&dir);
+
+        let mut file = PreferencesFile::new();
+        file.get_group_mut("audio").unwrap().set("volume", 0.5);
+        store.save_async("settings", file.content());
+        file.get_group_mut("audio").unwrap().set("volume", 0.25);
+        store.save_async("settings", file.content());
+        wait_for_saves(&store);
+
+        let loaded = store.load_async("settings").unwrap();
+        assert_eq!(
+            loaded.get_group("audio").unwrap().get::<f32>("volume"),
+            Some(0.25)
+        );
+        assert!(store.take_save_failures().is_empty());
+        let temp_files = std::fs::read_dir(&dir)
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .ends_with(".new")
+            })
+            .count();
+        assert_eq!(temp_files, 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_save_async_failure() {
+        IoTaskPool::get_or_init(TaskPool::new);
+        // The preferences directory can't be created where a file already exists.
+        let path = temp_dir("save_async_failure");
+        std::fs::write(&path, "").unwrap();
+        let store = StoreFs::at_path(&path);
+
+        store.save_async("settings", PreferencesFile::new().content());
+        wait_for_saves(&store);
+
+        let failed = store.take_save_failures();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].filename, "settings");
+        assert!(store.take_save_failures().is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_save_async_counted_when_finished() {
+        IoTaskPool::get_or_init(TaskPool::new);
+        let poll = |prefs: &Preferences| {
+            let start = Instant::now();
+            loop {
+                let failed = prefs.take_save_failures();
+                let saves = prefs.counters().saves.load(Ordering::Relaxed);
+                if !failed.is_empty() || saves > 0 || start.elapsed() > Duration::from_secs(3) {
+                    return (failed, saves);
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        };
+        let set_volume = |prefs: &mut Preferences| {
+            let file = prefs.get_mut("settings").unwrap();
+            file.get_group_mut("audio").unwrap().set("volume", 0.5);
+        };
+
+        let dir = temp_dir("save_async_counted");
+        let mut prefs = Preferences::with_store(StoreFs::at_path(&dir));
+        set_volume(&mut prefs);
+        assert!(prefs.save_async(false).is_empty());
+        assert_eq!(prefs.counters().saves.load(Ordering::Relaxed), 0);
+        let (failed, saves) = poll(&prefs);
+        assert!(failed.is_empty());
+        assert_eq!(saves, 1);
+        assert!(!prefs.is_file_changed("settings"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // A failed save is not counted, and the file is marked as changed again.
+        let path = temp_dir("save_async_uncounted");
+        std::fs::write(&path, "").unwrap();
+        let mut prefs = Preferences::with_store(StoreFs::at_path(&path));
+        set_volume(&mut prefs);
+        prefs.save_async(false);
+        let (failed, saves) = poll(&prefs);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(saves, 0);
+        assert!(prefs.is_file_changed("settings"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_file_format() {
+        let dir = temp_dir("file_format");
+        let store = StoreFs::at_path(&dir).with_file_format("cache", FsFormat::Json);
+
+        let mut settings = PreferencesFile::new();
+        settings.get_group_mut("audio").unwrap().set("volume", 0.5);
+        store.save("settings", &settings).unwrap();
+        let mut cache = PreferencesFile::new();
+        cache.get_group_mut("tiles").unwrap().set("count", 12);
+        store.save("cache", &cache).unwrap();
+
+        assert!(dir.join("settings.toml").exists());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("cache.json")).unwrap(),
+            r#"{"tiles":{"count":12}}"#
+        );
+        assert_eq!(store.list(), vec!["cache", "settings"]);
+        let loaded = store.load_async("cache").unwrap();
+        assert_eq!(
+            loaded.get_group("tiles").unwrap().get::<i32>("count"),
+            Some(12)
+        );
+
+        // Files in another format are found by their extension.
+        std::fs::write(dir.join("legacy.json"), r#"{"window":{"x":10}}"#).unwrap();
+        let loaded = store.load_async("legacy").unwrap();
+        assert_eq!(
+            loaded.get_group("window").unwrap().get::<i32>("x"),
+            Some(10)
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_file() {
+        let dir = temp_dir("corrupt_file");
+        std::fs::create_dir_all(&dir).unwrap();
+        let backups = || {
+            std::fs::read_dir(&dir)
+                .unwrap()
+                .filter(|entry| {
+                    entry
+                        .as_ref()
+                        .unwrap()
+                        .file_name()
+                        .to_string_lossy()
+                        .starts_with("settings.toml.corrupt-")
+                })
+                .count()
+        };
+
+        for _ in 0..3 {
+            std::fs::write(dir.join("settings.toml"), "[audio\nvolume = ").unwrap();
+            let mut prefs = Preferences::with_store(StoreFs::at_path(&dir));
+            // The app carries on with an empty file.
+            let file = prefs.get_mut("settings").unwrap();
+            assert!(file.get_group("audio").is_none());
+            assert!(!dir.join("settings.toml").exists());
+
+            let corrupt = prefs.take_corrupt_files();
+            assert_eq!(corrupt.len(), 1);
+            assert_eq!(corrupt[0].filename, "settings");
+            let backup = PathBuf::from(corrupt[0].backup.as_ref().unwrap());
+            assert_eq!(
+                std::fs::read_to_string(backup).unwrap(),
+                "[audio\nvolume = "
+            );
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        // Only the newest backups are kept.
+        assert_eq!(backups(), MAX_CORRUPT_BACKUPS);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_utf8_file() {
+        let dir = temp_dir("invalid_utf8_file");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("settings.toml"), b"volume = \"\xff\"\n").unwrap();
+        let mut prefs = Preferences::with_store(StoreFs::at_path(&dir));
+        assert!(prefs
+            .get_mut("settings")
+            .unwrap()
+            .get_group("audio")
+            .is_none());
+        assert!(!dir.join("settings.toml").exists());
+
+        let corrupt = prefs.take_corrupt_files();
+        assert_eq!(corrupt.len(), 1);
+        assert!(PathBuf::from(corrupt[0].backup.as_ref().unwrap()).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "lz4")]
+    fn test_truncated_compressed_file() {
+        let dir = temp_dir("truncated_compressed_file");
+        let store = StoreFs::at_path(&dir).with_compression(CompressionAlgorithm::Lz4);
+        let mut file = PreferencesFile::new();
+        file.get_group_mut("audio").unwrap().set("volume", 0.5);
+        store.save("settings", &file).unwrap();
+        let path = dir.join("settings.toml.lz4");
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+
+        let mut prefs = Preferences::with_store(store);
+        assert!(prefs
+            .get_mut("settings")
+            .unwrap()
+            .get_group("audio")
+            .is_none());
+        assert!(!path.exists());
+        let corrupt = prefs.take_corrupt_files();
+        assert_eq!(corrupt.len(), 1);
+        assert_eq!(corrupt[0].filename, "settings");
+        let backup = PathBuf::from(corrupt[0].backup.as_ref().unwrap());
+        assert_eq!(std::fs::read(backup).unwrap(), &bytes[..bytes.len() / 2]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_diff_corrupt_file() {
+        let dir = temp_dir("diff_corrupt_file");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("settings.toml"), "[audio]\nvolume = 0.5\n").unwrap();
+        let mut prefs = Preferences::with_store(StoreFs::at_path(&dir));
+        assert!(prefs.get("settings").is_some());
+
+        // Previewing the changes has no side effects, even if the file has been corrupted.
+        std::fs::write(dir.join("settings.toml"), "[audio\nvolume = ").unwrap();
+        assert!(prefs.diff("settings").is_some());
+        assert!(prefs.take_corrupt_files().is_empty());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("settings.toml")).unwrap(),
+            "[audio\nvolume = "
+        );
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_durable_writes() {
+        let dir = temp_dir("durable_writes");
+        let mut file = PreferencesFile::new();
+        file.get_group_mut("audio").unwrap().set("volume", 0.5);
+        for durable_writes in [true, false] {
+            let store = StoreFs::at_path(&dir).with_durable_writes(durable_writes);
+            store.save("settings", &file).unwrap();
+            let loaded = store.load_async("settings").unwrap();
+            assert_eq!(
+                loaded.get_group("audio").unwrap().get::<f32>("volume"),
+                Some(0.5)
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,151 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::SystemTime,
+};
+
+use bevy::log::warn;
+
+use crate::{
+    prefs::PreferencesStore, PreferencesError, PreferencesFile, PreferencesFileContent,
+    PreferencesFileCorrupt, PreferencesSaveFailed,
+};
+
+/// PreferencesStore which wraps another store and refuses to write to it. Files are loaded from
+/// the inner store as usual, but saves are discarded, so changes made at runtime are never
+/// persisted. This is useful for demo or kiosk builds which ship with fixed preferences.
+pub struct StoreReadOnly<S: PreferencesStore> {
+    inner: S,
+    warned: AtomicBool,
+}
+
+impl<S: PreferencesStore> StoreReadOnly<S> {
+    /// Construct a new read-only store.
+    ///
+    /// # Arguments
+    /// * `inner` - The store to load preferences files from.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            warned: AtomicBool::new(false),
+        }
+    }
+
+    /// Log a warning the first time a save is discarded.
+    fn discard(&self, filename: &str) {
+        if !self.warned.swap(true, Ordering::Relaxed) {
+            warn!("Preferences store is read-only, not saving: {}", filename);
+        }
+    }
+}
+
+impl<S: PreferencesStore> PreferencesStore for StoreReadOnly<S> {
+    fn is_valid(&self) -> bool {
+        self.inner.is_valid()
+    }
+
+    fn create(&self) -> PreferencesFile {
+        self.inner.create()
+    }
+
+    /// Discard the file. This always succeeds.
+    fn save(&self, filename: &str, _file: &PreferencesFile) -> Result<(), PreferencesError> {
+        self.discard(filename);
+        Ok(())
+    }
+
+    /// Discard the file.
+    fn save_async(&self, filename: &str, _file: PreferencesFileContent) {
+        self.discard(filename);
+    }
+
+    fn load(&mut self, filename: &str) -> Option<PreferencesFile> {
+        self.inner.load(filename)
+    }
+
+    fn load_async(&self, filename: &str) -> Option<PreferencesFile> {
+        self.inner.load_async(filename)
+    }
+
+    fn reload(&self, filename: &str) -> Option<PreferencesFile> {
+        self.inner.reload(filename)
+    }
+
+    fn take_external_changes(&self) -> Vec<String> {
+        self.inner.take_external_changes()
+    }
+
+    fn take_save_failures(&self) -> Vec<PreferencesSaveFailed> {
+        self.inner.take_save_failures()
+    }
+
+    fn take_completed_saves(&self) -> Vec<(String, usize)> {
+        self.inner.take_completed_saves()
+    }
+
+    fn take_corrupt_files(&self) -> Vec<PreferencesFileCorrupt> {
+        self.inner.take_corrupt_files()
+    }
+
+    fn modified(&self, filename: &str) -> Option<SystemTime> {
+        self.inner.modified(filename)
+    }
+
+    fn set_app_name(&mut self, app_name: &str) {
+        self.inner.set_app_name(app_name);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::sync::{atomic::AtomicUsize, Arc};
+
+    use super::*;
+
+    /// Store which serves a single file, and counts saves.
+    struct TestStore {
+        saves: Arc<AtomicUsize>,
+    }
+
+    impl PreferencesStore for TestStore {
+        fn is_valid(&self) -> bool {
+            true
+        }
+
+        fn create(&self) -> PreferencesFile {
+            PreferencesFile::new()
+        }
+
+        fn load(&mut self, filename: &str) -> Option<PreferencesFile> {
+            self.load_async(filename)
+        }
+
+        fn load_async(&self, _filename: &str) -> Option<PreferencesFile> {
+            Some("[audio]\nvolume = 0.5\n".parse().unwrap())
+        }
+
+        fn save(&self, _filename: &str, _file: &PreferencesFile) -> Result<(), PreferencesError> {
+            self.saves.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn save_async(&self, _filename: &str, _file: PreferencesFileContent) {
+            self.saves.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_store_read_only() {
+        let saves = Arc::new(AtomicUsize::new(0));
+        let mut store = StoreReadOnly::new(TestStore {
+            saves: saves.clone(),
+        });
+        assert!(store.is_valid());
+
+        let file = store.load("settings").unwrap();
+        assert_eq!(file.path().group("audio").get::<f32>("volume"), Some(0.5));
+
+        assert_eq!(store.save("settings", &file), Ok(()));
+        store.save_async("settings", file.content());
+        assert_eq!(saves.load(Ordering::Relaxed), 0);
+    }
+}
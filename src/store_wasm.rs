@@ -1,6 +1,20 @@
+use std::{
+    collections::HashSet,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    key::{escape_key_segment, unescape_key_segment},
+    PreferencesError, PreferencesFileCorrupt,
+};
 pub use crate::{prefs::PreferencesStore, PreferencesFile, PreferencesFileContent};
-use bevy::tasks::IoTaskPool;
-use web_sys::window;
+use bevy::{
+    ecs::resource::Resource,
+    log::{error, info, warn},
+    tasks::IoTaskPool,
+};
+use web_sys::{js_sys::Date, window, Storage};
 
 /// Resource which represents the place where preferences files are stored. This can be either
 /// a filesystem directory (when working on a desktop platform) or a virtual directory such
@@ -11,6 +25,11 @@ use web_sys::window;
 #[derive(Resource)]
 pub struct StoreWasm {
     app_name: String,
+    corrupt: Mutex<Vec<PreferencesFileCorrupt>>,
+    /// Files saved by `save_async`, with the number of bytes written, waiting to be reported.
+    completed: Mutex<Vec<(String, usize)>>,
+    /// Files which were loaded from their legacy key, which is removed when they are next saved.
+    migrated: Mutex<HashSet<String>>,
 }
 
 impl StoreWasm {
@@ -26,14 +45,133 @@ impl StoreWasm {
     pub fn new(app_name: &str) -> Self {
         Self {
             app_name: app_name.to_owned(),
+            corrupt: Mutex::default(),
+            completed: Mutex::default(),
+            migrated: Mutex::default(),
         }
     }
 
-    /// Returns the storage key for a given filename. This consists of the app name combined
-    /// with the filename.
+    /// Returns the storage key for a given filename, such as `com.example.myapp::settings`. The
+    /// app name and filename are escaped so that neither can contain the `::` separator, which
+    /// means that keys for different apps or files can never collide.
     fn storage_key(&self, filename: &str) -> String {
+        format!(
+            "{}::{}",
+            escape_key_segment(&self.app_name),
+            escape_key_segment(filename)
+        )
+    }
+
+    /// Returns the storage key which holds the time a file was last saved, in milliseconds
+    /// since the Unix epoch.
+    fn modified_key(&self, filename: &str) -> String {
+        format!("{}::modified", self.storage_key(filename))
+    }
+
+    /// Returns the storage key which holds a copy of a file which could not be parsed.
+    fn corrupt_key(&self, filename: &str) -> String {
+        format!("{}::corrupt", self.storage_key(filename))
+    }
+
+    /// Returns the key which older versions stored a file under. These could collide, for
+    /// example between app `foo` with file `bar-baz` and app `foo-bar` with file `baz`, so they
+    /// are only read if the file has not been saved under the current key.
+    fn legacy_storage_key(&self, filename: &str) -> String {
         format!("{}-{}", self.app_name, filename)
     }
+
+    /// Write a file to local storage, and record the time it was saved. If the file was loaded
+    /// from its legacy key, that copy is removed; otherwise the legacy key is left alone, since
+    /// it may belong to another app whose key collides with this one.
+    fn write(&self, storage: &Storage, filename: &str, json_str: &str) -> Result<(), String> {
+        storage
+            .set_item(&self.storage_key(filename), json_str)
+            .map_err(|e| format!("{e:?}"))?;
+        // `SystemTime::now` is not available in the browser, so use the JavaScript clock.
+        storage
+            .set_item(
+                &self.modified_key(filename),
+                &(Date::now() as u64).to_string(),
+            )
+            .map_err(|e| format!("{e:?}"))?;
+        if self.migrated.lock().unwrap().contains(filename) {
+            let legacy_key = self.legacy_storage_key(filename);
+            for key in [format!("{legacy_key}.modified"), legacy_key] {
+                storage.remove_item(&key).map_err(|e| format!("{e:?}"))?;
+            }
+            self.migrated.lock().unwrap().remove(filename);
+        }
+        Ok(())
+    }
+
+    /// Read and parse a preferences file. If it can't be parsed and `back_up` is true, it is
+    /// copied to a backup key and reported by `take_corrupt_files`; otherwise it is left alone.
+    fn read(&self, filename: &str, back_up: bool) -> Option<PreferencesFile> {
+        if let Ok(Some(storage)) = window().unwrap().local_storage() {
+            let storage_key = self.storage_key(filename);
+            let json_str = match storage.get_item(&storage_key) {
+                Ok(Some(json_str)) => json_str,
+                _ => match storage.get_item(&self.legacy_storage_key(filename)) {
+                    Ok(Some(json_str)) => {
+                        self.migrated.lock().unwrap().insert(filename.to_owned());
+                        json_str
+                    }
+                    _ => return None,
+                },
+            };
+
+            match json_str.parse::<PreferencesFile>() {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    error!("Error parsing preferences file {}: {}", filename, e);
+                    if !back_up {
+                        return None;
+                    }
+                    let corrupt_key = self.corrupt_key(filename);
+                    let backup = match storage.set_item(&corrupt_key, &json_str) {
+                        Ok(()) => {
+                            warn!("Corrupt preferences file was copied to {}", corrupt_key);
+                            Some(corrupt_key)
+                        }
+                        Err(e) => {
+                            error!("Could not back up corrupt preferences file: {:?}", e);
+                            None
+                        }
+                    };
+                    self.corrupt.lock().unwrap().push(PreferencesFileCorrupt {
+                        filename: filename.to_owned(),
+                        backup,
+                        reason: e.to_string(),
+                    });
+                    None
+                }
+            }
+        } else {
+            None
+        }
+    }
+
+    /// The names of the preferences files which this app has saved in local storage. Files
+    /// saved by older versions, under the legacy key format, are not listed.
+    pub fn list(&self) -> Vec<String> {
+        let Some(storage) = window().and_then(|window| window.local_storage().ok().flatten())
+        else {
+            return Vec::new();
+        };
+        let prefix = format!("{}::", escape_key_segment(&self.app_name));
+        let len = storage.length().unwrap_or(0);
+        let mut filenames: Vec<String> = (0..len)
+            .filter_map(|index| storage.key(index).ok().flatten())
+            .filter_map(|key| {
+                let filename = key.strip_prefix(&prefix)?;
+                // Skip the timestamp and backup keys, whose filename is followed by another
+                // separator.
+                (!filename.contains("::")).then(|| unescape_key_segment(filename))
+            })
+            .collect();
+        filenames.sort();
+        filenames
+    }
 }
 
 impl PreferencesStore for StoreWasm {
@@ -52,14 +190,14 @@ impl PreferencesStore for StoreWasm {
     /// # Arguments
     /// * `filename` - the name of the file to be saved
     /// * `contents` - the contents of the file
-    fn save(&self, filename: &str, contents: &PreferencesFile) {
+    fn save(&self, filename: &str, contents: &PreferencesFile) -> Result<(), PreferencesError> {
         if let Ok(Some(storage)) = window().unwrap().local_storage() {
             info!("Saving preferences file: {}", filename);
-            let json_str = contents.encode();
-            storage
-                .set_item(&self.storage_key(filename).as_str(), &json_str)
-                .unwrap();
+            let json_str = contents.encode()?;
+            self.write(&storage, filename, &json_str)
+                .map_err(PreferencesError::Storage)?;
         }
+        Ok(())
     }
 
     /// Save all changed `PreferenceFile`s to disk, in another thread
@@ -72,10 +210,21 @@ impl PreferencesStore for StoreWasm {
             scope.spawn(async {
                 if let Ok(Some(storage)) = window().unwrap().local_storage() {
                     info!("Saving preferences file (async): {}", filename);
-                    let json_str = contents.encode();
-                    storage
-                        .set_item(&self.storage_key(filename).as_str(), &json_str)
-                        .unwrap();
+                    let json_str = match contents.encode() {
+                        Ok(json_str) => json_str,
+                        Err(e) => {
+                            error!("Could not save preferences file {}: {}", filename, e);
+                            return;
+                        }
+                    };
+                    match self.write(&storage, filename, &json_str) {
+                        Ok(()) => self
+                            .completed
+                            .lock()
+                            .unwrap()
+                            .push((filename.to_owned(), json_str.len())),
+                        Err(e) => error!("Could not save preferences file {}: {}", filename, e),
+                    }
                 }
             });
         });
@@ -87,15 +236,54 @@ impl PreferencesStore for StoreWasm {
     /// # Arguments
     /// * `filename` - The name of the preferences file, without the file extension.
     fn load(&mut self, filename: &str) -> Option<PreferencesFile> {
-        if let Ok(Some(storage)) = window().unwrap().local_storage() {
-            let storage_key = self.storage_key(filename);
-            let Ok(Some(json_str)) = storage.get_item(&storage_key) else {
-                return None;
-            };
+        self.load_async(filename)
+    }
 
-            Some(PreferencesFile::from_string(&json_str, filename))
-        } else {
-            None
-        }
+    /// Deserialize a preferences file from local storage, without exclusive access to the store.
+    /// If the file can't be parsed, it is copied to a backup key and `None` is returned, so that
+    /// the app starts again with an empty file. Only the most recent backup is kept.
+    ///
+    /// # Arguments
+    /// * `filename` - The name of the preferences file, without the file extension.
+    fn load_async(&self, filename: &str) -> Option<PreferencesFile> {
+        self.read(filename, true)
+    }
+
+    /// Deserialize a preferences file which is already loaded. A file which can't be parsed is
+    /// logged and left as it is.
+    ///
+    /// # Arguments
+    /// * `filename` - The name of the preferences file, without the file extension.
+    fn reload(&self, filename: &str) -> Option<PreferencesFile> {
+        self.read(filename, false)
+    }
+
+    /// Files which `save_async` has written since the last call.
+    fn take_completed_saves(&self) -> Vec<(String, usize)> {
+        std::mem::take(&mut *self.completed.lock().unwrap())
+    }
+
+    /// Files which could not be parsed since the last call.
+    fn take_corrupt_files(&self) -> Vec<PreferencesFileCorrupt> {
+        std::mem::take(&mut *self.corrupt.lock().unwrap())
+    }
+
+    /// The time a file was last saved, from the timestamp stored alongside it. Files saved by
+    /// older versions have no timestamp.
+    fn modified(&self, filename: &str) -> Option<SystemTime> {
+        let storage = window()?.local_storage().ok()??;
+        let millis = match storage.get_item(&self.modified_key(filename)) {
+            Ok(Some(millis)) => millis,
+            _ => storage
+                .get_item(&format!("{}.modified", self.legacy_storage_key(filename)))
+                .ok()??,
+        };
+        Some(SystemTime::UNIX_EPOCH + Duration::from_millis(millis.parse().ok()?))
+    }
+
+    /// Use the storage keys for a different app name.
+    fn set_app_name(&mut self, app_name: &str) {
+        self.app_name = app_name.to_owned();
+        self.migrated.get_mut().unwrap().clear();
     }
 }
@@ -0,0 +1,230 @@
+use serde::{
+    de::{
+        self,
+        value::{Error, MapDeserializer, SeqDeserializer, StrDeserializer},
+        DeserializeSeed, EnumAccess, IntoDeserializer, Unexpected, VariantAccess, Visitor,
+    },
+    forward_to_deserialize_any,
+};
+
+use crate::datetime::TOML_DATETIME_FIELD;
+
+/// A serde `Deserializer` which reads from a borrowed TOML value, so that values can be read
+/// without cloning them first. It behaves the same way as the `Deserializer` implementation of
+/// an owned `toml::Value`.
+#[derive(Clone, Copy)]
+pub(crate) struct TomlValueDeserializer<'a>(pub(crate) &'a toml::Value);
+
+impl<'a> IntoDeserializer<'a, Error> for TomlValueDeserializer<'a> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> de::Deserializer<'de> for TomlValueDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            toml::Value::String(s) => visitor.visit_borrowed_str(s),
+            toml::Value::Integer(i) => visitor.visit_i64(*i),
+            toml::Value::Float(f) => visitor.visit_f64(*f),
+            toml::Value::Boolean(b) => visitor.visit_bool(*b),
+            toml::Value::Datetime(datetime) => {
+                // Datetimes are presented as a map with a private field, the same way as `toml`
+                // does, so that `toml::value::Datetime` and `PrefsDateTime` can read them.
+                let entry = (TOML_DATETIME_FIELD, datetime.to_string());
+                visitor.visit_map(MapDeserializer::new(std::iter::once(entry)))
+            }
+            toml::Value::Array(array) => {
+                let mut seq = SeqDeserializer::new(array.iter().map(TomlValueDeserializer));
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            toml::Value::Table(table) => {
+                let entries = table
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), TomlValueDeserializer(value)));
+                let mut map = MapDeserializer::new(entries);
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+        }
+    }
+
+    /// TOML has no null, so a value which is present is always `Some`.
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Enums are read from a string for unit variants, or from a table with a single entry,
+    /// keyed by the variant name, for other variants.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.0 {
+            toml::Value::String(variant) => {
+                visitor.visit_enum(StrDeserializer::<Error>::new(variant))
+            }
+            toml::Value::Table(table) => {
+                let mut entries = table.iter();
+                match (entries.next(), entries.next()) {
+                    (Some((variant, value)), None) => visitor.visit_enum(TomlEnumDeserializer {
+                        variant,
+                        value: TomlValueDeserializer(value),
+                    }),
+                    _ => Err(de::Error::invalid_length(table.len(), &"exactly 1 element")),
+                }
+            }
+            _ => Err(de::Error::invalid_type(self.unexpected(), &"string only")),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+impl TomlValueDeserializer<'_> {
+    /// Describe the value for an error message.
+    fn unexpected(&self) -> Unexpected<'_> {
+        match self.0 {
+            toml::Value::String(s) => Unexpected::Str(s),
+            toml::Value::Integer(i) => Unexpected::Signed(*i),
+            toml::Value::Float(f) => Unexpected::Float(*f),
+            toml::Value::Boolean(b) => Unexpected::Bool(*b),
+            toml::Value::Datetime(_) => Unexpected::Other("datetime"),
+            toml::Value::Array(_) => Unexpected::Seq,
+            toml::Value::Table(_) => Unexpected::Map,
+        }
+    }
+}
+
+/// Access to an enum variant stored as a single-entry table.
+struct TomlEnumDeserializer<'a> {
+    variant: &'a str,
+    value: TomlValueDeserializer<'a>,
+}
+
+impl<'de> EnumAccess<'de> for TomlEnumDeserializer<'de> {
+    type Error = Error;
+    type Variant = TomlValueDeserializer<'de>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant), Error> {
+        let variant = seed.deserialize(StrDeserializer::<Error>::new(self.variant))?;
+        Ok((variant, self.value))
+    }
+}
+
+impl<'de> VariantAccess<'de> for TomlValueDeserializer<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        de::Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{de::DeserializeOwned, Deserialize};
+
+    use super::*;
+    use crate::PrefsDateTime;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    enum Shape {
+        Point,
+        Circle(f32),
+        Rect { width: u32, height: u32 },
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Settings {
+        name: String,
+        scale: f64,
+        size: (u16, u16),
+        tags: Vec<String>,
+        limit: Option<i8>,
+        shapes: Vec<Shape>,
+        saved: PrefsDateTime,
+    }
+
+    /// Deserialize with both the borrowed and the owned deserializer, and check that they agree.
+    fn both<D: DeserializeOwned + PartialEq + std::fmt::Debug>(value: &toml::Value) -> Option<D> {
+        let borrowed = D::deserialize(TomlValueDeserializer(value)).ok();
+        let owned = value.clone().try_into::<D>().ok();
+        assert_eq!(borrowed, owned);
+        borrowed
+    }
+
+    #[test]
+    fn test_borrowed_deserializer() {
+        let value: toml::Value = toml::from_str(
+            r#"
+            name = "main"
+            scale = 1.5
+            size = [800, 600]
+            tags = ["a", "b"]
+            limit = 5
+            shapes = ["Point", { Circle = 2.0 }, { Rect = { width = 3, height = 4 } }]
+            saved = 2024-03-01T12:30:00Z
+            "#,
+        )
+        .unwrap();
+        let settings = both::<Settings>(&value).unwrap();
+        assert_eq!(
+            settings.shapes[2],
+            Shape::Rect {
+                width: 3,
+                height: 4
+            }
+        );
+        assert_eq!(settings.saved.to_string(), "2024-03-01T12:30:00Z");
+
+        let table = value.as_table().unwrap();
+        assert_eq!(both::<i8>(&table["limit"]), Some(5));
+        assert_eq!(both::<Option<u32>>(&table["limit"]), Some(Some(5)));
+        assert_eq!(both::<u8>(&table["scale"]), None);
+        assert_eq!(both::<(u16, u16, u16)>(&table["size"]), None);
+        assert_eq!(both::<Shape>(&table["name"]), None);
+        assert_eq!(both::<Shape>(&table["size"]), None);
+        assert_eq!(both::<PrefsDateTime>(&table["saved"]), Some(settings.saved));
+        assert_eq!(both::<PrefsDateTime>(&table["size"]), None);
+    }
+}
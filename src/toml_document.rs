@@ -0,0 +1,175 @@
+use toml_edit::{Array, ArrayOfTables, DocumentMut, InlineTable, Item, Table, Value};
+
+use crate::prefs_toml::sorted_table;
+
+/// Serialize a table by merging it into the document it was loaded from, so that comments,
+/// key order and formatting written by hand are kept. Values which are unchanged are left as
+/// they were, changed values are replaced in place, removed keys are dropped along with their
+/// comments, and new keys are appended to the end of their table in sorted order.
+pub(crate) fn serialize_document(document: &DocumentMut, table: &toml::Table) -> String {
+    let mut document = document.clone();
+    merge_table(document.as_table_mut(), &sorted_table(table));
+    document.to_string()
+}
+
+fn merge_table(target: &mut Table, table: &toml::Table) {
+    target.retain(|key, _| table.contains_key(key));
+    for (key, value) in table {
+        match target.get_mut(key) {
+            Some(item) => merge_item(item, value),
+            None => {
+                target.insert(key, to_item(value));
+            }
+        }
+    }
+}
+
+fn merge_item(item: &mut Item, value: &toml::Value) {
+    match (item, value) {
+        (Item::Table(target), toml::Value::Table(table)) => merge_table(target, table),
+        (Item::Value(target), value) => merge_value(target, value),
+        (Item::ArrayOfTables(target), toml::Value::Array(array))
+            if target.len() == array.len() && array.iter().all(toml::Value::is_table) =>
+        {
+            for (target, value) in target.iter_mut().zip(array) {
+                if let toml::Value::Table(table) = value {
+                    merge_table(target, table);
+                }
+            }
+        }
+        (item, value) => *item = to_item(value),
+    }
+}
+
+fn merge_value(target: &mut Value, value: &toml::Value) {
+    if let (Value::InlineTable(target), toml::Value::Table(table)) = (&mut *target, value) {
+        target.retain(|key, _| table.contains_key(key));
+        for (key, value) in table {
+            match target.get_mut(key) {
+                Some(target) => merge_value(target, value),
+                None => {
+                    target.insert(key, to_value(value));
+                }
+            }
+        }
+    } else if from_value(target) != *value {
+        let decor = target.decor().clone();
+        *target = to_value(value);
+        *target.decor_mut() = decor;
+    }
+}
+
+/// Convert a value to a document item. Tables become standard tables, and arrays of tables
+/// become arrays of tables, so that new groups are written the same way as by `toml`.
+fn to_item(value: &toml::Value) -> Item {
+    match value {
+        toml::Value::Table(table) => {
+            let mut target: Table = table
+                .iter()
+                .map(|(key, value)| (key, to_item(value)))
+                .collect();
+            target.set_implicit(!table.is_empty() && table.values().all(toml::Value::is_table));
+            Item::Table(target)
+        }
+        toml::Value::Array(array)
+            if !array.is_empty() && array.iter().all(toml::Value::is_table) =>
+        {
+            Item::ArrayOfTables(
+                array
+                    .iter()
+                    .filter_map(|value| match to_item(value) {
+                        Item::Table(table) => Some(table),
+                        _ => None,
+                    })
+                    .collect::<ArrayOfTables>(),
+            )
+        }
+        value => Item::Value(to_value(value)),
+    }
+}
+
+fn to_value(value: &toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::from(s),
+        toml::Value::Integer(i) => Value::from(*i),
+        toml::Value::Float(f) => Value::from(*f),
+        toml::Value::Boolean(b) => Value::from(*b),
+        toml::Value::Datetime(datetime) => Value::from(*datetime),
+        toml::Value::Array(array) => Value::Array(array.iter().map(to_value).collect::<Array>()),
+        toml::Value::Table(table) => Value::InlineTable(
+            table
+                .iter()
+                .map(|(key, value)| (key, to_value(value)))
+                .collect::<InlineTable>(),
+        ),
+    }
+}
+
+fn from_value(value: &Value) -> toml::Value {
+    match value {
+        Value::String(s) => toml::Value::String(s.value().clone()),
+        Value::Integer(i) => toml::Value::Integer(*i.value()),
+        Value::Float(f) => toml::Value::Float(*f.value()),
+        Value::Boolean(b) => toml::Value::Boolean(*b.value()),
+        Value::Datetime(datetime) => toml::Value::Datetime(*datetime.value()),
+        Value::Array(array) => toml::Value::Array(array.iter().map(from_value).collect()),
+        Value::InlineTable(table) => toml::Value::Table(
+            table
+                .iter()
+                .map(|(key, value)| (key.to_owned(), from_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_document() {
+        let text = r#"# Settings for the game.
+
+[window]
+# Set to false on the Steam Deck.
+fullscreen = true
+size = [ 800, 600 ]   # width, height
+title = 'Game'
+flags = 0x10
+
+[audio]
+volume = 0.5
+"#;
+        let document: DocumentMut = text.parse().unwrap();
+        let mut table: toml::Table = toml::from_str(text).unwrap();
+        assert_eq!(serialize_document(&document, &table), text);
+
+        let window = table["window"].as_table_mut().unwrap();
+        window.insert(
+            "size".into(),
+            toml::Value::Array(vec![1024.into(), 768.into()]),
+        );
+        window.remove("title");
+        window.insert("vsync".into(), true.into());
+        table.remove("audio");
+        let mut input = toml::Table::new();
+        input.insert("invert".into(), false.into());
+        table.insert("input".into(), toml::Value::Table(input));
+
+        assert_eq!(
+            serialize_document(&document, &table),
+            r#"# Settings for the game.
+
+[window]
+# Set to false on the Steam Deck.
+fullscreen = true
+size = [1024, 768]   # width, height
+flags = 0x10
+vsync = true
+
+[input]
+invert = false
+"#
+        );
+    }
+}
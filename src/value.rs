@@ -0,0 +1,721 @@
+use std::{
+    collections::BTreeMap,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use serde::{
+    de::{self, MapAccess, SeqAccess, Visitor},
+    ser::{SerializeMap, SerializeSeq},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::Value as JsonValue;
+
+use crate::PreferencesError;
+
+/// Returns true if `value` serializes as `None`, unit or `null`.
+pub(crate) fn is_null<S: Serialize + ?Sized>(value: &S) -> bool {
+    serde_json::to_value(value).is_ok_and(|v| v.is_null())
+}
+
+/// The error for a value containing a float which is NaN or infinite. Neither format can store
+/// these portably: JSON writes them as `null`, and many tools reject TOML's `nan` and `inf`.
+pub(crate) fn non_finite_error() -> PreferencesError {
+    PreferencesError::Serialize("value contains a NaN or infinite float".to_owned())
+}
+
+/// Returns true if `value` contains a float which is NaN or infinite.
+pub(crate) fn has_non_finite<S: Serialize + ?Sized>(value: &S) -> bool {
+    toml::Value::try_from(value).is_ok_and(|value| has_non_finite_toml(&value))
+}
+
+/// Returns true if a TOML value contains a float which is NaN or infinite.
+pub(crate) fn has_non_finite_toml(value: &toml::Value) -> bool {
+    match value {
+        toml::Value::Float(f) => !f.is_finite(),
+        toml::Value::Array(array) => array.iter().any(has_non_finite_toml),
+        toml::Value::Table(table) => table.values().any(has_non_finite_toml),
+        _ => false,
+    }
+}
+
+/// Returns true if a JSON value is or contains `null`.
+pub(crate) fn contains_null(value: &JsonValue) -> bool {
+    match value {
+        JsonValue::Null => true,
+        JsonValue::Array(array) => array.iter().any(contains_null),
+        JsonValue::Object(map) => map.values().any(contains_null),
+        _ => false,
+    }
+}
+
+/// Replace integers which are too large for an `i64`, such as `u64` hashes or IDs, with their
+/// decimal string form. TOML integers are signed 64-bit, so both backends store these values as
+/// strings, which keeps the two formats interchangeable. Returns true if any integers were
+/// replaced.
+pub(crate) fn encode_large_ints(value: &mut JsonValue) -> bool {
+    match value {
+        JsonValue::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (None, Some(u)) => {
+                *value = JsonValue::String(u.to_string());
+                true
+            }
+            _ => false,
+        },
+        JsonValue::Array(array) => array
+            .iter_mut()
+            .map(encode_large_ints)
+            .fold(false, |encoded, item| encoded | item),
+        JsonValue::Object(map) => map
+            .values_mut()
+            .map(encode_large_ints)
+            .fold(false, |encoded, item| encoded | item),
+        _ => false,
+    }
+}
+
+/// Reverse [`encode_large_ints`], replacing strings which hold an integer too large for an `i64`
+/// with a number. Returns true if any strings were replaced.
+pub(crate) fn decode_large_ints(value: &mut JsonValue) -> bool {
+    match value {
+        JsonValue::String(s) => match s.parse::<u64>() {
+            Ok(u) if i64::try_from(u).is_err() => {
+                *value = JsonValue::from(u);
+                true
+            }
+            _ => false,
+        },
+        JsonValue::Array(array) => array
+            .iter_mut()
+            .map(decode_large_ints)
+            .fold(false, |decoded, item| decoded | item),
+        JsonValue::Object(map) => map
+            .values_mut()
+            .map(decode_large_ints)
+            .fold(false, |decoded, item| decoded | item),
+        _ => false,
+    }
+}
+
+/// The kind of a stored preferences value, independent of the file format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValueKind {
+    String,
+    Integer,
+    Float,
+    Bool,
+    Array,
+    Table,
+    Datetime,
+    Null,
+}
+
+impl From<&toml::Value> for ValueKind {
+    fn from(value: &toml::Value) -> Self {
+        match value {
+            toml::Value::String(_) => ValueKind::String,
+            toml::Value::Integer(_) => ValueKind::Integer,
+            toml::Value::Float(_) => ValueKind::Float,
+            toml::Value::Boolean(_) => ValueKind::Bool,
+            toml::Value::Datetime(_) => ValueKind::Datetime,
+            toml::Value::Array(_) => ValueKind::Array,
+            toml::Value::Table(_) => ValueKind::Table,
+        }
+    }
+}
+
+impl From<&JsonValue> for ValueKind {
+    fn from(value: &JsonValue) -> Self {
+        match value {
+            JsonValue::Null => ValueKind::Null,
+            JsonValue::Bool(_) => ValueKind::Bool,
+            JsonValue::Number(n) if n.is_f64() => ValueKind::Float,
+            JsonValue::Number(_) => ValueKind::Integer,
+            JsonValue::String(_) => ValueKind::String,
+            JsonValue::Array(_) => ValueKind::Array,
+            JsonValue::Object(_) => ValueKind::Table,
+        }
+    }
+}
+
+/// A borrowed preferences value, which can be inspected without knowing the file format or
+/// deserializing it as a particular type. This is useful for walking a group to build a settings
+/// UI; see `entries` on the preferences group types.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PrefsValueRef<'a> {
+    /// A value from a TOML preferences file.
+    Toml(&'a toml::Value),
+    /// A value from a JSON preferences file.
+    Json(&'a JsonValue),
+}
+
+impl<'a> PrefsValueRef<'a> {
+    /// The kind of the value.
+    pub fn kind(&self) -> ValueKind {
+        match self {
+            PrefsValueRef::Toml(value) => ValueKind::from(*value),
+            PrefsValueRef::Json(value) => ValueKind::from(*value),
+        }
+    }
+
+    /// The value as a bool, or `None` if it is not a bool.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            PrefsValueRef::Toml(value) => value.as_bool(),
+            PrefsValueRef::Json(value) => value.as_bool(),
+        }
+    }
+
+    /// The value as an integer, or `None` if it is not an integer which fits in an `i64`.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            PrefsValueRef::Toml(value) => value.as_integer(),
+            PrefsValueRef::Json(value) => value.as_i64(),
+        }
+    }
+
+    /// The value as a float, or `None` if it is not a number. Integers are converted.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            PrefsValueRef::Toml(toml::Value::Integer(i)) => Some(*i as f64),
+            PrefsValueRef::Toml(value) => value.as_float(),
+            PrefsValueRef::Json(value) => value.as_f64(),
+        }
+    }
+
+    /// The value as a string, or `None` if it is not a string.
+    pub fn as_str(&self) -> Option<&'a str> {
+        match self {
+            PrefsValueRef::Toml(value) => value.as_str(),
+            PrefsValueRef::Json(value) => value.as_str(),
+        }
+    }
+
+    /// The elements of the value, or `None` if it is not an array.
+    pub fn as_array(&self) -> Option<Vec<PrefsValueRef<'a>>> {
+        match self {
+            PrefsValueRef::Toml(value) => value
+                .as_array()
+                .map(|array| array.iter().map(PrefsValueRef::Toml).collect()),
+            PrefsValueRef::Json(value) => value
+                .as_array()
+                .map(|array| array.iter().map(PrefsValueRef::Json).collect()),
+        }
+    }
+
+    /// The entries of the value, or `None` if it is not a nested group.
+    pub fn as_group(&self) -> Option<PrefsEntries<'a>> {
+        match self {
+            PrefsValueRef::Toml(value) => value.as_table().map(PrefsEntries::from),
+            PrefsValueRef::Json(value) => value.as_object().map(PrefsEntries::from),
+        }
+    }
+
+    /// Copy the value into an owned [`PrefsValue`].
+    pub fn to_owned_value(&self) -> PrefsValue {
+        match self {
+            PrefsValueRef::Toml(value) => PrefsValue::from(*value),
+            PrefsValueRef::Json(value) => PrefsValue::from(*value),
+        }
+    }
+}
+
+/// Iterator over the keys and values of a preferences group, in the order they are stored.
+pub enum PrefsEntries<'a> {
+    #[doc(hidden)]
+    Toml(toml::map::Iter<'a>),
+    #[doc(hidden)]
+    Json(serde_json::map::Iter<'a>),
+}
+
+impl<'a> From<&'a toml::Table> for PrefsEntries<'a> {
+    fn from(table: &'a toml::Table) -> Self {
+        PrefsEntries::Toml(table.iter())
+    }
+}
+
+impl<'a> From<&'a serde_json::Map<String, JsonValue>> for PrefsEntries<'a> {
+    fn from(map: &'a serde_json::Map<String, JsonValue>) -> Self {
+        PrefsEntries::Json(map.iter())
+    }
+}
+
+impl<'a> Iterator for PrefsEntries<'a> {
+    type Item = (&'a str, PrefsValueRef<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            PrefsEntries::Toml(iter) => iter
+                .next()
+                .map(|(k, v)| (k.as_str(), PrefsValueRef::Toml(v))),
+            PrefsEntries::Json(iter) => iter
+                .next()
+                .map(|(k, v)| (k.as_str(), PrefsValueRef::Json(v))),
+        }
+    }
+}
+
+/// A format-independent preferences value. This is used to move values between the TOML and
+/// JSON backends, for example when a group is sent from a desktop app to a web client.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrefsValue {
+    /// A JSON `null`. TOML has no equivalent, so nulls are dropped when converting to TOML.
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    /// A TOML datetime, stored in its RFC 3339 string form.
+    Datetime(String),
+    Array(Vec<PrefsValue>),
+    Table(BTreeMap<String, PrefsValue>),
+}
+
+impl PrefsValue {
+    /// Returns a hash of the value which depends only on its contents, not on the key order of
+    /// the backend it came from. Two values which compare equal have the same canonical hash.
+    pub fn canonical_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Convert this value to a TOML value. Returns `None` for [`PrefsValue::Null`]; nulls nested
+    /// within arrays and tables are omitted.
+    pub fn into_toml(self) -> Option<toml::Value> {
+        Some(match self {
+            PrefsValue::Null => return None,
+            PrefsValue::Bool(b) => toml::Value::Boolean(b),
+            PrefsValue::Integer(i) => toml::Value::Integer(i),
+            PrefsValue::Float(f) => toml::Value::Float(f),
+            PrefsValue::String(s) => toml::Value::String(s),
+            PrefsValue::Datetime(s) => match s.parse() {
+                Ok(dt) => toml::Value::Datetime(dt),
+                Err(_) => toml::Value::String(s),
+            },
+            PrefsValue::Array(array) => toml::Value::Array(
+                array
+                    .into_iter()
+                    .filter_map(PrefsValue::into_toml)
+                    .collect(),
+            ),
+            PrefsValue::Table(table) => toml::Value::Table(
+                table
+                    .into_iter()
+                    .filter_map(|(k, v)| v.into_toml().map(|v| (k, v)))
+                    .collect(),
+            ),
+        })
+    }
+}
+
+impl Hash for PrefsValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            PrefsValue::Null => (),
+            PrefsValue::Bool(b) => b.hash(state),
+            PrefsValue::Integer(i) => i.hash(state),
+            // `-0.0 == 0.0`, so both must hash the same.
+            PrefsValue::Float(f) => (if *f == 0.0 { 0.0 } else { *f }).to_bits().hash(state),
+            PrefsValue::String(s) | PrefsValue::Datetime(s) => s.hash(state),
+            PrefsValue::Array(array) => array.hash(state),
+            PrefsValue::Table(table) => table.hash(state),
+        }
+    }
+}
+
+impl From<&toml::Value> for PrefsValue {
+    fn from(value: &toml::Value) -> Self {
+        match value {
+            toml::Value::String(s) => PrefsValue::String(s.clone()),
+            toml::Value::Integer(i) => PrefsValue::Integer(*i),
+            toml::Value::Float(f) => PrefsValue::Float(*f),
+            toml::Value::Boolean(b) => PrefsValue::Bool(*b),
+            toml::Value::Datetime(dt) => PrefsValue::Datetime(dt.to_string()),
+            toml::Value::Array(array) => PrefsValue::Array(array.iter().map(Into::into).collect()),
+            toml::Value::Table(table) => PrefsValue::from(table),
+        }
+    }
+}
+
+impl From<&toml::Table> for PrefsValue {
+    fn from(table: &toml::Table) -> Self {
+        PrefsValue::Table(
+            table
+                .iter()
+                .map(|(k, v)| (k.clone(), PrefsValue::from(v)))
+                .collect(),
+        )
+    }
+}
+
+impl From<&JsonValue> for PrefsValue {
+    fn from(value: &JsonValue) -> Self {
+        match value {
+            JsonValue::Null => PrefsValue::Null,
+            JsonValue::Bool(b) => PrefsValue::Bool(*b),
+            JsonValue::Number(n) => match (n.as_i64(), n.as_u64()) {
+                (Some(i), _) => PrefsValue::Integer(i),
+                (None, Some(u)) => PrefsValue::String(u.to_string()),
+                (None, None) => PrefsValue::Float(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            JsonValue::String(s) => PrefsValue::String(s.clone()),
+            JsonValue::Array(array) => PrefsValue::Array(array.iter().map(Into::into).collect()),
+            JsonValue::Object(map) => PrefsValue::from(map),
+        }
+    }
+}
+
+impl From<&serde_json::Map<String, JsonValue>> for PrefsValue {
+    fn from(map: &serde_json::Map<String, JsonValue>) -> Self {
+        PrefsValue::Table(
+            map.iter()
+                .map(|(k, v)| (k.clone(), PrefsValue::from(v)))
+                .collect(),
+        )
+    }
+}
+
+impl From<PrefsValue> for JsonValue {
+    fn from(value: PrefsValue) -> Self {
+        match value {
+            PrefsValue::Null => JsonValue::Null,
+            PrefsValue::Bool(b) => JsonValue::Bool(b),
+            PrefsValue::Integer(i) => JsonValue::from(i),
+            PrefsValue::Float(f) => JsonValue::from(f),
+            PrefsValue::String(s) | PrefsValue::Datetime(s) => JsonValue::String(s),
+            PrefsValue::Array(array) => {
+                JsonValue::Array(array.into_iter().map(Into::into).collect())
+            }
+            PrefsValue::Table(table) => {
+                JsonValue::Object(table.into_iter().map(|(k, v)| (k, v.into())).collect())
+            }
+        }
+    }
+}
+
+impl Serialize for PrefsValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            PrefsValue::Null => serializer.serialize_unit(),
+            PrefsValue::Bool(b) => serializer.serialize_bool(*b),
+            PrefsValue::Integer(i) => serializer.serialize_i64(*i),
+            PrefsValue::Float(f) => serializer.serialize_f64(*f),
+            PrefsValue::String(s) | PrefsValue::Datetime(s) => serializer.serialize_str(s),
+            PrefsValue::Array(array) => {
+                let mut seq = serializer.serialize_seq(Some(array.len()))?;
+                for item in array {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            PrefsValue::Table(table) => {
+                let mut map = serializer.serialize_map(Some(table.len()))?;
+                for (k, v) in table {
+                    map.serialize_entry(k, v)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PrefsValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(PrefsValueVisitor)
+    }
+}
+
+struct PrefsValueVisitor;
+
+impl<'de> Visitor<'de> for PrefsValueVisitor {
+    type Value = PrefsValue;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a preferences value")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<PrefsValue, E> {
+        Ok(PrefsValue::Null)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<PrefsValue, E> {
+        Ok(PrefsValue::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<PrefsValue, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<PrefsValue, E> {
+        Ok(PrefsValue::Bool(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<PrefsValue, E> {
+        Ok(PrefsValue::Integer(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<PrefsValue, E> {
+        Ok(match i64::try_from(v) {
+            Ok(i) => PrefsValue::Integer(i),
+            Err(_) => PrefsValue::Float(v as f64),
+        })
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<PrefsValue, E> {
+        Ok(PrefsValue::Float(v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<PrefsValue, E> {
+        Ok(PrefsValue::String(v.to_owned()))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<PrefsValue, E> {
+        Ok(PrefsValue::String(v))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<PrefsValue, A::Error> {
+        let mut array = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            array.push(item);
+        }
+        Ok(PrefsValue::Array(array))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<PrefsValue, A::Error> {
+        let mut table = BTreeMap::new();
+        while let Some((k, v)) = map.next_entry()? {
+            table.insert(k, v);
+        }
+        Ok(PrefsValue::Table(table))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{prefs_json::JsonPreferencesFile, prefs_toml::TomlPreferencesFile};
+
+    use super::*;
+
+    fn tuning_table() -> toml::Table {
+        toml::from_str(
+            r#"
+            [tuning]
+            damage = 1.5
+            lives = 3
+            name = "hard"
+            waves = [1, 2, 3]
+
+            [tuning.boss]
+            health = 500
+            phases = [{ speed = 1.0 }, { speed = 2.0 }]
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_group_transport_toml_to_json() {
+        let server = TomlPreferencesFile::from_table(tuning_table());
+        let server_group = server.get_group("tuning").unwrap();
+        let bytes = serde_json::to_vec(&server_group).unwrap();
+
+        let mut client = JsonPreferencesFile::new();
+        let snapshot = serde_json::from_slice::<PrefsValue>(&bytes).unwrap();
+        client
+            .get_group_mut("tuning")
+            .unwrap()
+            .replace_from_value(snapshot.clone());
+        assert!(client.is_changed());
+
+        let client_group = client.get_group("tuning").unwrap();
+        assert_eq!(
+            client_group.to_owned_value().canonical_hash(),
+            server_group.to_owned_value().canonical_hash()
+        );
+        assert_eq!(client_group.get::<i32>("lives"), Some(3));
+
+        // Installing the same snapshot again is not a change.
+        client.clear_changed();
+        client
+            .get_group_mut("tuning")
+            .unwrap()
+            .replace_from_value(snapshot);
+        assert!(!client.is_changed());
+    }
+
+    #[test]
+    fn test_group_transport_json_to_toml() {
+        let mut server = JsonPreferencesFile::new();
+        server
+            .get_group_mut("tuning")
+            .unwrap()
+            .replace_from_value(PrefsValue::from(&tuning_table()["tuning"]));
+        let server_group = server.get_group("tuning").unwrap();
+        let bytes = serde_json::to_vec(&server_group).unwrap();
+
+        let mut client = TomlPreferencesFile::new();
+        client
+            .get_group_mut("tuning")
+            .unwrap()
+            .replace_from_value(serde_json::from_slice(&bytes).unwrap());
+        let client_group = client.get_group("tuning").unwrap();
+        assert_eq!(
+            client_group.to_owned_value().canonical_hash(),
+            server_group.to_owned_value().canonical_hash()
+        );
+        assert_eq!(
+            client_group.get_group("boss").unwrap().get::<i32>("health"),
+            Some(500)
+        );
+    }
+
+    #[test]
+    fn test_null_is_dropped_for_toml() {
+        let value = PrefsValue::Table(BTreeMap::from([
+            ("a".to_string(), PrefsValue::Null),
+            ("b".to_string(), PrefsValue::Integer(1)),
+        ]));
+        let toml::Value::Table(table) = value.into_toml().unwrap() else {
+            panic!("expected a table");
+        };
+        assert_eq!(table.len(), 1);
+        assert_eq!(PrefsValue::Null.into_toml(), None);
+    }
+
+    /// Describe each entry of a group, recursively, as a settings UI might.
+    fn describe(entries: PrefsEntries<'_>) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (key, value) in entries {
+            let text = match value.kind() {
+                ValueKind::Bool => value.as_bool().unwrap().to_string(),
+                ValueKind::Integer => value.as_i64().unwrap().to_string(),
+                ValueKind::Float => value.as_f64().unwrap().to_string(),
+                ValueKind::String => value.as_str().unwrap().to_string(),
+                ValueKind::Array => format!("{} items", value.as_array().unwrap().len()),
+                ValueKind::Table => {
+                    let nested = describe(value.as_group().unwrap());
+                    lines.extend(nested.into_iter().map(|line| format!("{key}.{line}")));
+                    continue;
+                }
+                kind => format!("{kind:?}"),
+            };
+            lines.push(format!("{key} = {text}"));
+        }
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn test_entries_match_across_backends() {
+        let toml_file = TomlPreferencesFile::from_table(tuning_table());
+        let json_file: JsonPreferencesFile =
+            serde_json::to_string(&toml_file.get_group("tuning").unwrap())
+                .map(|tuning| format!("{{\"tuning\": {tuning}}}"))
+                .unwrap()
+                .parse()
+                .unwrap();
+
+        let toml_lines = describe(toml_file.get_group("tuning").unwrap().entries());
+        let json_lines = describe(json_file.get_group("tuning").unwrap().entries());
+        assert_eq!(
+            toml_lines,
+            [
+                "boss.health = 500",
+                "boss.phases = 2 items",
+                "damage = 1.5",
+                "lives = 3",
+                "name = hard",
+                "waves = 3 items",
+            ]
+        );
+        assert_eq!(toml_lines, json_lines);
+
+        let lives = toml_file
+            .get_group("tuning")
+            .unwrap()
+            .entries()
+            .find(|(key, _)| *key == "lives")
+            .unwrap()
+            .1;
+        assert_eq!(lives.as_f64(), Some(3.0));
+        assert_eq!(lives.as_str(), None);
+        assert_eq!(lives.to_owned_value(), PrefsValue::Integer(3));
+    }
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct SaveSlot {
+        name: String,
+        last_played: crate::PrefsDateTime,
+    }
+
+    #[test]
+    fn test_datetime_across_backends() {
+        use crate::PrefsDateTime;
+
+        let played = PrefsDateTime::from_unix_timestamp(1709296200).unwrap();
+        let slot = SaveSlot {
+            name: "autosave".to_string(),
+            last_played: "2024-03-01T14:30:00+02:00".parse().unwrap(),
+        };
+
+        let mut server = TomlPreferencesFile::new();
+        let mut session = server.get_group_mut("session").unwrap();
+        session.set_datetime("last_played", played);
+        session.set("slot", &slot);
+        assert_eq!(session.get_datetime("last_played"), Some(played));
+        assert_eq!(session.get::<SaveSlot>("slot").as_ref(), Some(&slot));
+        let session = server.get_group("session").unwrap();
+        let kinds: BTreeMap<&str, ValueKind> = session
+            .entries()
+            .map(|(key, value)| (key, value.kind()))
+            .collect();
+        assert_eq!(kinds["last_played"], ValueKind::Datetime);
+
+        // Convert the group to JSON, as if sending it to a web client.
+        let bytes = serde_json::to_vec(&session).unwrap();
+        let mut client = JsonPreferencesFile::new();
+        client
+            .get_group_mut("session")
+            .unwrap()
+            .replace_from_value(serde_json::from_slice::<PrefsValue>(&bytes).unwrap());
+        let session = client.get_group("session").unwrap();
+        assert_eq!(
+            session
+                .get_datetime("last_played")
+                .unwrap()
+                .unix_timestamp(),
+            played.unix_timestamp()
+        );
+        assert_eq!(
+            session
+                .get::<SaveSlot>("slot")
+                .unwrap()
+                .last_played
+                .unix_timestamp(),
+            Some(1709296200)
+        );
+
+        // The JSON backend stores datetimes as strings.
+        let mut session = client.get_group_mut("session").unwrap();
+        session.set_datetime("started", played);
+        session.set("slot", &slot);
+        assert_eq!(
+            session.get::<String>("started"),
+            Some("2024-03-01T12:30:00Z".to_string())
+        );
+        assert_eq!(session.get::<SaveSlot>("slot"), Some(slot));
+    }
+
+    #[test]
+    fn test_negative_zero_hash() {
+        let zero = PrefsValue::Float(0.0);
+        let negative_zero = PrefsValue::Float(-0.0);
+        assert_eq!(zero, negative_zero);
+        assert_eq!(zero.canonical_hash(), negative_zero.canonical_hash());
+        assert_ne!(
+            zero.canonical_hash(),
+            PrefsValue::Float(f64::MIN_POSITIVE).canonical_hash()
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use bevy::ecs::{
+    message::{Message, MessageWriter},
+    system::{IntoSystem, Local, Res, System},
+};
+use serde::de::DeserializeOwned;
+
+use crate::Preferences;
+
+/// Message which is sent by the system returned from [`watch_pref_key`] when the watched
+/// preference changes.
+#[derive(Message, Debug, Clone, PartialEq)]
+pub struct PrefKeyChanged<T: Send + Sync + 'static> {
+    /// The name of the preferences file, without the file extension.
+    pub filename: String,
+    /// The name of the group in the file.
+    pub group: String,
+    /// The name of the preference in the group.
+    pub key: String,
+    /// The previous value.
+    pub old: T,
+    /// The new value.
+    pub new: T,
+}
+
+/// Returns a system which checks a single preference every frame, and sends a
+/// [`PrefKeyChanged`] message when its value differs from the last one seen. This suits systems
+/// which only read a preference, such as an audio system which applies a new volume when the
+/// user changes it on the settings screen. Each system keeps its own copy of the last value, so
+/// any number of keys of the same type can be watched; use the `key` of the message to tell
+/// them apart.
+///
+/// The first value seen is cached without sending a message, and no message is sent when the
+/// preference is added or removed, since there is no old or new value. The file is not loaded
+/// by this system, so the key is only watched once something else has loaded it, such as
+/// [`PreferencesPlugin::preload`](crate::PreferencesPlugin::preload). The message type must be
+/// registered with `app.add_message::<PrefKeyChanged<T>>()`.
+///
+/// # Arguments
+/// * `filename` - The name of the preferences file, without the file extension.
+/// * `group` - The name of the group in the file.
+/// * `key` - The name of the preference in the group.
+pub fn watch_pref_key<T>(filename: &str, group: &str, key: &str) -> impl System<In = (), Out = ()>
+where
+    T: DeserializeOwned + Clone + PartialEq + Send + Sync + 'static,
+{
+    let filename = filename.to_owned();
+    let group = group.to_owned();
+    let key = key.to_owned();
+    IntoSystem::into_system(
+        move |prefs: Option<Res<Preferences>>,
+              mut cached: Local<Option<T>>,
+              mut writer: MessageWriter<PrefKeyChanged<T>>| {
+            let Some(prefs) = prefs else {
+                return;
+            };
+            let value = prefs
+                .peek(&filename)
+                .and_then(|file| file.get_group(&group)?.get::<T>(&key));
+            if *cached == value {
+                return;
+            }
+            if let (Some(old), Some(new)) = (cached.take(), value.clone()) {
+                writer.write(PrefKeyChanged {
+                    filename: filename.clone(),
+                    group: group.clone(),
+                    key: key.clone(),
+                    old,
+                    new,
+                });
+            }
+            *cached = value;
+        },
+    )
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use bevy::{
+        app::{App, Update},
+        ecs::message::Messages,
+    };
+
+    use crate::StoreChain;
+
+    use super::*;
+
+    #[test]
+    fn test_watch_pref_key() {
+        let mut app = App::new();
+        app.insert_resource(Preferences::with_store(StoreChain::new(Vec::new())))
+            .add_message::<PrefKeyChanged<f32>>()
+            .add_systems(
+                Update,
+                (
+                    watch_pref_key::<f32>("settings", "audio", "volume"),
+                    watch_pref_key::<f32>("settings", "audio", "pitch"),
+                ),
+            );
+
+        let set = |app: &mut App, key: &str, value: f32| {
+            let mut prefs = app.world_mut().resource_mut::<Preferences>();
+            let file = prefs.get_mut("settings").unwrap();
+            file.get_group_mut("audio").unwrap().set(key, value);
+        };
+        let take_changes = |app: &mut App| {
+            let mut messages = app
+                .world_mut()
+                .resource_mut::<Messages<PrefKeyChanged<f32>>>();
+            messages.drain().collect::<Vec<_>>()
+        };
+
+        set(&mut app, "volume", 0.5);
+        set(&mut app, "pitch", 1.0);
+        app.update();
+        app.update();
+        assert!(take_changes(&mut app).is_empty());
+
+        // Each watcher keeps its own value, even though both are `f32`.
+        set(&mut app, "volume", 0.25);
+        app.update();
+        assert_eq!(
+            take_changes(&mut app),
+            vec![PrefKeyChanged {
+                filename: "settings".to_owned(),
+                group: "audio".to_owned(),
+                key: "volume".to_owned(),
+                old: 0.5,
+                new: 0.25
+            }]
+        );
+
+        app.update();
+        assert!(take_changes(&mut app).is_empty());
+    }
+}